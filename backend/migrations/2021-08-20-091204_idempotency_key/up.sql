@@ -0,0 +1,9 @@
+-- Your SQL goes here
+create table idempotency_key (
+    key text not null unique,
+    request text not null,
+    status integer not null,
+    response bytea not null,
+    created_at timestamp not null default now(),
+    primary key (key)
+);
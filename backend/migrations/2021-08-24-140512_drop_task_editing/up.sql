@@ -0,0 +1,2 @@
+-- Your SQL goes here
+alter table task drop column editing;
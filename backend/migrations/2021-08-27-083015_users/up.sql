@@ -0,0 +1,8 @@
+-- Your SQL goes here
+create table users (
+    id uuid not null unique,
+    username text not null unique,
+    password_hash text not null,
+    created_at timestamp not null default now(),
+    primary key (id)
+);
@@ -0,0 +1,13 @@
+-- This file should undo anything in `up.sql`
+drop table idempotency_key;
+create table idempotency_key (
+    key text not null unique,
+    request text not null,
+    status integer not null,
+    response bytea not null,
+    created_at timestamp not null default now(),
+    primary key (key)
+);
+
+drop index task_owner;
+alter table task drop column owner;
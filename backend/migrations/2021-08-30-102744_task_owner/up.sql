@@ -0,0 +1,22 @@
+-- Your SQL goes here
+-- Tasks created before accounts existed go to a `legacy` user, who cannot
+-- log in until given a password.
+insert into users (id, username, password_hash)
+select md5(random()::text)::uuid, 'legacy', '!'
+where exists (select 1 from task)
+on conflict (username) do nothing;
+alter table task add column owner uuid references users (id) on delete cascade;
+update task set owner = (select id from users where username = 'legacy');
+alter table task alter column owner set not null;
+create index task_owner on task (owner);
+
+drop table idempotency_key;
+create table idempotency_key (
+    owner uuid not null references users (id) on delete cascade,
+    key text not null,
+    request text not null,
+    status integer not null,
+    response bytea not null,
+    created_at timestamp not null default now(),
+    primary key (owner, key)
+);
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+create table api_token (
+    id uuid not null unique,
+    owner uuid not null references users (id) on delete cascade,
+    name text not null,
+    token_hash text not null unique,
+    read_only boolean not null,
+    expires_at timestamp,
+    created_at timestamp not null default now(),
+    primary key (id)
+);
+create index api_token_owner on api_token (owner);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+drop index task_list_id;
+alter table task drop column list_id;
+drop table lists;
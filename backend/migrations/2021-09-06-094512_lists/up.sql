@@ -0,0 +1,20 @@
+-- Your SQL goes here
+create table lists (
+    id uuid not null unique,
+    owner uuid not null references users (id) on delete cascade,
+    name text not null,
+    created_at timestamp not null default now(),
+    primary key (id)
+);
+create index lists_owner on lists (owner);
+
+-- Every user who already has tasks gets a list to hold them.
+insert into lists (id, owner, name)
+select md5(random()::text || owner::text)::uuid, owner, 'Todos'
+from task
+group by owner;
+
+alter table task add column list_id uuid references lists (id) on delete cascade;
+update task set list_id = lists.id from lists where lists.owner = task.owner;
+alter table task alter column list_id set not null;
+create index task_list_id on task (list_id);
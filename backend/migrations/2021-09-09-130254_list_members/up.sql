@@ -0,0 +1,12 @@
+-- Your SQL goes here
+create table list_members (
+    list_id uuid not null references lists (id) on delete cascade,
+    user_id uuid not null references users (id) on delete cascade,
+    role text not null check (role in ('viewer', 'editor', 'owner')),
+    created_at timestamp not null default now(),
+    primary key (list_id, user_id)
+);
+create index list_members_user_id on list_members (user_id);
+
+insert into list_members (list_id, user_id, role)
+select id, owner, 'owner' from lists;
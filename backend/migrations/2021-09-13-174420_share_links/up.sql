@@ -0,0 +1,10 @@
+-- Your SQL goes here
+create table share_links (
+    id uuid not null unique,
+    list_id uuid not null references lists (id) on delete cascade,
+    created_by uuid not null references users (id) on delete cascade,
+    expires_at timestamp,
+    created_at timestamp not null default now(),
+    primary key (id)
+);
+create index share_links_list_id on share_links (list_id);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Make the first admin by hand: update users set is_admin = true where username = '...';
+alter table users add column is_admin boolean not null default false;
+alter table users add column disabled boolean not null default false;
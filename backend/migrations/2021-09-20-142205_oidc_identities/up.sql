@@ -0,0 +1,9 @@
+-- Your SQL goes here
+create table oidc_identities (
+    issuer text not null,
+    subject text not null,
+    user_id uuid not null references users (id) on delete cascade,
+    created_at timestamp not null default now(),
+    primary key (issuer, subject)
+);
+create index oidc_identities_user_id on oidc_identities (user_id);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+drop index task_due_at;
+alter table task drop column due_at;
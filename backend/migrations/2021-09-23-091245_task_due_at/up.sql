@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- An instant in UTC, clients show it in their own time zone.
+alter table task add column due_at timestamp;
+create index task_due_at on task (due_at);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- 0 for none up to 3 for high, see `Priority::level`.
+alter table task add column priority smallint not null default 0;
+create index task_priority on task (priority);
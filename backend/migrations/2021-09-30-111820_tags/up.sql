@@ -0,0 +1,16 @@
+-- Your SQL goes here
+create table tag (
+    id uuid not null unique,
+    owner uuid not null references users (id) on delete cascade,
+    name text not null,
+    created_at timestamp not null default now(),
+    primary key (id),
+    unique (owner, name)
+);
+
+create table task_tag (
+    task_id uuid not null references task (id) on delete cascade,
+    tag_id uuid not null references tag (id) on delete cascade,
+    primary key (task_id, tag_id)
+);
+create index task_tag_tag_id on task_tag (tag_id);
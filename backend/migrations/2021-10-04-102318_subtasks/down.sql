@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+drop index task_parent_id;
+alter table task drop constraint task_not_own_parent;
+alter table task drop column auto_complete;
+alter table task drop column parent_id;
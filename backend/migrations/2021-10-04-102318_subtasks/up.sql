@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Subtasks go away with their parent.
+alter table task add column parent_id uuid references task (id) on delete cascade;
+alter table task add column auto_complete boolean not null default false;
+alter table task add constraint task_not_own_parent check (parent_id <> id);
+create index task_parent_id on task (parent_id);
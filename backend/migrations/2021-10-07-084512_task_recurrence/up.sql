@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- A rule like `weekly:mon,thu`, see `Recurrence::to_rule`.
+alter table task add column recurrence text;
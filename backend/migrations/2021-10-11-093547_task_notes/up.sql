@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Markdown, see `todomvc_shared::markdown`.
+alter table task add column notes text not null default '';
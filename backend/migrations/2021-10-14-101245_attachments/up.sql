@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- Files are stored once per content, named by `sha256`.
+create table attachments (
+    id uuid not null unique,
+    task_id uuid not null references task (id) on delete cascade,
+    name text not null,
+    content_type text not null,
+    size bigint not null,
+    sha256 text not null,
+    uploaded_by uuid references users (id) on delete set null,
+    created_at timestamp not null default now(),
+    primary key (id)
+);
+create index attachments_task_id on attachments (task_id);
+create index attachments_sha256 on attachments (sha256);
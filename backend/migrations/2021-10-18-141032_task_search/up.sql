@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Left out of `schema.rs`, diesel has no type for it: queried with raw sql by
+-- `db::search_tasks`.
+alter table task add column search tsvector generated always as (
+    setweight(to_tsvector('english', content), 'A') ||
+    setweight(to_tsvector('english', notes), 'B')
+) stored;
+create index task_search on task using gin (search);
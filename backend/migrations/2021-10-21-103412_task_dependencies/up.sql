@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- `task_id` is blocked until `depends_on` is completed. Both are in the same
+-- list, and the routes keep cycles out.
+create table task_dependency (
+    task_id uuid not null references task (id) on delete cascade,
+    depends_on uuid not null references task (id) on delete cascade,
+    primary key (task_id, depends_on),
+    check (task_id <> depends_on)
+);
+create index task_dependency_depends_on on task_dependency (depends_on);
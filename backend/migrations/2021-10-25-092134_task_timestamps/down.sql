@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+alter table task drop column completed_at;
+alter table task drop column created_at;
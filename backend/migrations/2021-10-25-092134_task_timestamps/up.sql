@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Older tasks count as created now, and as completed at no known time.
+alter table task add column created_at timestamp not null default now();
+alter table task add column completed_at timestamp;
+create index task_created_at on task (created_at);
+create index task_completed_at on task (completed_at);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+delete from idempotency_key where status is null or response is null;
+alter table idempotency_key alter column response set not null;
+alter table idempotency_key alter column status set not null;
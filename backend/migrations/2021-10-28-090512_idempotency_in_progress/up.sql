@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- A key without a status is reserved by a request that is still running.
+alter table idempotency_key alter column status drop not null;
+alter table idempotency_key alter column response drop not null;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- Existing session cookies hold a user id and stop working, users log in again.
+create table sessions (
+    id uuid not null,
+    owner uuid not null references users (id) on delete cascade,
+    expires_at timestamp not null,
+    created_at timestamp not null default now(),
+    primary key (id)
+);
+create index sessions_owner on sessions (owner);
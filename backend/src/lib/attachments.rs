@@ -0,0 +1,383 @@
+//! Files attached to tasks. Their contents are stored once in
+//! `attachments.dir`, named by their SHA-256, however many attachments share
+//! them.
+use super::{
+    db::{self, AttachmentRow},
+    MyResult, PgPool,
+};
+use rocket::{
+    data::{ByteUnit, Capped},
+    error,
+    fairing::{Fairing, Info, Kind},
+    fs::{NamedFile, TempFile},
+    http::{ContentType, Header, Status},
+    info,
+    request::Request,
+    response::{self, Responder, Response},
+    tokio::{
+        fs,
+        sync::{Mutex, OwnedMutexGuard},
+        task,
+    },
+    Build, FromForm, Orbit, Rocket,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{self, Arc},
+};
+
+/// Longest file name kept, in characters.
+const MAX_NAME_LEN: usize = 255;
+
+/// The `attachments` table of `Rocket.toml`.
+///
+/// The `file` limits of Rocket cap uploads before `max_size` is checked, and
+/// `data-form` caps the whole upload form, so they have to allow at least
+/// `max_size`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AttachmentConfig {
+    /// Created if it does not exist.
+    pub dir: PathBuf,
+    pub max_size: ByteUnit,
+    /// Content types accepted, `image/*` accepts every image.
+    pub types: Vec<String>,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("attachments"),
+            max_size: ByteUnit::Mebibyte(20),
+            types: [
+                "image/png",
+                "image/jpeg",
+                "image/gif",
+                "image/webp",
+                "application/pdf",
+                "text/plain",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+        }
+    }
+}
+
+/// The multipart form of an upload.
+#[derive(FromForm)]
+pub struct Upload<'r> {
+    pub file: Capped<TempFile<'r>>,
+}
+
+/// Where attachments are stored.
+pub struct Attachments {
+    config: AttachmentConfig,
+    /// Locks of the contents being written or removed, by hash, so that a
+    /// file is not removed right as another attachment starts using it.
+    locks: sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl Attachments {
+    /// Fails unless a file of `size` bytes and type `content_type` can be
+    /// attached.
+    pub fn check(&self, size: u64, content_type: &str) -> Result<(), (Status, String)> {
+        if size > self.config.max_size.as_u64() {
+            return Err((
+                Status::PayloadTooLarge,
+                format!("Attachments can be at most {}.", self.config.max_size),
+            ));
+        }
+        let top = content_type.split('/').next();
+        let allowed = self
+            .config
+            .types
+            .iter()
+            .any(|t| match t.strip_suffix("/*") {
+                Some(prefix) => top == Some(prefix),
+                None => t == content_type,
+            });
+        if allowed {
+            Ok(())
+        } else {
+            Err((
+                Status::UnsupportedMediaType,
+                format!("Cannot attach {} files.", content_type),
+            ))
+        }
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.config.dir.join(hash)
+    }
+
+    /// Waits until no other request writes or removes the content `hash`.
+    async fn lock(&self, hash: &str) -> ContentLock<'_> {
+        let mutex = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(hash.to_string())
+            .or_default()
+            .clone();
+        ContentLock {
+            locks: &self.locks,
+            guard: Some(mutex.lock_owned().await),
+        }
+    }
+
+    /// Records `row` and stores the content of `file` for it.
+    pub async fn save(
+        &self,
+        pool: &PgPool,
+        row: &AttachmentRow,
+        file: &mut TempFile<'_>,
+    ) -> MyResult<()> {
+        let _guard = self.lock(row.sha256()).await;
+        db::create_attachment(pool, row).await?;
+        // the same content is written again, the new copy replaces the old
+        // one at once
+        if let Err(e) = self.write(file, row.sha256()).await {
+            db::remove_attachment(pool, *row.id()).await?;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    async fn write(&self, file: &mut TempFile<'_>, hash: &str) -> io::Result<()> {
+        let dest = self.path(hash);
+        if file.persist_to(&dest).await.is_ok() {
+            return Ok(());
+        }
+        // `temp_dir` is on another file system
+        let part = self.path(&format!("{}.part", hash));
+        file.move_copy_to(&part).await?;
+        fs::rename(&part, &dest).await
+    }
+
+    /// Removes `row`, and its content unless another attachment has it too.
+    pub async fn remove(&self, pool: &PgPool, row: &AttachmentRow) -> MyResult<usize> {
+        let _guard = self.lock(row.sha256()).await;
+        let removed = db::remove_attachment(pool, *row.id()).await?;
+        if removed > 0 && !db::is_content_used(pool, row.sha256().clone()).await? {
+            remove_file(&self.path(row.sha256())).await?;
+        }
+        Ok(removed)
+    }
+
+    /// Removes the contents of `hashes` no attachment has any more, once the
+    /// tasks they were attached to are deleted.
+    pub async fn forget(&self, pool: &PgPool, hashes: Vec<String>) -> MyResult<usize> {
+        let mut removed = 0;
+        for hash in hashes {
+            // an upload may have started using it since
+            let _guard = self.lock(&hash).await;
+            if !db::is_content_used(pool, hash.clone()).await? {
+                remove_file(&self.path(&hash)).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    pub async fn open(&self, row: &AttachmentRow) -> io::Result<NamedFile> {
+        NamedFile::open(self.path(row.sha256())).await
+    }
+
+    /// Removes the contents of attachments that went away with their task,
+    /// list or user without being forgotten, as when the server stopped in
+    /// between.
+    async fn sweep(&self, pool: &PgPool) -> MyResult<usize> {
+        let mut unused = Vec::new();
+        let mut dir = fs::read_dir(&self.config.dir).await?;
+        while let Some(entry) = dir.next_entry().await? {
+            if let Some(name) = entry.file_name().to_str().filter(|n| is_hash(n)) {
+                unused.push(name.to_string());
+            }
+        }
+        let used = db::get_content_hashes(pool).await?;
+        unused.retain(|hash| !used.contains(hash));
+        self.forget(pool, unused).await
+    }
+}
+
+/// Held while a content is written or removed.
+struct ContentLock<'a> {
+    locks: &'a sync::Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for ContentLock<'_> {
+    fn drop(&mut self) {
+        self.guard = None;
+        // forgets the locks nobody holds or waits for, including those of
+        // requests that went away while waiting
+        self.locks
+            .lock()
+            .unwrap()
+            .retain(|_, mutex| Arc::strong_count(mutex) > 1);
+    }
+}
+
+/// Hex SHA-256 of the file at `path`.
+pub async fn hash_file(path: &Path) -> io::Result<String> {
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || {
+        let mut hasher = Sha256::new();
+        io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .map_err(|_| io::Error::from(io::ErrorKind::Other))?
+}
+
+fn is_hash(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+async fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The name an upload is listed under, without the directories some
+/// browsers send along.
+pub fn display_name(raw: Option<&str>) -> String {
+    let name: String = raw
+        .and_then(|n| n.rsplit(['/', '\\']).next())
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LEN)
+        .collect();
+    match name.trim() {
+        "" => "attachment".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// `type/subtype` of an upload, without parameters.
+pub fn media_type(content_type: Option<&ContentType>) -> String {
+    match content_type {
+        Some(ct) => format!("{}/{}", ct.top(), ct.sub()).to_ascii_lowercase(),
+        None => "application/octet-stream".to_string(),
+    }
+}
+
+/// An attachment sent as a download under its own name.
+pub struct Download {
+    file: NamedFile,
+    name: String,
+    content_type: String,
+}
+
+impl Download {
+    pub fn new(file: NamedFile, row: &AttachmentRow) -> Self {
+        Self {
+            file,
+            name: row.name().clone(),
+            content_type: row.content_type().clone(),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Download {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let content_type =
+            ContentType::parse_flexible(&self.content_type).unwrap_or(ContentType::Binary);
+        Response::build_from(self.file.respond_to(req)?)
+            .header(content_type)
+            .header(Header::new(
+                "Content-Disposition",
+                content_disposition(&self.name),
+            ))
+            // uploads are never rendered as something other than they claim
+            .header(Header::new("X-Content-Type-Options", "nosniff"))
+            .ok()
+    }
+}
+
+/// Always a download, with an ascii fallback for the name and the name
+/// itself percent-encoded.
+fn content_disposition(name: &str) -> String {
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+/// Manages `Attachments` and clears out unused contents at launch.
+pub struct AttachmentFairing;
+
+#[rocket::async_trait]
+impl Fairing for AttachmentFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Attachments",
+            kind: Kind::Ignite | Kind::Liftoff,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let config = if rocket.figment().find_value("attachments").is_ok() {
+            match rocket
+                .figment()
+                .extract_inner::<AttachmentConfig>("attachments")
+            {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Invalid attachments: {}", e);
+                    return Err(rocket);
+                }
+            }
+        } else {
+            AttachmentConfig::default()
+        };
+        if let Err(e) = fs::create_dir_all(&config.dir).await {
+            error!(
+                "Cannot create attachments.dir {}: {}",
+                config.dir.display(),
+                e
+            );
+            return Err(rocket);
+        }
+        Ok(rocket.manage(Attachments {
+            config,
+            locks: sync::Mutex::new(HashMap::new()),
+        }))
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        if let (Some(attachments), Some(pool)) =
+            (rocket.state::<Attachments>(), rocket.state::<Arc<PgPool>>())
+        {
+            match attachments.sweep(pool).await {
+                Ok(0) => {}
+                Ok(n) => info!("Removed {} unused attachment file(s).", n),
+                Err(e) => error!("Cannot clean up attachments: {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use super::{csrf, db, Error, MyResult as Result, PgPool};
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+use getset::*;
+use rocket::{
+    http::{Cookie, CookieJar, Status},
+    outcome::Outcome,
+    request::{self, FromRequest, Request},
+    tokio::task,
+    State,
+};
+use sha2::{Digest, Sha256};
+use std::{ops::Deref, sync::Arc, time::Duration};
+use uuid::Uuid;
+
+/// Private cookie holding the id of the session, see `db::Session`.
+pub const SESSION_COOKIE: &str = "session";
+
+/// How long a session lasts after logging in.
+const SESSION_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Checked against for unknown users and unusable hashes, so that a failed
+/// login takes as long whether the user exists or not.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=4096,t=3,p=1$tuBVIs03szqTcHk/lHd57g$ezwc9RIQ5NuZ4kAE8iR+XxwScWtY6CMZwmZqpBgPsZ0";
+
+/// Prefix of personal API token secrets, makes them easy to spot in logs.
+const TOKEN_PREFIX: &str = "todo_";
+
+/// Request guard for a logged in user, fails with `401` otherwise.
+///
+/// Scripts authenticate with `Authorization: Bearer <token>` instead of the
+/// session cookie, a request carrying that header never falls back to the
+/// cookie.
+#[derive(Debug, Clone, Getters)]
+#[getset(get = "pub")]
+pub struct AuthUser {
+    id: Uuid,
+    username: String,
+    /// The API token used for this request, `None` for a browser session.
+    token: Option<Uuid>,
+    read_only: bool,
+    admin: bool,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AuthUser {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        // Cached so that guards built on top of this one don't look the user
+        // up again.
+        let user = req
+            .local_cache_async(async {
+                let pool = match req.guard::<&State<Arc<PgPool>>>().await {
+                    Outcome::Success(pool) => pool,
+                    _ => {
+                        return Err((
+                            Status::InternalServerError,
+                            "Database pool is not managed.".to_string(),
+                        ))
+                    }
+                };
+                let (id, token, read_only) = match req.headers().get_one("Authorization") {
+                    Some(header) => {
+                        let secret = header.strip_prefix("Bearer ").ok_or((
+                            Status::Unauthorized,
+                            "Expected a bearer token.".to_string(),
+                        ))?;
+                        match db::get_api_token_by_hash(pool.as_ref(), hash_token(secret.trim()))
+                            .await
+                        {
+                            Ok(Some(t)) => (*t.owner(), Some(*t.id()), *t.read_only()),
+                            Ok(None) => {
+                                return Err((
+                                    Status::Unauthorized,
+                                    "Invalid or expired token.".to_string(),
+                                ))
+                            }
+                            Err(e) => return Err((Status::InternalServerError, e.to_string())),
+                        }
+                    }
+                    None => {
+                        let session = req
+                            .cookies()
+                            .get_private(SESSION_COOKIE)
+                            .and_then(|c| c.value().parse::<Uuid>().ok())
+                            .ok_or((Status::Unauthorized, "Not logged in.".to_string()))?;
+                        match db::get_session_owner(pool.as_ref(), session).await {
+                            Ok(Some(id)) => (id, None, false),
+                            Ok(None) => {
+                                return Err((
+                                    Status::Unauthorized,
+                                    "Session has ended.".to_string(),
+                                ))
+                            }
+                            Err(e) => return Err((Status::InternalServerError, e.to_string())),
+                        }
+                    }
+                };
+                match db::get_user(pool.as_ref(), id).await {
+                    Ok(Some(u)) if *u.disabled() => {
+                        Err((Status::Unauthorized, "Account is disabled.".to_string()))
+                    }
+                    Ok(Some(u)) => Ok(Self {
+                        id: *u.id(),
+                        username: u.username().clone(),
+                        token,
+                        read_only,
+                        admin: *u.is_admin(),
+                    }),
+                    Ok(None) => Err((Status::Unauthorized, "No such user.".to_string())),
+                    Err(e) => Err((Status::InternalServerError, e.to_string())),
+                }
+            })
+            .await;
+        match user {
+            Ok(u) => Outcome::Success(u.clone()),
+            Err(e) => Outcome::Failure(e.clone()),
+        }
+    }
+}
+
+/// Request guard for a user allowed to modify tasks, fails with `403` for
+/// read-only tokens.
+pub struct WriteUser(AuthUser);
+
+impl Deref for WriteUser {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &AuthUser {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WriteUser {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let user = match req.guard::<AuthUser>().await {
+            Outcome::Success(u) => u,
+            Outcome::Failure(f) => return Outcome::Failure(f),
+            Outcome::Forward(f) => return Outcome::Forward(f),
+        };
+        if user.read_only {
+            Outcome::Failure((Status::Forbidden, "Token is read-only.".to_string()))
+        } else {
+            Outcome::Success(Self(user))
+        }
+    }
+}
+
+/// Request guard for an admin using a browser session, fails with `403`
+/// otherwise.
+pub struct AdminUser(AuthUser);
+
+impl Deref for AdminUser {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &AuthUser {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let user = match req.guard::<AuthUser>().await {
+            Outcome::Success(u) => u,
+            Outcome::Failure(f) => return Outcome::Failure(f),
+            Outcome::Forward(f) => return Outcome::Forward(f),
+        };
+        if user.admin && user.token.is_none() {
+            Outcome::Success(Self(user))
+        } else {
+            Outcome::Failure((Status::Forbidden, "Requires an admin.".to_string()))
+        }
+    }
+}
+
+/// Starts a session for `user`.
+pub async fn login(pool: &PgPool, cookies: &CookieJar<'_>, user: &db::User) -> Result<()> {
+    let session = db::Session::new(*user.id(), SESSION_LIFETIME);
+    db::create_session(pool, &session).await?;
+    cookies.add_private(Cookie::new(SESSION_COOKIE, session.id().to_string()));
+    csrf::issue(cookies);
+    Ok(())
+}
+
+/// Ends the session on the server too, so a copied cookie stops working.
+pub async fn logout(pool: &PgPool, cookies: &CookieJar<'_>) -> Result<()> {
+    let session = cookies
+        .get_private(SESSION_COOKIE)
+        .and_then(|c| c.value().parse::<Uuid>().ok());
+    cookies.remove_private(Cookie::named(SESSION_COOKIE));
+    csrf::remove(cookies);
+    match session {
+        Some(session) => db::remove_session(pool, session).await.map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Hashes `password` with argon2 off the async workers.
+pub async fn hash_password(password: String) -> Result<String> {
+    task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|h| h.to_string())
+            .map_err(|e| format!("Cannot hash password: {}", e))
+    })
+    .await
+    .map_err(|e| Error::General(e.to_string()))?
+    .map_err(Error::General)
+}
+
+/// Checks `password` against `hash`, or against `DUMMY_HASH` for unknown
+/// users (`None`), which always fails.
+pub async fn verify_password(password: String, hash: Option<String>) -> bool {
+    task::spawn_blocking(move || {
+        let (hash, usable) = match hash.as_deref().map(PasswordHash::new) {
+            Some(Ok(h)) => (h, true),
+            _ => (
+                PasswordHash::new(DUMMY_HASH).expect("valid dummy hash"),
+                false,
+            ),
+        };
+        let verified = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+        verified && usable
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// A new random token secret.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", TOKEN_PREFIX, hex)
+}
+
+/// Tokens are long and random, so unlike passwords a plain hash is enough and
+/// keeps the lookup on every request cheap.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
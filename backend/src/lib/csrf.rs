@@ -0,0 +1,72 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use rocket::{
+    http::{Cookie, CookieJar, SameSite, Status},
+    outcome::Outcome,
+    request::{self, FromRequest, Request},
+};
+
+/// Cookie holding the CSRF token, readable by scripts so the frontend can
+/// echo it back.
+pub const CSRF_COOKIE: &str = "csrf";
+
+/// Header mutating requests repeat the CSRF token in.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Sets a fresh CSRF token for the session, on login and for sessions that
+/// predate the token.
+pub fn issue(cookies: &CookieJar<'_>) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    cookies.add(
+        Cookie::build(CSRF_COOKIE, token)
+            .path("/")
+            .same_site(SameSite::Strict)
+            .finish(),
+    );
+}
+
+pub fn remove(cookies: &CookieJar<'_>) {
+    cookies.remove(Cookie::named(CSRF_COOKIE));
+}
+
+/// Request guard for routes that change state on behalf of a session,
+/// fails with `403` unless the `X-CSRF-Token` header matches the CSRF cookie
+/// (double submit).
+///
+/// Requests authenticated with a bearer token carry no ambient credentials
+/// and are exempt.
+pub struct Csrf;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Csrf {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        if req.headers().contains("Authorization") {
+            return Outcome::Success(Csrf);
+        }
+        let cookie = req
+            .cookies()
+            .get(CSRF_COOKIE)
+            .map(|c| c.value().to_string());
+        match (cookie, req.headers().get_one(CSRF_HEADER)) {
+            (Some(expected), Some(token)) if !expected.is_empty() && same(&expected, token) => {
+                Outcome::Success(Csrf)
+            }
+            _ => Outcome::Failure((
+                Status::Forbidden,
+                "Missing or invalid CSRF token.".to_string(),
+            )),
+        }
+    }
+}
+
+/// Compares without stopping at the first difference.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -2,25 +2,67 @@ mod models;
 mod schema;
 use super::{Error, MyResult as Result, PgPool};
 use diesel::{
+    dsl::{now, IntervalDsl},
     pg::PgConnection,
     prelude::*,
     r2d2::{ConnectionManager, Pool},
 };
 use models::Task;
-use todomvc_shared::Entry;
+pub use models::{
+    ApiToken, AttachmentRow, IdempotentResponse, List, SearchRow, Session, ShareLinkRow, TagRow,
+    User, UserStatsRow,
+};
+use models::{
+    ListMember, OidcIdentity, PeriodRow, StatsRow, TaskDependency, TaskTag, HIGHLIGHT_START,
+    HIGHLIGHT_STOP,
+};
+use std::{collections::HashMap, env, time::SystemTime};
+use todomvc_shared::{
+    Entry, Member, Period, Priority, Recurrence, Role, Stats, Tag, MAX_SUBTASK_DEPTH, STATS_DAYS,
+    STATS_WEEKS,
+};
 use tokio_diesel::*;
 use uuid::Uuid;
 
 pub fn establish_connection() -> Result<PgPool> {
-    let db = "postgres://<user>:<passwd>@<host>/<db>";
+    let db = env::var("DATABASE_URL")
+        .unwrap_or_else(|_| "postgres://<user>:<passwd>@<host>/<db>".to_string());
     let manager = ConnectionManager::<PgConnection>::new(db);
     let pool = Pool::builder().build(manager)?;
     Ok(pool)
 }
 
-pub async fn crate_task(pool: &PgPool, content: String) -> Result<Entry> {
-    let task = Task::new(content);
-    create_task_full(pool, &task).await.map(|_| task.to_entry())
+/// How long a stored idempotency key is honored.
+const IDEMPOTENCY_KEY_TTL_HOURS: i32 = 24;
+
+/// Most tasks a search returns.
+const MAX_SEARCH_HITS: i64 = 20;
+
+/// Name of the list created for users who have none.
+const DEFAULT_LIST_NAME: &str = "Todos";
+
+pub async fn crate_task(
+    pool: &PgPool,
+    owner: Uuid,
+    list: Uuid,
+    tid: Option<Uuid>,
+    content: String,
+    priority: Priority,
+    parent: Option<Uuid>,
+) -> Result<Entry> {
+    let mut task = Task::new(owner, list, content);
+    task.set_priority(priority.level());
+    task.set_parent_id(parent);
+    if let Some(tid) = tid {
+        if let Some(e) = get_task(pool, owner, tid).await? {
+            return Ok(e);
+        }
+        task.set_id(tid);
+    }
+    create_task_full(pool, &task).await?;
+    // a new open subtask reopens its parents
+    settle(pool, get_ancestors(pool, *task.id()).await?).await?;
+    Ok(task.to_entry())
 }
 
 pub async fn create_task_full(pool: &PgPool, t: &Task) -> Result<usize> {
@@ -31,17 +73,50 @@ pub async fn create_task_full(pool: &PgPool, t: &Task) -> Result<usize> {
         .map_err(Error::AsyncDiesel)
 }
 
-pub async fn get_tasks(pool: &PgPool) -> Result<Vec<Entry>> {
-    schema::task::table
-        .load_async(pool)
-        .await
-        .map(|v: Vec<Task>| v.iter().map(Task::to_entry).collect())
-        .map_err(Error::AsyncDiesel)
+/// Tasks `uid` can see, only those in `list` and with `tag` if given, and
+/// at least `min_priority`. Most urgent first if `by_priority`.
+pub async fn get_tasks(
+    pool: &PgPool,
+    uid: Uuid,
+    list: Option<Uuid>,
+    min_priority: Priority,
+    tag: Option<Uuid>,
+    by_priority: bool,
+) -> Result<Vec<Entry>> {
+    use schema::task::dsl::*;
+    let mut readable = lists_with_role(pool, uid, Role::Viewer).await?;
+    if let Some(list) = list {
+        readable.retain(|l| *l == list);
+    }
+    // boxed queries are not `Send`, so this one is built on the connection
+    let tasks: Vec<Task> = pool
+        .run(move |conn| {
+            let mut visible = task
+                .filter(list_id.eq_any(readable))
+                .filter(priority.ge(min_priority.level()))
+                .into_boxed();
+            if let Some(tag) = tag {
+                use schema::task_tag::dsl::{tag_id, task_id, task_tag};
+                let tagged = task_tag.filter(tag_id.eq(tag)).select(task_id);
+                visible = visible.filter(id.eq_any(tagged));
+            }
+            if by_priority {
+                visible = visible.order(priority.desc());
+            }
+            visible.load(conn)
+        })
+        .await?;
+    with_tags(pool, tasks).await
 }
 
-pub async fn get_task(pool: &PgPool, task_id: Uuid) -> Result<Option<Entry>> {
+pub async fn get_task(pool: &PgPool, uid: Uuid, task_id: Uuid) -> Result<Option<Entry>> {
     use schema::task::dsl::*;
-    let tasks: Vec<Task> = task.filter(id.eq(task_id)).load_async(pool).await?;
+    let readable = lists_with_role(pool, uid, Role::Viewer).await?;
+    let tasks: Vec<Task> = task
+        .filter(id.eq(task_id))
+        .filter(list_id.eq_any(readable))
+        .load_async(pool)
+        .await?;
     if tasks.len() > 1 {
         Err(Error::General(format!(
             "We uses uuid for our id, but we get {} tasks when querying id: {}.",
@@ -51,56 +126,530 @@ pub async fn get_task(pool: &PgPool, task_id: Uuid) -> Result<Option<Entry>> {
     } else if tasks.is_empty() {
         Ok(None)
     } else {
-        let t = tasks[0].clone();
-        let entry = t.to_entry();
-        Ok(Some(entry))
-    }
-}
-
-pub async fn update_all_tasks(pool: &PgPool, entries: Vec<Entry>) -> Result<()> {
-    for entry in entries {
-        let id = *entry.id();
-        match get_task(pool, id).await {
-            Ok(o) => match o {
-                Some(_e) => update_task(pool, id, entry).await?,
-                None => {
-                    let task = Task::from(&entry);
-                    create_task_full(pool, &task).await?
-                }
-            },
-            Err(_) => {
-                let task = Task::from(&entry);
-                create_task_full(pool, &task).await?
+        Ok(with_tags(pool, tasks).await?.pop())
+    }
+}
+
+/// Entries of `tasks` with the tags on each and the tasks they depend on.
+async fn with_tags(pool: &PgPool, tasks: Vec<Task>) -> Result<Vec<Entry>> {
+    use schema::{tag, task, task_dependency, task_tag};
+    let ids: Vec<Uuid> = tasks.iter().map(|t| *t.id()).collect();
+    let found: Vec<(Uuid, Uuid, String)> = task_tag::table
+        .inner_join(tag::table)
+        .filter(task_tag::task_id.eq_any(ids.clone()))
+        .select((task_tag::task_id, tag::id, tag::name))
+        .order(tag::name)
+        .load_async(pool)
+        .await?;
+    let mut tags: HashMap<Uuid, Vec<Tag>> = HashMap::new();
+    for (task_id, tag_id, name) in found {
+        tags.entry(task_id)
+            .or_default()
+            .push(Tag::new(tag_id, name));
+    }
+    let found: Vec<(Uuid, Uuid, bool)> = task_dependency::table
+        .inner_join(task::table.on(task::id.eq(task_dependency::depends_on)))
+        .filter(task_dependency::task_id.eq_any(ids))
+        .select((
+            task_dependency::task_id,
+            task_dependency::depends_on,
+            task::completed,
+        ))
+        .load_async(pool)
+        .await?;
+    let mut prerequisites: HashMap<Uuid, (Vec<Uuid>, bool)> = HashMap::new();
+    for (task_id, depends_on, done) in found {
+        let (on, blocked) = prerequisites.entry(task_id).or_default();
+        on.push(depends_on);
+        *blocked |= !done;
+    }
+    Ok(tasks
+        .iter()
+        .map(|t| {
+            let mut e = t.to_entry();
+            e.set_tags(tags.remove(t.id()).unwrap_or_default());
+            let (on, blocked) = prerequisites.remove(t.id()).unwrap_or_default();
+            e.set_depends_on(on);
+            e.set_blocked(blocked);
+            e
+        })
+        .collect())
+}
+
+/// The ones among `tids` that `uid` can see.
+pub async fn visible_tasks(pool: &PgPool, uid: Uuid, tids: Vec<Uuid>) -> Result<Vec<Uuid>> {
+    use schema::task::dsl::*;
+    let readable = lists_with_role(pool, uid, Role::Viewer).await?;
+    task.filter(id.eq_any(tids))
+        .filter(list_id.eq_any(readable))
+        .select(id)
+        .load_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Tasks `uid` can read whose content or notes match `terms`, in `list` or
+/// in any list, best matches first. Every word of `terms` has to match the
+/// start of a word, so results show up while the last word is being typed.
+pub async fn search_tasks(
+    pool: &PgPool,
+    uid: Uuid,
+    list: Option<Uuid>,
+    terms: &str,
+) -> Result<Vec<SearchRow>> {
+    use diesel::sql_types::{Array, BigInt, Text, Uuid as SqlUuid};
+    let query = match prefix_query(terms) {
+        Some(q) => q,
+        None => return Ok(Vec::new()),
+    };
+    let mut readable = lists_with_role(pool, uid, Role::Viewer).await?;
+    if let Some(list) = list {
+        readable.retain(|l| *l == list);
+    }
+    let options = format!(
+        "StartSel={}, StopSel={}, MinWords=5, MaxWords=20, MaxFragments=2, \
+         FragmentDelimiter=\" … \"",
+        HIGHLIGHT_START, HIGHLIGHT_STOP
+    );
+    diesel::sql_query(
+        "select t.id, t.list_id, t.content, ts_rank(t.search, q) as rank, \
+             ts_headline('english', t.content || E'\\n' || t.notes, q, $3) as snippet \
+         from task t, to_tsquery('english', $1) q \
+         where t.search @@ q and t.list_id = any($2) \
+         order by rank desc, t.content \
+         limit $4",
+    )
+    .bind::<Text, _>(query)
+    .bind::<Array<SqlUuid>, _>(readable)
+    .bind::<Text, _>(options)
+    .bind::<BigInt, _>(MAX_SEARCH_HITS)
+    .load_async(pool)
+    .await
+    .map_err(Error::AsyncDiesel)
+}
+
+/// `word:* & word:*` for the words of `terms`, `None` without any.
+fn prefix_query(terms: &str) -> Option<String> {
+    let words: Vec<_> = terms
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| format!("{}:*", w.to_lowercase()))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" & "))
+    }
+}
+
+/// How the tasks `uid` can read, in `list` or in any list, are coming
+/// along. Days and weeks are in UTC.
+pub async fn get_stats(pool: &PgPool, uid: Uuid, list: Option<Uuid>) -> Result<Stats> {
+    use diesel::sql_types::{Array, Timestamp, Uuid as SqlUuid};
+    let mut readable = lists_with_role(pool, uid, Role::Viewer).await?;
+    if let Some(list) = list {
+        readable.retain(|l| *l == list);
+    }
+    let at = SystemTime::now();
+    let days = get_periods(pool, readable.clone(), at, "day", STATS_DAYS).await?;
+    let weeks = get_periods(pool, readable.clone(), at, "week", STATS_WEEKS).await?;
+    let mut rows: Vec<StatsRow> = diesel::sql_query(
+        "select count(*) filter (where not t.completed) as open, \
+             count(*) filter (where not t.completed and t.due_at < $2) as overdue, \
+             count(*) filter (where not t.completed and exists ( \
+                 select 1 from task_dependency d join task p on p.id = d.depends_on \
+                 where d.task_id = t.id and not p.completed)) as blocked, \
+             count(*) filter (where t.completed) as completed, \
+             extract(epoch from avg(t.completed_at - t.created_at) \
+                 filter (where t.completed))::float8 as average_secs \
+         from task t where t.list_id = any($1)",
+    )
+    .bind::<Array<SqlUuid>, _>(readable)
+    .bind::<Timestamp, _>(at)
+    .load_async(pool)
+    .await?;
+    match rows.pop() {
+        Some(row) => Ok(row.to_stats(days, weeks)),
+        None => Err(Error::General("No task counts.".to_string())),
+    }
+}
+
+/// Tasks of `lists` created and completed in each of the last `count` days
+/// or weeks up to `at`, `unit` being `day` or `week`, oldest first.
+async fn get_periods(
+    pool: &PgPool,
+    lists: Vec<Uuid>,
+    at: SystemTime,
+    unit: &'static str,
+    count: i32,
+) -> Result<Vec<Period>> {
+    use diesel::sql_types::{Array, Integer, Text, Timestamp, Uuid as SqlUuid};
+    let rows: Vec<PeriodRow> = diesel::sql_query(
+        "select p.start, \
+             (select count(*) from task t where t.list_id = any($1) \
+                 and t.created_at >= p.start and t.created_at < p.start + s.step) as created, \
+             (select count(*) from task t where t.list_id = any($1) and t.completed \
+                 and t.completed_at >= p.start and t.completed_at < p.start + s.step) \
+                 as completed \
+         from (select ('1 ' || $3)::interval as step) s, \
+             generate_series(date_trunc($3, $2) - ($4 - 1) * s.step, \
+                 date_trunc($3, $2), s.step) p(start) \
+         order by p.start",
+    )
+    .bind::<Array<SqlUuid>, _>(lists)
+    .bind::<Timestamp, _>(at)
+    .bind::<Text, _>(unit)
+    .bind::<Integer, _>(count)
+    .load_async(pool)
+    .await?;
+    Ok(rows.iter().map(PeriodRow::to_period).collect())
+}
+
+/// The role `uid` has on the list of a task, `None` if the task is not
+/// visible to `uid`.
+pub async fn get_task_role(pool: &PgPool, uid: Uuid, tid: Uuid) -> Result<Option<Role>> {
+    match get_task_list(pool, tid).await? {
+        Some(list) => get_role(pool, uid, list).await,
+        None => Ok(None),
+    }
+}
+
+/// The list a task is in, whoever asks.
+pub async fn get_task_list(pool: &PgPool, tid: Uuid) -> Result<Option<Uuid>> {
+    use schema::task::dsl::*;
+    let mut lists: Vec<Uuid> = task
+        .filter(id.eq(tid))
+        .select(list_id)
+        .load_async(pool)
+        .await?;
+    Ok(lists.pop())
+}
+
+/// Ids of the tasks above a task, its parent first.
+pub async fn get_ancestors(pool: &PgPool, tid: Uuid) -> Result<Vec<Uuid>> {
+    pool.run(move |conn| ancestors_in(conn, tid))
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// `get_ancestors` on `conn`, so that it can take part in a transaction.
+fn ancestors_in(conn: &PgConnection, tid: Uuid) -> QueryResult<Vec<Uuid>> {
+    use schema::task::dsl::*;
+    let mut found = Vec::new();
+    let mut current = tid;
+    // cycles are never stored, the bound keeps a broken row from hanging us
+    while found.len() <= MAX_SUBTASK_DEPTH {
+        let parents: Vec<Option<Uuid>> =
+            task.filter(id.eq(current)).select(parent_id).load(conn)?;
+        match parents.into_iter().next().flatten() {
+            Some(p) => {
+                found.push(p);
+                current = p;
+            }
+            None => break,
+        }
+    }
+    Ok(found)
+}
+
+/// The tasks below a task, level by level.
+async fn subtask_levels(pool: &PgPool, tid: Uuid) -> Result<Vec<Vec<Task>>> {
+    use schema::task::dsl::*;
+    let mut levels = Vec::new();
+    let mut parents = vec![tid];
+    while !parents.is_empty() && levels.len() <= MAX_SUBTASK_DEPTH {
+        let children: Vec<Task> = task
+            .filter(parent_id.eq_any(parents))
+            .load_async(pool)
+            .await?;
+        parents = children.iter().map(|t| *t.id()).collect();
+        if !children.is_empty() {
+            levels.push(children);
+        }
+    }
+    Ok(levels)
+}
+
+/// How many levels of subtasks are below a task.
+pub async fn subtask_height(pool: &PgPool, tid: Uuid) -> Result<usize> {
+    Ok(subtask_levels(pool, tid).await?.len())
+}
+
+/// Every task below one `uid` can see, parents before their subtasks.
+/// `None` if `uid` cannot see the task.
+pub async fn get_subtasks(pool: &PgPool, uid: Uuid, tid: Uuid) -> Result<Option<Vec<Entry>>> {
+    if get_task(pool, uid, tid).await?.is_none() {
+        return Ok(None);
+    }
+    let tasks = subtask_levels(pool, tid)
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+    with_tags(pool, tasks).await.map(Some)
+}
+
+/// Subtasks right below a task, and how many of them are still open.
+pub async fn count_subtasks(pool: &PgPool, tid: Uuid) -> Result<(i64, i64)> {
+    pool.run(move |conn| count_subtasks_in(conn, tid))
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+fn count_subtasks_in(conn: &PgConnection, tid: Uuid) -> QueryResult<(i64, i64)> {
+    use schema::task::dsl::*;
+    let states: Vec<bool> = task
+        .filter(parent_id.eq(tid))
+        .select(completed)
+        .load(conn)?;
+    let open = states.iter().filter(|done| !**done).count();
+    Ok((states.len() as i64, open as i64))
+}
+
+/// Makes a task `uid` can edit a subtask of `parent`, or a top-level task
+/// without one. The caller checks that this makes no cycle.
+pub async fn set_parent(
+    pool: &PgPool,
+    uid: Uuid,
+    tid: Uuid,
+    parent: Option<Uuid>,
+) -> Result<usize> {
+    use schema::task::dsl::*;
+    let writable = lists_with_role(pool, uid, Role::Editor).await?;
+    let old = get_ancestors(pool, tid).await?;
+    let updated = diesel::update(task)
+        .filter(id.eq(tid))
+        .filter(list_id.eq_any(writable))
+        .set(parent_id.eq(parent))
+        .execute_async(pool)
+        .await?;
+    if updated > 0 {
+        settle(pool, old).await?;
+        settle(pool, get_ancestors(pool, tid).await?).await?;
+    }
+    Ok(updated)
+}
+
+/// Keeps completion consistent along `chain`, a task followed by the tasks
+/// above it: a task with open subtasks is open, one that completes itself
+/// is done once all its subtasks are.
+async fn settle(pool: &PgPool, chain: Vec<Uuid>) -> Result<()> {
+    pool.transaction(move |conn| settle_in(conn, &chain))
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+fn settle_in(conn: &PgConnection, chain: &[Uuid]) -> QueryResult<()> {
+    use schema::task::dsl::*;
+    for (i, tid) in chain.iter().enumerate() {
+        let mut found: Vec<(bool, bool)> = task
+            .filter(id.eq(tid))
+            .select((completed, auto_complete))
+            .load(conn)?;
+        let (done, auto) = match found.pop() {
+            Some(f) => f,
+            None => break,
+        };
+        let settled = match count_subtasks_in(conn, *tid)? {
+            (_, open) if open > 0 => false,
+            (total, _) if total > 0 && auto => true,
+            _ => done,
+        };
+        // past the first task, the ones further up only change if this one did
+        if settled == done {
+            if i == 0 {
+                continue;
             }
+            break;
+        }
+        let done_at = if settled {
+            Some(SystemTime::now())
+        } else {
+            None
         };
+        diesel::update(task.filter(id.eq(tid)))
+            .set((completed.eq(settled), completed_at.eq(done_at)))
+            .execute(conn)?;
     }
     Ok(())
 }
 
-pub async fn update_task(pool: &PgPool, eid: Uuid, e: Entry) -> Result<usize> {
+/// What `update_all_tasks` did.
+#[derive(Debug, PartialEq)]
+pub enum BulkUpdate {
+    Done,
+    /// Nothing changed, these tasks exist but cannot be written to.
+    Foreign(Vec<Uuid>),
+    /// Nothing changed, a task was completed before its subtasks.
+    Unfinished,
+}
+
+/// Updates the tasks `owner` can already write to, new ones are created in
+/// `list`. All of it happens in one transaction, which changes nothing
+/// unless every task can be written to and every completed task has its
+/// subtasks done, as in `update_task`.
+pub async fn update_all_tasks(
+    pool: &PgPool,
+    owner: Uuid,
+    list: Uuid,
+    mut entries: Vec<Entry>,
+) -> Result<BulkUpdate> {
+    // subtasks first, so that completing a whole tree does not reopen the
+    // parents on the way
+    let parents: HashMap<Uuid, Uuid> = entries
+        .iter()
+        .filter_map(|e| e.parent_id().map(|p| (*e.id(), p)))
+        .collect();
+    let depth = |mut id: Uuid| {
+        let mut depth = 0;
+        while let (Some(p), true) = (parents.get(&id), depth <= MAX_SUBTASK_DEPTH) {
+            depth += 1;
+            id = *p;
+        }
+        depth
+    };
+    entries.sort_by_key(|e| std::cmp::Reverse(depth(*e.id())));
+    let updated = pool.transaction(move |conn| {
+        use schema::task::dsl::{id, list_id, task};
+        let writable = lists_with_role_in(conn, owner, Role::Editor)?;
+        let ids: Vec<Uuid> = entries.iter().map(|e| *e.id()).collect();
+        let existing: Vec<(Uuid, Uuid)> = task
+            .filter(id.eq_any(ids))
+            .select((id, list_id))
+            .load(conn)?;
+        let foreign: Vec<Uuid> = existing
+            .iter()
+            .filter(|(_, l)| !writable.contains(l))
+            .map(|(t, _)| *t)
+            .collect();
+        if !foreign.is_empty() {
+            return Ok(BulkUpdate::Foreign(foreign));
+        }
+        for entry in entries {
+            let tid = *entry.id();
+            // the subtasks in the batch are written by now
+            if *entry.completed() && count_subtasks_in(conn, tid)?.1 > 0 {
+                return Err(diesel::result::Error::RollbackTransaction);
+            }
+            if existing.iter().any(|(t, _)| *t == tid) {
+                update_task_in(conn, owner, tid, &entry)?;
+            } else {
+                diesel::insert_into(task)
+                    .values(&Task::from_entry(owner, list, &entry))
+                    .execute(conn)?;
+            }
+        }
+        Ok(BulkUpdate::Done)
+    });
+    match updated.await {
+        Err(AsyncError::Error(diesel::result::Error::RollbackTransaction)) => {
+            Ok(BulkUpdate::Unfinished)
+        }
+        updated => updated.map_err(Error::AsyncDiesel),
+    }
+}
+
+/// Returns the number of updated rows, `0` if `uid` cannot edit such a task.
+/// Completion is settled from the task upwards, so a task with open subtasks
+/// stays open. Completing a recurring task creates its next occurrence once
+/// it is settled as done, completed tasks do not recur.
+pub async fn update_task(pool: &PgPool, uid: Uuid, eid: Uuid, e: Entry) -> Result<usize> {
+    pool.transaction(move |conn| update_task_in(conn, uid, eid, &e))
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+fn update_task_in(conn: &PgConnection, uid: Uuid, eid: Uuid, e: &Entry) -> QueryResult<usize> {
     use schema::task::dsl::*;
-    let t = Task::from(e);
-    diesel::update(schema::task::table)
+    let writable = lists_with_role_in(conn, uid, Role::Editor)?;
+    let before: Option<Task> = task
         .filter(id.eq(eid))
+        .filter(list_id.eq_any(writable.clone()))
+        .load(conn)?
+        .pop();
+    // kept while the task stays completed
+    let done_at = match &before {
+        Some(b) if *b.completed() && *e.completed() => *b.completed_at(),
+        _ if *e.completed() => Some(SystemTime::now()),
+        _ => None,
+    };
+    let updated = diesel::update(task)
+        .filter(id.eq(eid))
+        .filter(list_id.eq_any(writable))
         .set((
-            content.eq(t.content().to_owned()),
-            completed.eq(*t.completed()),
-            editing.eq(*t.editing()),
+            content.eq(e.content().to_owned()),
+            completed.eq(*e.completed()),
+            completed_at.eq(done_at),
+            due_at.eq(*e.due_at()),
+            priority.eq(e.priority().level()),
+            auto_complete.eq(*e.auto_complete()),
+            recurrence.eq(e.recurrence().map(|r| r.to_rule())),
+            notes.eq(e.notes().to_owned()),
         ))
-        .execute_async(pool)
-        .await
-        .map_err(Error::AsyncDiesel)
+        .execute(conn)?;
+    if updated == 0 {
+        return Ok(0);
+    }
+    let mut chain = vec![eid];
+    chain.extend(ancestors_in(conn, eid)?);
+    settle_in(conn, &chain)?;
+    if let Some(rule) = e.recurrence() {
+        let done: bool = task.filter(id.eq(eid)).select(completed).first(conn)?;
+        if done {
+            // the rule moves on to the next occurrence
+            diesel::update(task.filter(id.eq(eid)))
+                .set(recurrence.eq(None::<String>))
+                .execute(conn)?;
+            match before {
+                Some(before) if !*before.completed() => recur_in(conn, &before, e, *rule)?,
+                _ => (),
+            }
+        }
+    }
+    Ok(updated)
+}
+
+/// Creates the next occurrence of a task that was just completed, with its
+/// tags.
+fn recur_in(conn: &PgConnection, before: &Task, e: &Entry, rule: Recurrence) -> QueryResult<()> {
+    let mut next = Task::from_entry(*before.owner(), *before.list_id(), e);
+    next.set_id(Uuid::new_v4());
+    next.set_completed(false);
+    next.set_completed_at(None);
+    next.set_parent_id(*before.parent_id());
+    next.set_due_at(Some(rule.next_due(*e.due_at(), SystemTime::now())));
+    diesel::insert_into(schema::task::table)
+        .values(&next)
+        .execute(conn)?;
+    use schema::task_tag::dsl::*;
+    let tags: Vec<Uuid> = task_tag
+        .filter(task_id.eq(*before.id()))
+        .select(tag_id)
+        .load(conn)?;
+    let copies: Vec<TaskTag> = tags
+        .into_iter()
+        .map(|t| TaskTag::new(*next.id(), t))
+        .collect();
+    diesel::insert_into(task_tag).values(copies).execute(conn)?;
+    Ok(())
 }
 
-pub async fn remove_task(pool: &PgPool, eid: Uuid) -> Result<usize> {
+pub async fn remove_task(pool: &PgPool, uid: Uuid, eid: Uuid) -> Result<usize> {
     use schema::task::dsl::*;
-    match get_task(pool, eid).await {
+    match get_task(pool, uid, eid).await {
         Ok(o) => match o {
-            Some(_e) => diesel::delete(schema::task::table)
-                .filter(id.eq(eid))
-                .execute_async(pool)
-                .await
-                .map_err(Error::AsyncDiesel),
+            Some(_e) => {
+                let writable = lists_with_role(pool, uid, Role::Editor).await?;
+                let parents = get_ancestors(pool, eid).await?;
+                let removed = diesel::delete(task)
+                    .filter(id.eq(eid))
+                    .filter(list_id.eq_any(writable))
+                    .execute_async(pool)
+                    .await?;
+                // its subtasks went with it, the parent may be done now
+                settle(pool, parents).await?;
+                Ok(removed)
+            }
             None => Err(Error::General("Task table is empty!".to_string())),
         },
         Err(e) => Err(Error::General(format!(
@@ -110,3 +659,814 @@ pub async fn remove_task(pool: &PgPool, eid: Uuid) -> Result<usize> {
         ))),
     }
 }
+
+/// Moves a task with its subtasks to `list`, `uid` must be able to edit both
+/// lists. A moved subtask leaves its parent behind.
+pub async fn move_task(pool: &PgPool, uid: Uuid, tid: Uuid, list: Uuid) -> Result<usize> {
+    use schema::task::dsl::*;
+    let writable = lists_with_role(pool, uid, Role::Editor).await?;
+    if !writable.contains(&list) {
+        return Ok(0);
+    }
+    let parents = get_ancestors(pool, tid).await?;
+    let moved = diesel::update(task)
+        .filter(id.eq(tid))
+        .filter(list_id.eq_any(writable))
+        .set((list_id.eq(list), parent_id.eq(None::<Uuid>)))
+        .execute_async(pool)
+        .await?;
+    if moved > 0 {
+        let below: Vec<Uuid> = subtask_levels(pool, tid)
+            .await?
+            .iter()
+            .flatten()
+            .map(|t| *t.id())
+            .collect();
+        diesel::update(task)
+            .filter(id.eq_any(below.clone()))
+            .set(list_id.eq(list))
+            .execute_async(pool)
+            .await?;
+        let mut moved = below;
+        moved.push(tid);
+        drop_crossing_dependencies(pool, moved).await?;
+        settle(pool, parents).await?;
+    }
+    Ok(moved)
+}
+
+/// Removes the dependencies between the `moved` tasks and the ones they
+/// left behind, dependencies stay within a list.
+async fn drop_crossing_dependencies(pool: &PgPool, moved: Vec<Uuid>) -> Result<usize> {
+    use schema::task_dependency::dsl::*;
+    diesel::delete(task_dependency)
+        .filter(
+            task_id
+                .eq_any(moved.clone())
+                .and(depends_on.ne_all(moved.clone()))
+                .or(depends_on.eq_any(moved.clone()).and(task_id.ne_all(moved))),
+        )
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Ids of the lists `uid` has at least role `min` on.
+async fn lists_with_role(pool: &PgPool, uid: Uuid, min: Role) -> Result<Vec<Uuid>> {
+    pool.run(move |conn| lists_with_role_in(conn, uid, min))
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+fn lists_with_role_in(conn: &PgConnection, uid: Uuid, min: Role) -> QueryResult<Vec<Uuid>> {
+    use schema::list_members::dsl::*;
+    let roles: Vec<&str> = Role::ALL
+        .iter()
+        .filter(|r| **r >= min)
+        .map(Role::as_str)
+        .collect();
+    list_members
+        .filter(user_id.eq(uid))
+        .filter(role.eq_any(roles))
+        .select(list_id)
+        .load(conn)
+}
+
+/// The role of `uid` on `lid`, `None` if `uid` is not a member.
+pub async fn get_role(pool: &PgPool, uid: Uuid, lid: Uuid) -> Result<Option<Role>> {
+    use schema::list_members::dsl::*;
+    let mut found: Vec<String> = list_members
+        .filter(user_id.eq(uid))
+        .filter(list_id.eq(lid))
+        .select(role)
+        .load_async(pool)
+        .await?;
+    Ok(found.pop().and_then(|r| Role::parse(&r)))
+}
+
+/// Creates a list owned by its creator.
+pub async fn create_list(pool: &PgPool, l: &List) -> Result<usize> {
+    let list = l.clone();
+    let member = ListMember::new(*l.id(), *l.owner(), Role::Owner);
+    pool.transaction(move |conn| {
+        diesel::insert_into(schema::lists::table)
+            .values(&list)
+            .execute(conn)?;
+        diesel::insert_into(schema::list_members::table)
+            .values(&member)
+            .execute(conn)
+    })
+    .await
+    .map_err(Error::AsyncDiesel)
+}
+
+pub async fn crate_list(
+    pool: &PgPool,
+    uid: Uuid,
+    lid: Option<Uuid>,
+    name: String,
+) -> Result<(List, Role)> {
+    let mut list = List::new(uid, name);
+    if let Some(lid) = lid {
+        if let Some(found) = get_list(pool, uid, lid).await? {
+            return Ok(found);
+        }
+        list.set_id(lid);
+    }
+    create_list(pool, &list).await.map(|_| (list, Role::Owner))
+}
+
+/// Lists `uid` is a member of, with the role `uid` has on each.
+pub async fn get_lists(pool: &PgPool, uid: Uuid) -> Result<Vec<(List, Role)>> {
+    use schema::lists::dsl::*;
+    let roles: HashMap<Uuid, Role> = {
+        use schema::list_members::dsl::*;
+        let memberships: Vec<(Uuid, String)> = list_members
+            .filter(user_id.eq(uid))
+            .select((list_id, role))
+            .load_async(pool)
+            .await?;
+        memberships
+            .into_iter()
+            .filter_map(|(l, r)| Role::parse(&r).map(|r| (l, r)))
+            .collect()
+    };
+    let found: Vec<List> = lists
+        .filter(id.eq_any(roles.keys().copied().collect::<Vec<_>>()))
+        .select((id, owner, name))
+        .order(created_at)
+        .load_async(pool)
+        .await?;
+    Ok(found
+        .into_iter()
+        .map(|l| {
+            let r = roles[l.id()];
+            (l, r)
+        })
+        .collect())
+}
+
+pub async fn get_list(pool: &PgPool, uid: Uuid, lid: Uuid) -> Result<Option<(List, Role)>> {
+    use schema::lists::dsl::*;
+    let r = match get_role(pool, uid, lid).await? {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let mut found: Vec<List> = lists
+        .filter(id.eq(lid))
+        .select((id, owner, name))
+        .load_async(pool)
+        .await?;
+    Ok(found.pop().map(|l| (l, r)))
+}
+
+/// The oldest list `uid` owns, created if the user owns none.
+pub async fn default_list(pool: &PgPool, uid: Uuid) -> Result<(List, Role)> {
+    let owned = get_lists(pool, uid)
+        .await?
+        .into_iter()
+        .find(|(_, r)| *r == Role::Owner);
+    match owned {
+        Some(found) => Ok(found),
+        None => crate_list(pool, uid, None, DEFAULT_LIST_NAME.to_string()).await,
+    }
+}
+
+pub async fn rename_list(pool: &PgPool, uid: Uuid, lid: Uuid, new_name: String) -> Result<usize> {
+    use schema::lists::dsl::*;
+    let owned = lists_with_role(pool, uid, Role::Owner).await?;
+    diesel::update(lists)
+        .filter(id.eq(lid))
+        .filter(id.eq_any(owned))
+        .set(name.eq(new_name))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Removes a list `uid` owns along with its tasks.
+pub async fn remove_list(pool: &PgPool, uid: Uuid, lid: Uuid) -> Result<usize> {
+    use schema::lists::dsl::*;
+    let owned = lists_with_role(pool, uid, Role::Owner).await?;
+    diesel::delete(lists)
+        .filter(id.eq(lid))
+        .filter(id.eq_any(owned))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Members of `lid`, empty unless `uid` is one of them.
+pub async fn get_members(pool: &PgPool, uid: Uuid, lid: Uuid) -> Result<Vec<Member>> {
+    use schema::{list_members, users};
+    if get_role(pool, uid, lid).await?.is_none() {
+        return Ok(Vec::new());
+    }
+    let found: Vec<(Uuid, String, String)> = list_members::table
+        .inner_join(users::table)
+        .filter(list_members::list_id.eq(lid))
+        .select((users::id, users::username, list_members::role))
+        .order(list_members::created_at)
+        .load_async(pool)
+        .await?;
+    Ok(found
+        .into_iter()
+        .filter_map(|(id, name, r)| Role::parse(&r).map(|r| Member::new(id, name, r)))
+        .collect())
+}
+
+/// Adds `member` to `lid` or changes the role it has there.
+pub async fn set_member(pool: &PgPool, lid: Uuid, member: Uuid, r: Role) -> Result<usize> {
+    use schema::list_members::dsl::*;
+    diesel::insert_into(list_members)
+        .values(&ListMember::new(lid, member, r))
+        .on_conflict((list_id, user_id))
+        .do_update()
+        .set(role.eq(r.as_str()))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn remove_member(pool: &PgPool, lid: Uuid, member: Uuid) -> Result<usize> {
+    use schema::list_members::dsl::*;
+    diesel::delete(list_members)
+        .filter(list_id.eq(lid))
+        .filter(user_id.eq(member))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn count_owners(pool: &PgPool, lid: Uuid) -> Result<i64> {
+    use schema::list_members::dsl::*;
+    list_members
+        .filter(list_id.eq(lid))
+        .filter(role.eq(Role::Owner.as_str()))
+        .count()
+        .get_result_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn create_share_link(pool: &PgPool, l: &ShareLinkRow) -> Result<usize> {
+    diesel::insert_into(schema::share_links::table)
+        .values(l)
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Unexpired share links of `lid`, empty unless `uid` owns the list.
+pub async fn get_share_links(pool: &PgPool, uid: Uuid, lid: Uuid) -> Result<Vec<ShareLinkRow>> {
+    use schema::share_links::dsl::*;
+    if get_role(pool, uid, lid).await? != Some(Role::Owner) {
+        return Ok(Vec::new());
+    }
+    share_links
+        .filter(list_id.eq(lid))
+        .filter(expires_at.is_null().or(expires_at.gt(now)))
+        .select((id, list_id, created_by, expires_at))
+        .order(created_at)
+        .load_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// The list behind an unexpired share link, with its tasks.
+pub async fn get_shared_list(pool: &PgPool, sid: Uuid) -> Result<Option<(List, Vec<Entry>)>> {
+    let lid = {
+        use schema::share_links::dsl::*;
+        let mut found: Vec<Uuid> = share_links
+            .filter(id.eq(sid))
+            .filter(expires_at.is_null().or(expires_at.gt(now)))
+            .select(list_id)
+            .load_async(pool)
+            .await?;
+        match found.pop() {
+            Some(lid) => lid,
+            None => return Ok(None),
+        }
+    };
+    let mut found: Vec<List> = {
+        use schema::lists::dsl::*;
+        lists
+            .filter(id.eq(lid))
+            .select((id, owner, name))
+            .load_async(pool)
+            .await?
+    };
+    let list = match found.pop() {
+        Some(l) => l,
+        None => return Ok(None),
+    };
+    let tasks: Vec<Task> = {
+        use schema::task::dsl::*;
+        task.filter(list_id.eq(lid)).load_async(pool).await?
+    };
+    Ok(Some((list, with_tags(pool, tasks).await?)))
+}
+
+/// Revokes a share link of a list `uid` owns.
+pub async fn remove_share_link(pool: &PgPool, uid: Uuid, lid: Uuid, sid: Uuid) -> Result<usize> {
+    use schema::share_links::dsl::*;
+    if get_role(pool, uid, lid).await? != Some(Role::Owner) {
+        return Ok(0);
+    }
+    diesel::delete(share_links)
+        .filter(id.eq(sid))
+        .filter(list_id.eq(lid))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn get_idempotent_response(
+    pool: &PgPool,
+    uid: Uuid,
+    k: String,
+) -> Result<Option<IdempotentResponse>> {
+    use schema::idempotency_key::dsl::*;
+    let mut responses: Vec<IdempotentResponse> = idempotency_key
+        .filter(owner.eq(uid))
+        .filter(key.eq(k))
+        .filter(created_at.gt(now - IDEMPOTENCY_KEY_TTL_HOURS.hours()))
+        .select((owner, key, request, status, response))
+        .load_async(pool)
+        .await?;
+    Ok(responses.pop())
+}
+
+/// Stores `r` as reserved, `false` if another request holds its key.
+pub async fn reserve_idempotency_key(pool: &PgPool, r: &IdempotentResponse) -> Result<bool> {
+    use schema::idempotency_key::dsl::*;
+    diesel::delete(idempotency_key)
+        .filter(created_at.lt(now - IDEMPOTENCY_KEY_TTL_HOURS.hours()))
+        .execute_async(pool)
+        .await?;
+    let inserted = diesel::insert_into(idempotency_key)
+        .values(r)
+        .on_conflict_do_nothing()
+        .execute_async(pool)
+        .await?;
+    Ok(inserted > 0)
+}
+
+/// Stores the response of a request that reserved its key.
+pub async fn save_idempotent_response(pool: &PgPool, r: &IdempotentResponse) -> Result<usize> {
+    use schema::idempotency_key::dsl::*;
+    diesel::update(idempotency_key)
+        .filter(owner.eq(*r.owner()))
+        .filter(key.eq(r.key().to_owned()))
+        .set((status.eq(*r.status()), response.eq(r.response().to_owned())))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Frees a key that is still reserved, so the request can be retried.
+pub async fn release_idempotency_key(pool: &PgPool, uid: Uuid, k: String) -> Result<usize> {
+    use schema::idempotency_key::dsl::*;
+    diesel::delete(idempotency_key)
+        .filter(owner.eq(uid))
+        .filter(key.eq(k))
+        .filter(status.is_null())
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn create_user(pool: &PgPool, u: &User) -> Result<usize> {
+    diesel::insert_into(schema::users::table)
+        .values(u)
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn get_user(pool: &PgPool, uid: Uuid) -> Result<Option<User>> {
+    use schema::users::dsl::*;
+    let mut found: Vec<User> = users
+        .filter(id.eq(uid))
+        .select((id, username, password_hash, is_admin, disabled))
+        .load_async(pool)
+        .await?;
+    Ok(found.pop())
+}
+
+pub async fn get_user_by_name(pool: &PgPool, name: String) -> Result<Option<User>> {
+    use schema::users::dsl::*;
+    let mut found: Vec<User> = users
+        .filter(username.eq(name))
+        .select((id, username, password_hash, is_admin, disabled))
+        .load_async(pool)
+        .await?;
+    Ok(found.pop())
+}
+
+/// Every user with counts of what they own, for the admin page.
+pub async fn get_user_stats(pool: &PgPool) -> Result<Vec<UserStatsRow>> {
+    diesel::sql_query(
+        "select u.id, u.username, u.is_admin, u.disabled, u.created_at, \
+             (select count(*) from task t where t.owner = u.id) as tasks, \
+             (select count(*) from lists l where l.owner = u.id) as lists, \
+             (select coalesce(sum(octet_length(t.content) + octet_length(t.notes)), 0) \
+                 from task t \
+                 where t.owner = u.id) + \
+             (select coalesce(sum(a.size), 0)::bigint \
+                 from attachments a join task t on t.id = a.task_id \
+                 where t.owner = u.id) as storage_bytes \
+         from users u order by u.username",
+    )
+    .load_async(pool)
+    .await
+    .map_err(Error::AsyncDiesel)
+}
+
+/// Disabling a user also ends their sessions, so that enabling them again
+/// does not bring those back.
+pub async fn set_user_disabled(pool: &PgPool, uid: Uuid, value: bool) -> Result<usize> {
+    pool.transaction(move |conn| {
+        use schema::users::dsl::*;
+        if value {
+            use schema::sessions::dsl::{owner, sessions};
+            diesel::delete(sessions.filter(owner.eq(uid))).execute(conn)?;
+        }
+        diesel::update(users.filter(id.eq(uid)))
+            .set(disabled.eq(value))
+            .execute(conn)
+    })
+    .await
+    .map_err(Error::AsyncDiesel)
+}
+
+/// Stores a new session, dropping the expired ones.
+pub async fn create_session(pool: &PgPool, s: &Session) -> Result<usize> {
+    use schema::sessions::dsl::*;
+    diesel::delete(sessions.filter(expires_at.lt(now)))
+        .execute_async(pool)
+        .await?;
+    diesel::insert_into(sessions)
+        .values(s)
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// The user of session `sid`, `None` once it expired or was ended.
+pub async fn get_session_owner(pool: &PgPool, sid: Uuid) -> Result<Option<Uuid>> {
+    use schema::sessions::dsl::*;
+    let mut found: Vec<Uuid> = sessions
+        .filter(id.eq(sid))
+        .filter(expires_at.gt(now))
+        .select(owner)
+        .load_async(pool)
+        .await?;
+    Ok(found.pop())
+}
+
+pub async fn remove_session(pool: &PgPool, sid: Uuid) -> Result<usize> {
+    use schema::sessions::dsl::*;
+    diesel::delete(sessions.filter(id.eq(sid)))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Deletes a user along with everything they own, lists they created go
+/// away for their other members too.
+pub async fn remove_user(pool: &PgPool, uid: Uuid) -> Result<usize> {
+    use schema::users::dsl::*;
+    diesel::delete(users.filter(id.eq(uid)))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// The local user of a provider identity.
+pub async fn get_user_by_identity(pool: &PgPool, iss: String, sub: String) -> Result<Option<User>> {
+    use schema::{oidc_identities, users};
+    let mut found: Vec<User> = users::table
+        .inner_join(oidc_identities::table)
+        .filter(oidc_identities::issuer.eq(iss))
+        .filter(oidc_identities::subject.eq(sub))
+        .select((
+            users::id,
+            users::username,
+            users::password_hash,
+            users::is_admin,
+            users::disabled,
+        ))
+        .load_async(pool)
+        .await?;
+    Ok(found.pop())
+}
+
+/// Creates `u` as the local user of a provider identity.
+pub async fn create_user_with_identity(
+    pool: &PgPool,
+    u: &User,
+    iss: String,
+    sub: String,
+) -> Result<usize> {
+    let user = u.clone();
+    let identity = OidcIdentity::new(iss, sub, *u.id());
+    pool.transaction(move |conn| {
+        diesel::insert_into(schema::users::table)
+            .values(&user)
+            .execute(conn)?;
+        diesel::insert_into(schema::oidc_identities::table)
+            .values(&identity)
+            .execute(conn)
+    })
+    .await
+    .map_err(Error::AsyncDiesel)
+}
+
+pub async fn create_api_token(pool: &PgPool, t: &ApiToken) -> Result<usize> {
+    diesel::insert_into(schema::api_token::table)
+        .values(t)
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn get_api_tokens(pool: &PgPool, uid: Uuid) -> Result<Vec<ApiToken>> {
+    use schema::api_token::dsl::*;
+    api_token
+        .filter(owner.eq(uid))
+        .select((id, owner, name, token_hash, read_only, expires_at))
+        .order(created_at)
+        .load_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Looks up an unexpired token by the hash of its secret.
+pub async fn get_api_token_by_hash(pool: &PgPool, hash: String) -> Result<Option<ApiToken>> {
+    use schema::api_token::dsl::*;
+    let mut found: Vec<ApiToken> = api_token
+        .filter(token_hash.eq(hash))
+        .filter(expires_at.is_null().or(expires_at.gt(now)))
+        .select((id, owner, name, token_hash, read_only, expires_at))
+        .load_async(pool)
+        .await?;
+    Ok(found.pop())
+}
+
+pub async fn remove_api_token(pool: &PgPool, uid: Uuid, tid: Uuid) -> Result<usize> {
+    use schema::api_token::dsl::*;
+    diesel::delete(api_token)
+        .filter(id.eq(tid))
+        .filter(owner.eq(uid))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn get_tags(pool: &PgPool, uid: Uuid) -> Result<Vec<TagRow>> {
+    use schema::tag::dsl::*;
+    tag.filter(owner.eq(uid))
+        .select((id, owner, name))
+        .order(name)
+        .load_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// A tag of `uid`, by id or by name.
+pub async fn find_tag(
+    pool: &PgPool,
+    uid: Uuid,
+    tid: Option<Uuid>,
+    tag_name: Option<String>,
+) -> Result<Option<TagRow>> {
+    Ok(get_tags(pool, uid)
+        .await?
+        .into_iter()
+        .find(|t| Some(*t.id()) == tid || Some(t.name()) == tag_name.as_ref()))
+}
+
+pub async fn create_tag(pool: &PgPool, t: &TagRow) -> Result<usize> {
+    diesel::insert_into(schema::tag::table)
+        .values(t)
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn rename_tag(pool: &PgPool, uid: Uuid, tid: Uuid, new_name: String) -> Result<usize> {
+    use schema::tag::dsl::*;
+    diesel::update(tag)
+        .filter(id.eq(tid))
+        .filter(owner.eq(uid))
+        .set(name.eq(new_name))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Removes a tag of `uid` from every task it is on.
+pub async fn remove_tag(pool: &PgPool, uid: Uuid, tid: Uuid) -> Result<usize> {
+    use schema::tag::dsl::*;
+    diesel::delete(tag)
+        .filter(id.eq(tid))
+        .filter(owner.eq(uid))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Puts a tag on a task, doing it twice changes nothing.
+pub async fn tag_task(pool: &PgPool, tid: Uuid, tag: Uuid) -> Result<usize> {
+    diesel::insert_into(schema::task_tag::table)
+        .values(TaskTag::new(tid, tag))
+        .on_conflict_do_nothing()
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn untag_task(pool: &PgPool, tid: Uuid, tag: Uuid) -> Result<usize> {
+    use schema::task_tag::dsl::*;
+    diesel::delete(task_tag)
+        .filter(task_id.eq(tid))
+        .filter(tag_id.eq(tag))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Makes `tid` wait for `on`, both in `list`. `false` if `on` already waits
+/// for `tid`, which would make a cycle.
+pub async fn add_dependency(pool: &PgPool, list: Uuid, tid: Uuid, on: Uuid) -> Result<bool> {
+    pool.transaction(move |conn| {
+        // dependencies stay within a list, holding its row keeps concurrent
+        // requests from both passing the check below
+        {
+            use schema::lists::dsl::*;
+            lists
+                .filter(id.eq(list))
+                .select(id)
+                .for_update()
+                .load::<Uuid>(conn)?;
+        }
+        if depends_transitively_in(conn, on, tid)? {
+            return Ok(false);
+        }
+        diesel::insert_into(schema::task_dependency::table)
+            .values(TaskDependency::new(tid, on))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(true)
+    })
+    .await
+    .map_err(Error::AsyncDiesel)
+}
+
+pub async fn remove_dependency(pool: &PgPool, tid: Uuid, on: Uuid) -> Result<usize> {
+    use schema::task_dependency::dsl::*;
+    diesel::delete(task_dependency)
+        .filter(task_id.eq(tid))
+        .filter(depends_on.eq(on))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Whether `tid` waits for `on`, directly or through other tasks.
+fn depends_transitively_in(conn: &PgConnection, tid: Uuid, on: Uuid) -> QueryResult<bool> {
+    use schema::task_dependency::dsl::*;
+    let mut seen = vec![tid];
+    let mut current = vec![tid];
+    while !current.is_empty() {
+        let next: Vec<Uuid> = task_dependency
+            .filter(task_id.eq_any(current))
+            .select(depends_on)
+            .load(conn)?;
+        if next.contains(&on) {
+            return Ok(true);
+        }
+        current = next.into_iter().filter(|t| !seen.contains(t)).collect();
+        current.sort_unstable();
+        current.dedup();
+        seen.extend(current.iter().copied());
+    }
+    Ok(false)
+}
+
+pub async fn create_attachment(pool: &PgPool, a: &AttachmentRow) -> Result<usize> {
+    diesel::insert_into(schema::attachments::table)
+        .values(a)
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Attachments of a task, oldest first.
+pub async fn get_attachments(pool: &PgPool, tid: Uuid) -> Result<Vec<AttachmentRow>> {
+    use schema::attachments::dsl::*;
+    attachments
+        .filter(task_id.eq(tid))
+        .select((id, task_id, name, content_type, size, sha256, uploaded_by))
+        .order(created_at)
+        .load_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+pub async fn get_attachment(pool: &PgPool, aid: Uuid) -> Result<Option<AttachmentRow>> {
+    use schema::attachments::dsl::*;
+    let mut found: Vec<AttachmentRow> = attachments
+        .filter(id.eq(aid))
+        .select((id, task_id, name, content_type, size, sha256, uploaded_by))
+        .load_async(pool)
+        .await?;
+    Ok(found.pop())
+}
+
+pub async fn remove_attachment(pool: &PgPool, aid: Uuid) -> Result<usize> {
+    use schema::attachments::dsl::*;
+    diesel::delete(attachments.filter(id.eq(aid)))
+        .execute_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Whether any attachment still has the content stored under `hash`.
+pub async fn is_content_used(pool: &PgPool, hash: String) -> Result<bool> {
+    use schema::attachments::dsl::*;
+    let found: Vec<Uuid> = attachments
+        .filter(sha256.eq(hash))
+        .select(id)
+        .limit(1)
+        .load_async(pool)
+        .await?;
+    Ok(!found.is_empty())
+}
+
+/// Every stored content hash, to find files nothing refers to.
+pub async fn get_content_hashes(pool: &PgPool) -> Result<Vec<String>> {
+    use schema::attachments::dsl::*;
+    attachments
+        .select(sha256)
+        .distinct()
+        .load_async(pool)
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Content hashes of the attachments of `roots` and every task below them,
+/// the contents that may go unused when those tasks are deleted.
+fn content_hashes_below_in(conn: &PgConnection, roots: Vec<Uuid>) -> QueryResult<Vec<String>> {
+    let mut tasks = roots.clone();
+    let mut parents = roots;
+    while !parents.is_empty() {
+        use schema::task::dsl::*;
+        parents = task
+            .filter(parent_id.eq_any(parents))
+            .select(id)
+            .load(conn)?;
+        tasks.extend(&parents);
+    }
+    use schema::attachments::dsl::*;
+    attachments
+        .filter(task_id.eq_any(tasks))
+        .select(sha256)
+        .distinct()
+        .load(conn)
+}
+
+/// Content hashes of a task and its subtasks, see `Attachments::forget`.
+pub async fn get_task_content_hashes(pool: &PgPool, tid: Uuid) -> Result<Vec<String>> {
+    pool.run(move |conn| content_hashes_below_in(conn, vec![tid]))
+        .await
+        .map_err(Error::AsyncDiesel)
+}
+
+/// Content hashes of the tasks of a list.
+pub async fn get_list_content_hashes(pool: &PgPool, lid: Uuid) -> Result<Vec<String>> {
+    pool.run(move |conn| {
+        use schema::task::dsl::*;
+        let roots = task.filter(list_id.eq(lid)).select(id).load(conn)?;
+        content_hashes_below_in(conn, roots)
+    })
+    .await
+    .map_err(Error::AsyncDiesel)
+}
+
+/// Content hashes of the tasks deleting a user deletes: those they own and
+/// those in their lists, with the subtasks below.
+pub async fn get_user_content_hashes(pool: &PgPool, uid: Uuid) -> Result<Vec<String>> {
+    pool.run(move |conn| {
+        use schema::{lists, task};
+        let owned = lists::table.filter(lists::owner.eq(uid)).select(lists::id);
+        let roots = task::table
+            .filter(task::owner.eq(uid).or(task::list_id.eq_any(owned)))
+            .select(task::id)
+            .load(conn)?;
+        content_hashes_below_in(conn, roots)
+    })
+    .await
+    .map_err(Error::AsyncDiesel)
+}
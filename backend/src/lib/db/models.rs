@@ -1,6 +1,13 @@
-use super::schema::task;
+use super::schema::{
+    api_token, attachments, idempotency_key, list_members, lists, oidc_identities, sessions,
+    share_links, tag, task, task_dependency, task_tag, users,
+};
 use getset::*;
-use todomvc_shared::Entry;
+use std::time::{Duration, SystemTime};
+use todomvc_shared::{
+    Attachment, Entry, Period, Priority, Recurrence, Role, SearchHit, Stats, Tag, TaskList,
+    TokenInfo, UserInfo, UserStats,
+};
 use uuid::Uuid;
 
 #[derive(Debug, Insertable, Queryable, Identifiable, AsChangeset, Getters, Setters, Clone)]
@@ -10,17 +17,62 @@ pub struct Task {
     id: Uuid,
     content: String,
     completed: bool,
-    editing: bool,
+    owner: Uuid,
+    list_id: Uuid,
+    due_at: Option<SystemTime>,
+    /// `Priority::level` of the task.
+    priority: i16,
+    /// The task this one is a subtask of.
+    parent_id: Option<Uuid>,
+    /// Whether the task completes itself once all its subtasks are done.
+    auto_complete: bool,
+    /// `Recurrence::to_rule` of the task.
+    recurrence: Option<String>,
+    notes: String,
+    created_at: SystemTime,
+    /// When the task was last completed, `None` while it is open.
+    completed_at: Option<SystemTime>,
 }
 
 impl Task {
-    pub fn new(content: String) -> Self {
+    pub fn new(owner: Uuid, list_id: Uuid, content: String) -> Self {
         let id = Uuid::new_v4();
         Self {
             id,
             content,
             completed: false,
-            editing: false,
+            owner,
+            list_id,
+            due_at: None,
+            priority: Priority::None.level(),
+            parent_id: None,
+            auto_complete: false,
+            recurrence: None,
+            notes: String::new(),
+            created_at: SystemTime::now(),
+            completed_at: None,
+        }
+    }
+    pub fn from_entry(owner: Uuid, list_id: Uuid, e: &Entry) -> Self {
+        Self {
+            id: *e.id(),
+            content: e.content().to_string(),
+            completed: *e.completed(),
+            owner,
+            list_id,
+            due_at: *e.due_at(),
+            priority: e.priority().level(),
+            // parents are only set through routes that check them
+            parent_id: None,
+            auto_complete: *e.auto_complete(),
+            recurrence: e.recurrence().map(|r| r.to_rule()),
+            notes: e.notes().clone(),
+            created_at: SystemTime::now(),
+            completed_at: if *e.completed() {
+                Some(SystemTime::now())
+            } else {
+                None
+            },
         }
     }
     pub fn to_entry(&self) -> Entry {
@@ -28,28 +80,433 @@ impl Task {
         e.set_id(*self.id());
         e.set_content(self.content().clone());
         e.set_completed(*self.completed());
-        e.set_editing(*self.editing());
+        e.set_due_at(*self.due_at());
+        e.set_priority(Priority::from_level(*self.priority()));
+        e.set_parent_id(*self.parent_id());
+        e.set_auto_complete(*self.auto_complete());
+        e.set_recurrence(self.recurrence().as_deref().and_then(Recurrence::parse));
+        e.set_notes(self.notes().clone());
         e
     }
 }
 
-impl From<Entry> for Task {
-    fn from(e: Entry) -> Self {
+/// A label users put on tasks, names are unique per user.
+#[derive(Debug, Insertable, Queryable, Getters, Setters, Clone)]
+#[table_name = "tag"]
+#[getset(get = "pub", set = "pub")]
+pub struct TagRow {
+    id: Uuid,
+    owner: Uuid,
+    name: String,
+}
+
+impl TagRow {
+    pub fn new(owner: Uuid, name: String) -> Self {
+        let id = Uuid::new_v4();
+        Self { id, owner, name }
+    }
+    pub fn to_tag(&self) -> Tag {
+        Tag::new(*self.id(), self.name().clone())
+    }
+}
+
+#[derive(Debug, Insertable, Clone)]
+#[table_name = "task_tag"]
+pub struct TaskTag {
+    task_id: Uuid,
+    tag_id: Uuid,
+}
+
+impl TaskTag {
+    pub fn new(task_id: Uuid, tag_id: Uuid) -> Self {
+        Self { task_id, tag_id }
+    }
+}
+
+#[derive(Debug, Insertable, Clone)]
+#[table_name = "task_dependency"]
+pub struct TaskDependency {
+    task_id: Uuid,
+    depends_on: Uuid,
+}
+
+impl TaskDependency {
+    pub fn new(task_id: Uuid, depends_on: Uuid) -> Self {
         Self {
-            id: *e.id(),
-            content: e.content().to_string(),
-            completed: *e.completed(),
-            editing: *e.editing(),
+            task_id,
+            depends_on,
         }
     }
 }
-impl From<&Entry> for Task {
-    fn from(e: &Entry) -> Self {
+
+/// A file on a task, its content is stored under `sha256` and shared by
+/// every attachment with the same content.
+#[derive(Debug, Insertable, Queryable, Getters, Clone)]
+#[table_name = "attachments"]
+#[getset(get = "pub")]
+pub struct AttachmentRow {
+    id: Uuid,
+    task_id: Uuid,
+    name: String,
+    content_type: String,
+    size: i64,
+    sha256: String,
+    uploaded_by: Option<Uuid>,
+}
+
+impl AttachmentRow {
+    pub fn new(
+        task_id: Uuid,
+        name: String,
+        content_type: String,
+        size: i64,
+        sha256: String,
+        uploaded_by: Uuid,
+    ) -> Self {
+        let id = Uuid::new_v4();
         Self {
-            id: *e.id(),
-            content: e.content().to_string(),
-            completed: *e.completed(),
-            editing: *e.editing(),
+            id,
+            task_id,
+            name,
+            content_type,
+            size,
+            sha256,
+            uploaded_by: Some(uploaded_by),
+        }
+    }
+    pub fn to_attachment(&self) -> Attachment {
+        Attachment::new(
+            self.id,
+            self.name.clone(),
+            self.content_type.clone(),
+            self.size as u64,
+        )
+    }
+}
+
+#[derive(Debug, Insertable, Queryable, Getters, Setters, Clone)]
+#[table_name = "lists"]
+#[getset(get = "pub", set = "pub")]
+pub struct List {
+    id: Uuid,
+    owner: Uuid,
+    name: String,
+}
+
+impl List {
+    pub fn new(owner: Uuid, name: String) -> Self {
+        let id = Uuid::new_v4();
+        Self { id, owner, name }
+    }
+    pub fn to_task_list(&self, role: Role) -> TaskList {
+        TaskList::new(*self.id(), self.name().clone(), role)
+    }
+}
+
+#[derive(Debug, Insertable, Queryable, Clone)]
+#[table_name = "list_members"]
+pub struct ListMember {
+    list_id: Uuid,
+    user_id: Uuid,
+    role: String,
+}
+
+impl ListMember {
+    pub fn new(list_id: Uuid, user_id: Uuid, role: Role) -> Self {
+        Self {
+            list_id,
+            user_id,
+            role: role.as_str().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Insertable, Queryable, Getters, Clone)]
+#[table_name = "idempotency_key"]
+#[getset(get = "pub")]
+pub struct IdempotentResponse {
+    owner: Uuid,
+    key: String,
+    request: String,
+    /// `None` while the request is still running.
+    status: Option<i32>,
+    response: Option<Vec<u8>>,
+}
+
+impl IdempotentResponse {
+    /// Reserves `key` for a request that is about to run.
+    pub fn new(owner: Uuid, key: String, request: String) -> Self {
+        Self {
+            owner,
+            key,
+            request,
+            status: None,
+            response: None,
+        }
+    }
+
+    pub fn finish(&mut self, status: i32, response: Vec<u8>) {
+        self.status = Some(status);
+        self.response = Some(response);
+    }
+}
+
+#[derive(Debug, Insertable, Queryable, Getters, Clone)]
+#[table_name = "users"]
+#[getset(get = "pub")]
+pub struct User {
+    id: Uuid,
+    username: String,
+    password_hash: String,
+    is_admin: bool,
+    disabled: bool,
+}
+
+impl User {
+    pub fn new(username: String, password_hash: String) -> Self {
+        let id = Uuid::new_v4();
+        Self {
+            id,
+            username,
+            password_hash,
+            is_admin: false,
+            disabled: false,
+        }
+    }
+    pub fn to_user_info(&self) -> UserInfo {
+        UserInfo::new(*self.id(), self.username().clone(), self.is_admin)
+    }
+}
+
+/// A user with what they own, computed by `get_user_stats`.
+#[derive(Debug, QueryableByName)]
+pub struct UserStatsRow {
+    #[sql_type = "diesel::sql_types::Uuid"]
+    id: Uuid,
+    #[sql_type = "diesel::sql_types::Text"]
+    username: String,
+    #[sql_type = "diesel::sql_types::Bool"]
+    is_admin: bool,
+    #[sql_type = "diesel::sql_types::Bool"]
+    disabled: bool,
+    #[sql_type = "diesel::sql_types::Timestamp"]
+    created_at: SystemTime,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    tasks: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    lists: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    storage_bytes: i64,
+}
+
+impl UserStatsRow {
+    pub fn to_user_stats(&self) -> UserStats {
+        UserStats::new(
+            self.id,
+            self.username.clone(),
+            self.is_admin,
+            self.disabled,
+            self.created_at,
+            self.tasks,
+            self.lists,
+            self.storage_bytes,
+        )
+    }
+}
+
+/// Tasks created and completed in a period, computed by `get_stats`.
+#[derive(Debug, QueryableByName)]
+pub struct PeriodRow {
+    #[sql_type = "diesel::sql_types::Timestamp"]
+    start: SystemTime,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    created: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    completed: i64,
+}
+
+impl PeriodRow {
+    pub fn to_period(&self) -> Period {
+        Period::new(self.start, self.created, self.completed)
+    }
+}
+
+/// Counts of the tasks as they are now, computed by `get_stats`.
+#[derive(Debug, QueryableByName)]
+pub struct StatsRow {
+    #[sql_type = "diesel::sql_types::BigInt"]
+    open: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    overdue: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    blocked: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    completed: i64,
+    /// Mean seconds from creation to completion.
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Double>"]
+    average_secs: Option<f64>,
+}
+
+impl StatsRow {
+    pub fn to_stats(&self, days: Vec<Period>, weeks: Vec<Period>) -> Stats {
+        Stats::new(
+            days,
+            weeks,
+            // a clock set back can make it negative
+            self.average_secs
+                .map(|s| Duration::from_secs_f64(s.max(0.0))),
+            self.open,
+            self.overdue,
+            self.blocked,
+            self.completed,
+        )
+    }
+}
+
+/// Put around the matching words of a search snippet, control characters
+/// nobody types into a task.
+pub const HIGHLIGHT_START: char = '\u{2}';
+pub const HIGHLIGHT_STOP: char = '\u{3}';
+
+/// A task matching a search, computed by `search_tasks`.
+#[derive(Debug, QueryableByName)]
+pub struct SearchRow {
+    #[sql_type = "diesel::sql_types::Uuid"]
+    id: Uuid,
+    #[sql_type = "diesel::sql_types::Uuid"]
+    list_id: Uuid,
+    #[sql_type = "diesel::sql_types::Text"]
+    content: String,
+    #[sql_type = "diesel::sql_types::Float4"]
+    rank: f32,
+    /// Matches are between `HIGHLIGHT_START` and `HIGHLIGHT_STOP`.
+    #[sql_type = "diesel::sql_types::Text"]
+    snippet: String,
+}
+
+impl SearchRow {
+    pub fn to_search_hit(&self) -> SearchHit {
+        let mut pieces = Vec::new();
+        for (i, part) in self.snippet.split(HIGHLIGHT_START).enumerate() {
+            let (matched, rest) = match part.split_once(HIGHLIGHT_STOP) {
+                // the text before the first match has no stop marker
+                Some((matched, rest)) if i > 0 => (matched, rest),
+                _ => ("", part),
+            };
+            if !matched.is_empty() {
+                pieces.push((matched.to_string(), true));
+            }
+            if !rest.is_empty() {
+                pieces.push((rest.to_string(), false));
+            }
+        }
+        SearchHit::new(
+            self.id,
+            self.list_id,
+            self.content.clone(),
+            self.rank,
+            pieces,
+        )
+    }
+}
+
+#[derive(Debug, Insertable, Queryable, Getters, Clone)]
+#[table_name = "api_token"]
+#[getset(get = "pub")]
+pub struct ApiToken {
+    id: Uuid,
+    owner: Uuid,
+    name: String,
+    token_hash: String,
+    read_only: bool,
+    expires_at: Option<SystemTime>,
+}
+
+impl ApiToken {
+    pub fn new(
+        owner: Uuid,
+        name: String,
+        token_hash: String,
+        read_only: bool,
+        expires_at: Option<SystemTime>,
+    ) -> Self {
+        let id = Uuid::new_v4();
+        Self {
+            id,
+            owner,
+            name,
+            token_hash,
+            read_only,
+            expires_at,
+        }
+    }
+    pub fn to_token_info(&self) -> TokenInfo {
+        TokenInfo::new(
+            *self.id(),
+            self.name().clone(),
+            *self.read_only(),
+            *self.expires_at(),
+        )
+    }
+}
+
+#[derive(Debug, Insertable, Queryable, Getters, Clone)]
+#[table_name = "share_links"]
+#[getset(get = "pub")]
+pub struct ShareLinkRow {
+    id: Uuid,
+    list_id: Uuid,
+    created_by: Uuid,
+    expires_at: Option<SystemTime>,
+}
+
+impl ShareLinkRow {
+    pub fn new(list_id: Uuid, created_by: Uuid, expires_at: Option<SystemTime>) -> Self {
+        let id = Uuid::new_v4();
+        Self {
+            id,
+            list_id,
+            created_by,
+            expires_at,
+        }
+    }
+}
+
+/// A browser session, its id is kept in the session cookie.
+#[derive(Debug, Insertable, Clone, Getters)]
+#[table_name = "sessions"]
+#[getset(get = "pub")]
+pub struct Session {
+    id: Uuid,
+    owner: Uuid,
+    expires_at: SystemTime,
+}
+
+impl Session {
+    pub fn new(owner: Uuid, lifetime: Duration) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            owner,
+            expires_at: SystemTime::now() + lifetime,
+        }
+    }
+}
+
+/// Links the subject of an OpenID Connect provider to a local user.
+#[derive(Debug, Insertable, Clone)]
+#[table_name = "oidc_identities"]
+pub struct OidcIdentity {
+    issuer: String,
+    subject: String,
+    user_id: Uuid,
+}
+
+impl OidcIdentity {
+    pub fn new(issuer: String, subject: String, user_id: Uuid) -> Self {
+        Self {
+            issuer,
+            subject,
+            user_id,
         }
     }
 }
@@ -1,8 +1,165 @@
+table! {
+    attachments (id) {
+        id -> Uuid,
+        task_id -> Uuid,
+        name -> Text,
+        content_type -> Text,
+        size -> Int8,
+        sha256 -> Text,
+        uploaded_by -> Nullable<Uuid>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    api_token (id) {
+        id -> Uuid,
+        owner -> Uuid,
+        name -> Text,
+        token_hash -> Text,
+        read_only -> Bool,
+        expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    idempotency_key (owner, key) {
+        owner -> Uuid,
+        key -> Text,
+        request -> Text,
+        status -> Nullable<Int4>,
+        response -> Nullable<Bytea>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    list_members (list_id, user_id) {
+        list_id -> Uuid,
+        user_id -> Uuid,
+        role -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    lists (id) {
+        id -> Uuid,
+        owner -> Uuid,
+        name -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    oidc_identities (issuer, subject) {
+        issuer -> Text,
+        subject -> Text,
+        user_id -> Uuid,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Uuid,
+        owner -> Uuid,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    share_links (id) {
+        id -> Uuid,
+        list_id -> Uuid,
+        created_by -> Uuid,
+        expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    tag (id) {
+        id -> Uuid,
+        owner -> Uuid,
+        name -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     task (id) {
         id -> Uuid,
         content -> Text,
         completed -> Bool,
-        editing -> Bool,
+        owner -> Uuid,
+        list_id -> Uuid,
+        due_at -> Nullable<Timestamp>,
+        priority -> Int2,
+        parent_id -> Nullable<Uuid>,
+        auto_complete -> Bool,
+        recurrence -> Nullable<Text>,
+        notes -> Text,
+        created_at -> Timestamp,
+        completed_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    task_dependency (task_id, depends_on) {
+        task_id -> Uuid,
+        depends_on -> Uuid,
+    }
+}
+
+table! {
+    task_tag (task_id, tag_id) {
+        task_id -> Uuid,
+        tag_id -> Uuid,
+    }
+}
+
+table! {
+    users (id) {
+        id -> Uuid,
+        username -> Text,
+        password_hash -> Text,
+        created_at -> Timestamp,
+        is_admin -> Bool,
+        disabled -> Bool,
     }
 }
+
+joinable!(api_token -> users (owner));
+joinable!(attachments -> task (task_id));
+joinable!(attachments -> users (uploaded_by));
+joinable!(idempotency_key -> users (owner));
+joinable!(list_members -> lists (list_id));
+joinable!(list_members -> users (user_id));
+joinable!(lists -> users (owner));
+joinable!(oidc_identities -> users (user_id));
+joinable!(sessions -> users (owner));
+joinable!(share_links -> lists (list_id));
+joinable!(share_links -> users (created_by));
+joinable!(tag -> users (owner));
+joinable!(task -> lists (list_id));
+joinable!(task -> users (owner));
+joinable!(task_tag -> tag (tag_id));
+joinable!(task_tag -> task (task_id));
+
+allow_tables_to_appear_in_same_query!(
+    api_token,
+    attachments,
+    idempotency_key,
+    list_members,
+    lists,
+    oidc_identities,
+    sessions,
+    tag,
+    task,
+    task_dependency,
+    task_tag,
+    users,
+);
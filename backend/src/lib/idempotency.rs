@@ -0,0 +1,168 @@
+use super::{
+    auth::AuthUser,
+    db::{self, IdempotentResponse},
+    PgPool,
+};
+use rocket::{
+    error,
+    http::Status,
+    outcome::{try_outcome, Outcome},
+    request::{self, FromRequest, Request},
+    State,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{future::Future, sync::Arc};
+use uuid::Uuid;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Request guard for the `Idempotency-Key` header.
+///
+/// A key is bound to the method and uri of the request that first used it,
+/// replaying it against another request is rejected with `422`. Keys are
+/// scoped to the logged in user, so the guard also requires a session.
+/// A request is reserved its key while it runs, a retry arriving meanwhile is
+/// rejected with `409`.
+pub struct Idempotency {
+    owner: Uuid,
+    key: Option<String>,
+    request: String,
+    stored: Option<IdempotentResponse>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Idempotency {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let owner = *try_outcome!(req.guard::<AuthUser>().await).id();
+        let request = format!("{} {}", req.method(), req.uri());
+        let key = match req.headers().get_one(IDEMPOTENCY_KEY_HEADER) {
+            Some(k) if !k.trim().is_empty() => k.trim().to_string(),
+            _ => {
+                return Outcome::Success(Self {
+                    owner,
+                    key: None,
+                    request,
+                    stored: None,
+                })
+            }
+        };
+        let pool = try_outcome!(req
+            .guard::<&State<Arc<PgPool>>>()
+            .await
+            .map_failure(|(s, _)| (s, "Database pool is not managed.".to_string())));
+        match db::get_idempotent_response(pool.as_ref(), owner, key.clone()).await {
+            Ok(Some(r)) if *r.request() != request => Outcome::Failure((
+                Status::UnprocessableEntity,
+                format!(
+                    "{} was already used for: {}",
+                    IDEMPOTENCY_KEY_HEADER,
+                    r.request()
+                ),
+            )),
+            Ok(stored) => Outcome::Success(Self {
+                owner,
+                key: Some(key),
+                request,
+                stored,
+            }),
+            Err(e) => Outcome::Failure((Status::InternalServerError, e.to_string())),
+        }
+    }
+}
+
+/// Answers a route can give when a request is refused before it runs.
+pub trait Refusal {
+    fn refused(reason: String) -> Self;
+}
+
+impl Refusal for String {
+    fn refused(reason: String) -> Self {
+        reason
+    }
+}
+
+impl<T> Refusal for Result<T, String> {
+    fn refused(reason: String) -> Self {
+        Err(reason)
+    }
+}
+
+impl Idempotency {
+    /// Runs `f` once per key: a retried request gets the stored result back
+    /// instead of running `f` again. Only successful results are stored, so
+    /// failed requests can still be retried with the same key.
+    pub async fn once<T, F, Fut>(self, pool: &PgPool, f: F) -> (Status, T)
+    where
+        T: Serialize + DeserializeOwned + Refusal,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = (Status, T)>,
+    {
+        if let Some(r) = self.stored {
+            return replay(&r);
+        }
+        let key = match self.key {
+            Some(key) => key,
+            None => return f().await,
+        };
+        let mut reserved = IdempotentResponse::new(self.owner, key.clone(), self.request);
+        match db::reserve_idempotency_key(pool, &reserved).await {
+            Ok(true) => (),
+            // taken since the guard looked
+            Ok(false) => {
+                return match db::get_idempotent_response(pool, self.owner, key).await {
+                    Ok(Some(r)) if r.request() != reserved.request() => (
+                        Status::UnprocessableEntity,
+                        T::refused(format!(
+                            "{} was already used for: {}",
+                            IDEMPOTENCY_KEY_HEADER,
+                            r.request()
+                        )),
+                    ),
+                    Ok(Some(r)) => replay(&r),
+                    Ok(None) => (
+                        Status::Conflict,
+                        T::refused("Retry the request.".to_string()),
+                    ),
+                    Err(e) => (Status::InternalServerError, T::refused(e.to_string())),
+                }
+            }
+            Err(e) => return (Status::InternalServerError, T::refused(e.to_string())),
+        }
+        let (status, value) = f().await;
+        let stored = match bincode::serialize(&value) {
+            Ok(response) if status.class().is_success() => {
+                reserved.finish(status.code as i32, response);
+                // a failed store keeps the key reserved, so that retries
+                // are refused rather than run twice
+                db::save_idempotent_response(pool, &reserved).await
+            }
+            _ => db::release_idempotency_key(pool, self.owner, key).await,
+        };
+        if let Err(e) = stored {
+            error!("Failed to store idempotency key: {}", e);
+        }
+        (status, value)
+    }
+}
+
+/// The stored answer to a request, `409` while it is still running.
+fn replay<T: DeserializeOwned + Refusal>(r: &IdempotentResponse) -> (Status, T) {
+    let status = r.status().and_then(|s| Status::from_code(s as u16));
+    let value = r.response().as_ref().map(|v| bincode::deserialize(v));
+    match (status, value) {
+        (Some(status), Some(Ok(value))) => (status, value),
+        (_, None) => (
+            Status::Conflict,
+            T::refused(format!(
+                "A request with this {} is still running.",
+                IDEMPOTENCY_KEY_HEADER
+            )),
+        ),
+        _ => (
+            Status::InternalServerError,
+            T::refused("The stored response cannot be read.".to_string()),
+        ),
+    }
+}
@@ -1,17 +1,55 @@
+pub mod attachments;
+pub mod auth;
+pub mod csrf;
 pub mod db;
+pub mod idempotency;
+pub mod oidc;
+pub mod presence;
+pub mod rate_limit;
+pub mod share;
+#[cfg(test)]
+mod tests;
 
+use attachments::{AttachmentFairing, Attachments, Download, Upload};
+use auth::{AdminUser, AuthUser, WriteUser};
+use csrf::{Csrf, CSRF_COOKIE};
+use db::BulkUpdate;
 use diesel::{
     r2d2::{ConnectionManager, Pool},
+    result::{DatabaseErrorKind, Error as DieselError},
     PgConnection,
 };
+use idempotency::Idempotency;
+use oidc::{LoginAttempt, Oidc, LOGIN_COOKIE};
+use presence::Presence;
+use rate_limit::{AuthLimit, RateLimitFairing, ReadLimit, WriteLimit};
 use rocket::{
-    delete, fs::NamedFile, get, http::Status, post, put, response::Redirect, routes,
-    serde::msgpack::MsgPack, uri, Either, Route, State,
+    delete, error,
+    figment::Provider,
+    form::Form,
+    fs::NamedFile,
+    get,
+    http::{Cookie, CookieJar, Status},
+    post, put,
+    response::Redirect,
+    routes,
+    serde::msgpack::MsgPack,
+    uri, Build, Either, Rocket, Route, State,
 };
+use share::ShareKey;
 use std::{io, path::PathBuf};
-use std::{path::Path, sync::Arc};
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 use thiserror::Error as TError;
-use todomvc_shared::{Entries, Entry, TaskRequest, UpdateAll};
+use todomvc_shared::{
+    Attachment, Attachments as AttachmentList, Credentials, Editing, Entries, Entry, InviteRequest,
+    ListRequest, Lists, LoginOptions, Member, Members, NewToken, Priority, Role, SearchHits,
+    ShareLink, ShareLinks, ShareRequest, SharedList, Stats, Tag, TagRequest, Tags, TaskList,
+    TaskRequest, TokenRequest, Tokens, UpdateAll, UserInfo, Users, MAX_SUBTASK_DEPTH,
+};
 use uuid::Uuid;
 
 pub type PgPool = Pool<ConnectionManager<PgConnection>>;
@@ -27,10 +65,41 @@ pub enum Error {
     AsyncDiesel(#[from] tokio_diesel::AsyncError),
     #[error("Rocket error: {0}")]
     Rocket(#[from] rocket::Error),
+    #[error("OpenID Connect error: {0}")]
+    Oidc(String),
     #[error("Error: {0}")]
     General(String),
 }
 
+impl Error {
+    /// Whether a write failed because a row with the same key exists.
+    pub fn is_unique_violation(&self) -> bool {
+        matches!(
+            self,
+            Error::AsyncDiesel(tokio_diesel::AsyncError::Error(DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                _
+            )))
+        )
+    }
+}
+
+pub fn rocket(pool: PgPool) -> Rocket<Build> {
+    custom(rocket::Config::figment(), pool)
+}
+
+/// `rocket` with the configuration from `provider` instead of `Rocket.toml`.
+pub fn custom<T: Provider>(provider: T, pool: PgPool) -> Rocket<Build> {
+    rocket::custom(provider)
+        .mount("/", all_routes())
+        .manage(Arc::new(pool))
+        .manage(Presence::default())
+        .attach(ShareKey::fairing())
+        .attach(RateLimitFairing)
+        .attach(Oidc::fairing())
+        .attach(AttachmentFairing)
+}
+
 pub fn all_routes() -> Vec<Route> {
     routes![
         index,
@@ -39,8 +108,52 @@ pub fn all_routes() -> Vec<Route> {
         get_task,
         update_task,
         get_tasks,
+        search_tasks,
+        get_stats,
         update_all_tasks,
-        delete_task
+        delete_task,
+        move_task,
+        get_subtasks,
+        set_parent,
+        get_tags,
+        create_tag,
+        rename_tag,
+        delete_tag,
+        tag_task,
+        untag_task,
+        add_dependency,
+        remove_dependency,
+        get_attachments,
+        upload_attachment,
+        download_attachment,
+        delete_attachment,
+        get_lists,
+        create_list,
+        rename_list,
+        delete_list,
+        get_members,
+        invite_member,
+        revoke_member,
+        get_share_links,
+        create_share_link,
+        revoke_share_link,
+        get_shared,
+        acquire_edit,
+        release_edit,
+        get_edits,
+        register,
+        login,
+        logout,
+        me,
+        login_options,
+        oidc_login,
+        oidc_callback,
+        get_tokens,
+        create_token,
+        revoke_token,
+        get_users,
+        disable_user,
+        purge_user
     ]
 }
 
@@ -54,43 +167,333 @@ async fn static_files(path: PathBuf) -> Option<NamedFile> {
     NamedFile::open(Path::new("static/").join(path)).await.ok()
 }
 
-#[post("/task", format = "application/msgpack", data = "<task_req>")]
+/// The list a route works on: `list` if `user` has at least role `min` on it,
+/// the user's oldest own list if no list was given.
+async fn resolve_list(
+    pool: &PgPool,
+    user: &AuthUser,
+    list: Option<Uuid>,
+    min: Role,
+) -> Result<Uuid, (Status, String)> {
+    let found = match list {
+        Some(list) => db::get_list(pool, *user.id(), list).await,
+        None => db::default_list(pool, *user.id()).await.map(Some),
+    };
+    match found {
+        Ok(Some((l, role))) if role >= min => Ok(*l.id()),
+        Ok(Some(_)) => Err((
+            Status::Forbidden,
+            format!("Requires {} role.", min.as_str()),
+        )),
+        Ok(None) => Err((Status::NotFound, "No such list.".to_string())),
+        Err(e) => Err((Status::InternalServerError, e.to_string())),
+    }
+}
+
+/// Fails unless `user` has at least role `min` on the list of task `id`.
+async fn check_task(
+    pool: &PgPool,
+    user: &AuthUser,
+    id: Uuid,
+    min: Role,
+) -> Result<(), (Status, String)> {
+    match db::get_task_role(pool, *user.id(), id).await {
+        Ok(Some(role)) if role >= min => Ok(()),
+        Ok(Some(_)) => Err((
+            Status::Forbidden,
+            format!("Requires {} role.", min.as_str()),
+        )),
+        Ok(None) => Err((Status::NotFound, "No such task.".to_string())),
+        Err(e) => Err((Status::InternalServerError, e.to_string())),
+    }
+}
+
+fn internal_error(e: Error) -> (Status, String) {
+    (Status::InternalServerError, e.to_string())
+}
+
+/// Removes the contents `hashes` of attachments deleted along with their
+/// tasks, the sweep at launch catches what this misses.
+async fn forget_contents(store: &Attachments, pool: &PgPool, hashes: Vec<String>) {
+    if let Err(e) = store.forget(pool, hashes).await {
+        error!("Cannot clean up attachments: {}", e);
+    }
+}
+
+/// Longest lifetime of a token or share link, in seconds.
+const MAX_EXPIRES_IN: u64 = 10 * 365 * 24 * 60 * 60;
+
+/// When something living `expires_in` seconds from now expires, fails past
+/// `MAX_EXPIRES_IN`.
+fn expiry(expires_in: Option<u64>) -> Result<Option<SystemTime>, (Status, String)> {
+    let secs = match expires_in {
+        Some(secs) => secs,
+        None => return Ok(None),
+    };
+    SystemTime::now()
+        .checked_add(Duration::from_secs(secs))
+        .filter(|_| secs <= MAX_EXPIRES_IN)
+        .map(Some)
+        .ok_or_else(|| {
+            (
+                Status::BadRequest,
+                format!("Lifetime is at most {} seconds.", MAX_EXPIRES_IN),
+            )
+        })
+}
+
+/// Fails if a task would end up `depth` levels below a top-level task.
+fn check_depth(depth: usize) -> Result<(), (Status, String)> {
+    if depth > MAX_SUBTASK_DEPTH {
+        Err((
+            Status::Conflict,
+            format!("Subtasks nest at most {} levels deep.", MAX_SUBTASK_DEPTH),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// The list of `parent` if `user` can add a subtask to it.
+async fn subtask_list(
+    pool: &PgPool,
+    user: &AuthUser,
+    parent: Uuid,
+) -> Result<Uuid, (Status, String)> {
+    check_task(pool, user, parent, Role::Editor).await?;
+    let ancestors = db::get_ancestors(pool, parent)
+        .await
+        .map_err(internal_error)?;
+    check_depth(ancestors.len() + 1)?;
+    match db::get_task_list(pool, parent).await {
+        Ok(Some(list)) => Ok(list),
+        Ok(None) => Err((Status::NotFound, "No such task.".to_string())),
+        Err(e) => Err(internal_error(e)),
+    }
+}
+
+/// Fails unless task `id` can become a subtask of `parent`: both are in the
+/// same list, `id` is not above `parent` and the subtasks of `id` stay within
+/// the depth limit.
+async fn check_parent(
+    pool: &PgPool,
+    user: &AuthUser,
+    id: Uuid,
+    parent: Uuid,
+) -> Result<(), (Status, String)> {
+    let list = subtask_list(pool, user, parent).await?;
+    if db::get_task_list(pool, id).await.map_err(internal_error)? != Some(list) {
+        return Err((
+            Status::BadRequest,
+            "A subtask must be in the list of its parent.".to_string(),
+        ));
+    }
+    let ancestors = db::get_ancestors(pool, parent)
+        .await
+        .map_err(internal_error)?;
+    if parent == id || ancestors.contains(&id) {
+        return Err((
+            Status::Conflict,
+            "A task cannot be below itself.".to_string(),
+        ));
+    }
+    let height = db::subtask_height(pool, id).await.map_err(internal_error)?;
+    check_depth(ancestors.len() + 1 + height)
+}
+
+/// A task with `parent` goes into the list of its parent.
+#[post("/task?<list>", format = "application/msgpack", data = "<task_req>")]
 async fn create_task(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
     pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    list: Option<Uuid>,
     task_req: MsgPack<TaskRequest<'_>>,
 ) -> (Status, Either<MsgPack<Entry>, String>) {
     let content = task_req.content.to_string();
-    match db::crate_task(pool.as_ref(), content).await {
-        Ok(e) => (Status::Ok, Either::Left(MsgPack(e))),
+    let id = task_req.id;
+    let priority = task_req.priority;
+    let parent = task_req.parent;
+    let (status, r) = idem
+        .once(pool.as_ref(), || async {
+            let found = match parent {
+                Some(parent) => subtask_list(pool, &user, parent).await,
+                None => resolve_list(pool, &user, list, Role::Editor).await,
+            };
+            let target = match found {
+                Ok(l) if list.is_none() || list == Some(l) => l,
+                Ok(_) => {
+                    return (
+                        Status::BadRequest,
+                        Err("A subtask must be in the list of its parent.".to_string()),
+                    )
+                }
+                Err((status, e)) => return (status, Err(e)),
+            };
+            let created =
+                db::crate_task(pool, *user.id(), target, id, content, priority, parent).await;
+            match created {
+                Ok(e) => (Status::Ok, Ok(e)),
+                Err(e) if e.is_unique_violation() => (
+                    Status::Conflict,
+                    Err("A task with this id exists already.".to_string()),
+                ),
+                Err(e) => (Status::InternalServerError, Err(e.to_string())),
+            }
+        })
+        .await;
+    (
+        status,
+        r.map_or_else(Either::Right, |e| Either::Left(MsgPack(e))),
+    )
+}
+
+/// All tasks of the user, or those in `list`. `priority` leaves out tasks
+/// below it, `tag` those without that tag, `sort=priority` puts the most
+/// urgent first.
+#[get("/tasks?<list>&<sort>&<priority>&<tag>")]
+async fn get_tasks(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    list: Option<Uuid>,
+    sort: Option<&str>,
+    priority: Option<&str>,
+    tag: Option<Uuid>,
+) -> (Status, Either<MsgPack<Entries>, String>) {
+    let by_priority = match sort {
+        None => false,
+        Some("priority") => true,
+        Some(other) => {
+            return (
+                Status::BadRequest,
+                Either::Right(format!("Cannot sort by {}.", other)),
+            )
+        }
+    };
+    let min_priority = match priority.map(|p| (p, Priority::parse(p))) {
+        None => Priority::None,
+        Some((_, Some(p))) => p,
+        Some((other, None)) => {
+            return (
+                Status::BadRequest,
+                Either::Right(format!("Unknown priority {}.", other)),
+            )
+        }
+    };
+    match db::get_tasks(
+        pool.as_ref(),
+        *user.id(),
+        list,
+        min_priority,
+        tag,
+        by_priority,
+    )
+    .await
+    {
+        Ok(v) => (Status::Ok, Either::Left(MsgPack(v))),
         Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
     }
 }
 
-#[get("/tasks")]
-async fn get_tasks(pool: &State<Arc<PgPool>>) -> (Status, Either<MsgPack<Entries>, String>) {
-    match db::get_tasks(pool.as_ref()).await {
-        Ok(v) => (Status::Ok, Either::Left(MsgPack(v))),
+/// Longest search accepted, in characters.
+const MAX_SEARCH_LEN: usize = 200;
+
+/// Tasks whose content or notes match `q`, in `list` or in every list of the
+/// user, best matches first with snippets of where they match.
+#[get("/tasks/search?<q>&<list>")]
+async fn search_tasks(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    q: &str,
+    list: Option<Uuid>,
+) -> (Status, Either<MsgPack<SearchHits>, String>) {
+    if q.chars().count() > MAX_SEARCH_LEN {
+        return (
+            Status::BadRequest,
+            Either::Right(format!(
+                "Searches are at most {} characters.",
+                MAX_SEARCH_LEN
+            )),
+        );
+    }
+    match db::search_tasks(pool.as_ref(), *user.id(), list, q).await {
+        Ok(v) => (
+            Status::Ok,
+            Either::Left(MsgPack(
+                v.iter().map(db::SearchRow::to_search_hit).collect(),
+            )),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+/// Tasks created and completed per day and week, with counts of the tasks
+/// as they are now, in `list` or in every list of the user.
+#[get("/stats?<list>")]
+async fn get_stats(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    list: Option<Uuid>,
+) -> (Status, Either<MsgPack<Stats>, String>) {
+    match db::get_stats(pool.as_ref(), *user.id(), list).await {
+        Ok(stats) => (Status::Ok, Either::Left(MsgPack(stats))),
         Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
     }
 }
 
-#[post("/tasks", format = "application/msgpack", data = "<tasks>")]
+#[post("/tasks?<list>", format = "application/msgpack", data = "<tasks>")]
 async fn update_all_tasks(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
     pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    list: Option<Uuid>,
     tasks: MsgPack<UpdateAll>,
 ) -> (Status, String) {
-    match db::update_all_tasks(pool.as_ref(), tasks.0).await {
-        Ok(_) => (Status::Ok, "Acknowledged".to_string()),
-        Err(e) => (Status::InternalServerError, e.to_string()),
-    }
+    idem.once(pool.as_ref(), || async {
+        let list = match resolve_list(pool.as_ref(), &user, list, Role::Editor).await {
+            Ok(l) => l,
+            Err(e) => return e,
+        };
+        let invalid = tasks
+            .iter()
+            .find_map(|t| t.recurrence().and_then(|r| r.validate().err()));
+        if let Some(e) = invalid {
+            return (Status::BadRequest, e);
+        }
+        match db::update_all_tasks(pool.as_ref(), *user.id(), list, tasks.0).await {
+            Ok(BulkUpdate::Done) => (Status::Ok, "Acknowledged".to_string()),
+            Ok(BulkUpdate::Foreign(_)) => (
+                Status::Forbidden,
+                "Cannot change tasks of other users.".to_string(),
+            ),
+            Ok(BulkUpdate::Unfinished) => {
+                (Status::Conflict, "Finish its subtasks first.".to_string())
+            }
+            Err(e) if e.is_unique_violation() => (
+                Status::Conflict,
+                "A task with this id exists already.".to_string(),
+            ),
+            Err(e) => (Status::InternalServerError, e.to_string()),
+        }
+    })
+    .await
 }
 
 #[get("/task?<id>")]
 async fn get_task(
+    _limit: ReadLimit,
+    user: AuthUser,
     pool: &State<Arc<PgPool>>,
     id: Uuid,
 ) -> (Status, Either<MsgPack<Option<Entry>>, String>) {
-    match db::get_task(pool.as_ref(), id).await {
+    match db::get_task(pool.as_ref(), *user.id(), id).await {
         Ok(e) => (Status::Ok, Either::Left(MsgPack(e))),
         Err(e) => (Status::NotFound, Either::Right(e.to_string())),
     }
@@ -98,20 +501,1258 @@ async fn get_task(
 
 #[put("/task?<id>", format = "application/msgpack", data = "<task>")]
 async fn update_task(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
     pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
     id: Uuid,
     task: MsgPack<Entry>,
 ) -> (Status, String) {
-    match db::update_task(pool, id, task.0).await {
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = check_task(pool, &user, id, Role::Editor).await {
+            return e;
+        }
+        if let Some(Err(e)) = task.recurrence().map(|r| r.validate()) {
+            return (Status::BadRequest, e);
+        }
+        if *task.completed() {
+            match db::count_subtasks(pool, id).await {
+                Ok((_, 0)) => {}
+                Ok(_) => return (Status::Conflict, "Finish its subtasks first.".to_string()),
+                Err(e) => return internal_error(e),
+            }
+        }
+        match db::update_task(pool, *user.id(), id, task.0).await {
+            Ok(0) => (Status::NotFound, "No such task.".to_string()),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => (Status::NotFound, e.to_string()),
+        }
+    })
+    .await
+}
+
+#[delete("/task?<id>")]
+async fn delete_task(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    store: &State<Attachments>,
+    idem: Idempotency,
+    id: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = check_task(pool, &user, id, Role::Editor).await {
+            return e;
+        }
+        let hashes = match db::get_task_content_hashes(pool, id).await {
+            Ok(hashes) => hashes,
+            Err(e) => return internal_error(e),
+        };
+        match db::remove_task(pool, *user.id(), id).await {
+            Ok(_) => {
+                forget_contents(store, pool, hashes).await;
+                (Status::Ok, "Acknowledged".to_string())
+            }
+            Err(e) => (Status::NotFound, e.to_string()),
+        }
+    })
+    .await
+}
+
+/// Every task below a task, parents before their subtasks.
+#[get("/task/subtasks?<id>")]
+async fn get_subtasks(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    id: Uuid,
+) -> (Status, Either<MsgPack<Entries>, String>) {
+    match db::get_subtasks(pool.as_ref(), *user.id(), id).await {
+        Ok(Some(v)) => (Status::Ok, Either::Left(MsgPack(v))),
+        Ok(None) => (Status::NotFound, Either::Right("No such task.".to_string())),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+/// Makes a task a subtask of `parent`, or a top-level task without one.
+#[put("/task/parent?<id>&<parent>")]
+async fn set_parent(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+    parent: Option<Uuid>,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = check_task(pool, &user, id, Role::Editor).await {
+            return e;
+        }
+        if let Some(parent) = parent {
+            if let Err(e) = check_parent(pool, &user, id, parent).await {
+                return e;
+            }
+        }
+        match db::set_parent(pool, *user.id(), id, parent).await {
+            Ok(0) => (Status::NotFound, "No such task.".to_string()),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => internal_error(e),
+        }
+    })
+    .await
+}
+
+#[put("/task/list?<id>&<list>")]
+async fn move_task(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+    list: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = check_task(pool, &user, id, Role::Editor).await {
+            return e;
+        }
+        if let Err(e) = resolve_list(pool, &user, Some(list), Role::Editor).await {
+            return e;
+        }
+        match db::move_task(pool, *user.id(), id, list).await {
+            Ok(0) => (Status::NotFound, "No such task or list.".to_string()),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => (Status::InternalServerError, e.to_string()),
+        }
+    })
+    .await
+}
+
+/// Tag names are kept without the `#` people type in front of them.
+fn tag_name(name: &str) -> Option<String> {
+    let name = name.trim().trim_start_matches('#');
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+#[get("/tags")]
+async fn get_tags(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+) -> (Status, Either<MsgPack<Tags>, String>) {
+    match db::get_tags(pool.as_ref(), *user.id()).await {
+        Ok(v) => (
+            Status::Ok,
+            Either::Left(MsgPack(v.iter().map(db::TagRow::to_tag).collect())),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+/// Creating a tag that already exists returns it.
+#[post("/tags", format = "application/msgpack", data = "<tag_req>")]
+async fn create_tag(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    tag_req: MsgPack<TagRequest<'_>>,
+) -> (Status, Either<MsgPack<Tag>, String>) {
+    let name = match tag_name(tag_req.name) {
+        Some(name) => name,
+        None => {
+            return (
+                Status::BadRequest,
+                Either::Right("Tag names are single words.".to_string()),
+            )
+        }
+    };
+    let (status, r) = idem
+        .once(pool.as_ref(), || async {
+            match db::find_tag(pool, *user.id(), None, Some(name.clone())).await {
+                Ok(Some(t)) => return (Status::Ok, Ok(t.to_tag())),
+                Ok(None) => {}
+                Err(e) => return (Status::InternalServerError, Err(e.to_string())),
+            }
+            let tag = db::TagRow::new(*user.id(), name);
+            match db::create_tag(pool, &tag).await {
+                Ok(_) => (Status::Ok, Ok(tag.to_tag())),
+                Err(e) => (Status::InternalServerError, Err(e.to_string())),
+            }
+        })
+        .await;
+    (
+        status,
+        r.map_or_else(Either::Right, |t| Either::Left(MsgPack(t))),
+    )
+}
+
+#[put("/tag?<id>", format = "application/msgpack", data = "<tag_req>")]
+async fn rename_tag(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+    tag_req: MsgPack<TagRequest<'_>>,
+) -> (Status, String) {
+    let name = match tag_name(tag_req.name) {
+        Some(name) => name,
+        None => {
+            return (
+                Status::BadRequest,
+                "Tag names are single words.".to_string(),
+            )
+        }
+    };
+    idem.once(pool.as_ref(), || async {
+        match db::find_tag(pool, *user.id(), None, Some(name.clone())).await {
+            Ok(Some(t)) if *t.id() != id => {
+                return (Status::Conflict, "Tag name is already taken.".to_string())
+            }
+            Ok(_) => {}
+            Err(e) => return (Status::InternalServerError, e.to_string()),
+        }
+        match db::rename_tag(pool, *user.id(), id, name).await {
+            Ok(0) => (Status::NotFound, "No such tag.".to_string()),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => (Status::InternalServerError, e.to_string()),
+        }
+    })
+    .await
+}
+
+#[delete("/tag?<id>")]
+async fn delete_tag(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        match db::remove_tag(pool, *user.id(), id).await {
+            Ok(0) => (Status::NotFound, "No such tag.".to_string()),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => (Status::InternalServerError, e.to_string()),
+        }
+    })
+    .await
+}
+
+/// Puts one of the user's tags on a task the user can edit.
+#[put("/task/tag?<id>&<tag>")]
+async fn tag_task(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+    tag: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = check_task(pool, &user, id, Role::Editor).await {
+            return e;
+        }
+        match db::find_tag(pool, *user.id(), Some(tag), None).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return (Status::NotFound, "No such tag.".to_string()),
+            Err(e) => return (Status::InternalServerError, e.to_string()),
+        }
+        match db::tag_task(pool, id, tag).await {
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => (Status::InternalServerError, e.to_string()),
+        }
+    })
+    .await
+}
+
+/// Takes a tag off a task the user can edit, whoever put it there.
+#[delete("/task/tag?<id>&<tag>")]
+async fn untag_task(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+    tag: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = check_task(pool, &user, id, Role::Editor).await {
+            return e;
+        }
+        match db::untag_task(pool, id, tag).await {
+            Ok(0) => (Status::NotFound, "The task has no such tag.".to_string()),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => (Status::InternalServerError, e.to_string()),
+        }
+    })
+    .await
+}
+
+/// Makes task `id` wait for task `on` of the same list. Fails with a
+/// conflict if `on` already waits for `id`.
+#[put("/task/dependency?<id>&<on>")]
+async fn add_dependency(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+    on: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        let list = match check_dependency(pool, &user, id, on).await {
+            Ok(list) => list,
+            Err(e) => return e,
+        };
+        match db::add_dependency(pool, list, id, on).await {
+            Ok(true) => (Status::Ok, "Acknowledged".to_string()),
+            Ok(false) => waits_for_itself(),
+            Err(e) => internal_error(e),
+        }
+    })
+    .await
+}
+
+/// The list of tasks `id` and `on` if task `id` can wait for task `on`:
+/// both are in the same list and are different tasks. Whether `on` waits for
+/// `id` already is checked as the dependency is added.
+async fn check_dependency(
+    pool: &PgPool,
+    user: &AuthUser,
+    id: Uuid,
+    on: Uuid,
+) -> Result<Uuid, (Status, String)> {
+    check_task(pool, user, id, Role::Editor).await?;
+    check_task(pool, user, on, Role::Viewer).await?;
+    let list = db::get_task_list(pool, id).await.map_err(internal_error)?;
+    if db::get_task_list(pool, on).await.map_err(internal_error)? != list {
+        return Err((
+            Status::BadRequest,
+            "A task can only depend on tasks of its own list.".to_string(),
+        ));
+    }
+    if id == on {
+        return Err(waits_for_itself());
+    }
+    list.ok_or_else(|| (Status::NotFound, "No such task.".to_string()))
+}
+
+fn waits_for_itself() -> (Status, String) {
+    (
+        Status::Conflict,
+        "A task cannot wait for itself.".to_string(),
+    )
+}
+
+#[delete("/task/dependency?<id>&<on>")]
+async fn remove_dependency(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+    on: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = check_task(pool, &user, id, Role::Editor).await {
+            return e;
+        }
+        match db::remove_dependency(pool, id, on).await {
+            Ok(0) => (
+                Status::NotFound,
+                "The task does not depend on that one.".to_string(),
+            ),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => internal_error(e),
+        }
+    })
+    .await
+}
+
+#[get("/task/attachments?<id>")]
+async fn get_attachments(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    id: Uuid,
+) -> (Status, Either<MsgPack<AttachmentList>, String>) {
+    if let Err((status, e)) = check_task(pool, &user, id, Role::Viewer).await {
+        return (status, Either::Right(e));
+    }
+    match db::get_attachments(pool, id).await {
+        Ok(v) => (
+            Status::Ok,
+            Either::Left(MsgPack(
+                v.iter().map(db::AttachmentRow::to_attachment).collect(),
+            )),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+/// Attaches the `file` field of a multipart form to a task.
+#[post("/task/attachments?<id>", data = "<upload>")]
+// the usual guards plus the attachment store
+#[allow(clippy::too_many_arguments)]
+async fn upload_attachment(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    store: &State<Attachments>,
+    idem: Idempotency,
+    id: Uuid,
+    mut upload: Form<Upload<'_>>,
+) -> (Status, Either<MsgPack<Attachment>, String>) {
+    let (status, r) = idem
+        .once(pool.as_ref(), || async {
+            if let Err((status, e)) = check_task(pool, &user, id, Role::Editor).await {
+                return (status, Err(e));
+            }
+            let file = &mut upload.file;
+            let content_type = attachments::media_type(file.content_type());
+            // a capped file went over Rocket's `file` limits
+            let size = if file.is_complete() {
+                file.len()
+            } else {
+                u64::MAX
+            };
+            if let Err((status, e)) = store.check(size, &content_type) {
+                return (status, Err(e));
+            }
+            let hash = match file.path() {
+                Some(path) => attachments::hash_file(path).await,
+                None => return (Status::BadRequest, Err("Expected a file.".to_string())),
+            };
+            let hash = match hash {
+                Ok(hash) => hash,
+                Err(e) => return (Status::InternalServerError, Err(e.to_string())),
+            };
+            let name = attachments::display_name(
+                file.raw_name()
+                    .map(|n| n.dangerous_unsafe_unsanitized_raw().as_str()),
+            );
+            let row = db::AttachmentRow::new(id, name, content_type, size as i64, hash, *user.id());
+            match store.save(pool, &row, file).await {
+                Ok(_) => (Status::Ok, Ok(row.to_attachment())),
+                Err(e) => (Status::InternalServerError, Err(e.to_string())),
+            }
+        })
+        .await;
+    (
+        status,
+        r.map_or_else(Either::Right, |a| Either::Left(MsgPack(a))),
+    )
+}
+
+#[get("/attachment?<id>")]
+async fn download_attachment(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    store: &State<Attachments>,
+    id: Uuid,
+) -> (Status, Either<Download, String>) {
+    let row = match db::get_attachment(pool, id).await {
+        Ok(Some(row)) => row,
+        Ok(None) => {
+            return (
+                Status::NotFound,
+                Either::Right("No such attachment.".to_string()),
+            )
+        }
+        Err(e) => return (Status::InternalServerError, Either::Right(e.to_string())),
+    };
+    if let Err((status, e)) = check_task(pool, &user, *row.task_id(), Role::Viewer).await {
+        return (status, Either::Right(e));
+    }
+    match store.open(&row).await {
+        Ok(file) => (Status::Ok, Either::Left(Download::new(file, &row))),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[delete("/attachment?<id>")]
+async fn delete_attachment(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    store: &State<Attachments>,
+    idem: Idempotency,
+    id: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        let row = match db::get_attachment(pool, id).await {
+            Ok(Some(row)) => row,
+            Ok(None) => return (Status::NotFound, "No such attachment.".to_string()),
+            Err(e) => return internal_error(e),
+        };
+        if let Err(e) = check_task(pool, &user, *row.task_id(), Role::Editor).await {
+            return e;
+        }
+        match store.remove(pool, &row).await {
+            Ok(0) => (Status::NotFound, "No such attachment.".to_string()),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => internal_error(e),
+        }
+    })
+    .await
+}
+
+#[get("/lists")]
+async fn get_lists(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+) -> (Status, Either<MsgPack<Lists>, String>) {
+    // Makes sure there is always a list to put tasks in.
+    if let Err(e) = db::default_list(pool.as_ref(), *user.id()).await {
+        return (Status::InternalServerError, Either::Right(e.to_string()));
+    }
+    match db::get_lists(pool.as_ref(), *user.id()).await {
+        Ok(v) => (
+            Status::Ok,
+            Either::Left(MsgPack(
+                v.iter().map(|(l, role)| l.to_task_list(*role)).collect(),
+            )),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[post("/lists", format = "application/msgpack", data = "<list_req>")]
+async fn create_list(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    list_req: MsgPack<ListRequest<'_>>,
+) -> (Status, Either<MsgPack<TaskList>, String>) {
+    let name = list_req.name.trim().to_string();
+    if name.is_empty() {
+        return (
+            Status::BadRequest,
+            Either::Right("List name is required.".to_string()),
+        );
+    }
+    let id = list_req.id;
+    let (status, r) = idem
+        .once(pool.as_ref(), || async {
+            match db::crate_list(pool.as_ref(), *user.id(), id, name).await {
+                Ok((l, role)) => (Status::Ok, Ok(l.to_task_list(role))),
+                Err(e) => (Status::InternalServerError, Err(e.to_string())),
+            }
+        })
+        .await;
+    (
+        status,
+        r.map_or_else(Either::Right, |l| Either::Left(MsgPack(l))),
+    )
+}
+
+#[put("/list?<id>", format = "application/msgpack", data = "<list_req>")]
+async fn rename_list(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    idem: Idempotency,
+    id: Uuid,
+    list_req: MsgPack<ListRequest<'_>>,
+) -> (Status, String) {
+    let name = list_req.name.trim().to_string();
+    if name.is_empty() {
+        return (Status::BadRequest, "List name is required.".to_string());
+    }
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = resolve_list(pool, &user, Some(id), Role::Owner).await {
+            return e;
+        }
+        match db::rename_list(pool, *user.id(), id, name).await {
+            Ok(0) => (Status::NotFound, "No such list.".to_string()),
+            Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+            Err(e) => (Status::InternalServerError, e.to_string()),
+        }
+    })
+    .await
+}
+
+#[delete("/list?<id>")]
+async fn delete_list(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    store: &State<Attachments>,
+    idem: Idempotency,
+    id: Uuid,
+) -> (Status, String) {
+    idem.once(pool.as_ref(), || async {
+        if let Err(e) = resolve_list(pool, &user, Some(id), Role::Owner).await {
+            return e;
+        }
+        let hashes = match db::get_list_content_hashes(pool, id).await {
+            Ok(hashes) => hashes,
+            Err(e) => return internal_error(e),
+        };
+        match db::remove_list(pool, *user.id(), id).await {
+            Ok(0) => (Status::NotFound, "No such list.".to_string()),
+            Ok(_) => {
+                forget_contents(store, pool, hashes).await;
+                (Status::Ok, "Acknowledged".to_string())
+            }
+            Err(e) => (Status::InternalServerError, e.to_string()),
+        }
+    })
+    .await
+}
+
+#[get("/list/members?<id>")]
+async fn get_members(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    id: Uuid,
+) -> (Status, Either<MsgPack<Members>, String>) {
+    if let Err((status, e)) = resolve_list(pool, &user, Some(id), Role::Viewer).await {
+        return (status, Either::Right(e));
+    }
+    match db::get_members(pool, *user.id(), id).await {
+        Ok(v) => (Status::Ok, Either::Left(MsgPack(v))),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+/// Fails if removing `member` as owner of list `id` would leave it without one.
+async fn keep_an_owner(pool: &PgPool, id: Uuid, member: Uuid) -> Result<(), (Status, String)> {
+    let is_owner = match db::get_role(pool, member, id).await {
+        Ok(role) => role == Some(Role::Owner),
+        Err(e) => return Err((Status::InternalServerError, e.to_string())),
+    };
+    match db::count_owners(pool, id).await {
+        Ok(n) if is_owner && n <= 1 => Err((
+            Status::Conflict,
+            "A list needs at least one owner.".to_string(),
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => Err((Status::InternalServerError, e.to_string())),
+    }
+}
+
+/// Invites a user to list `id`, or changes the role of a member.
+#[post(
+    "/list/members?<id>",
+    format = "application/msgpack",
+    data = "<invite>"
+)]
+async fn invite_member(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    id: Uuid,
+    invite: MsgPack<InviteRequest<'_>>,
+) -> (Status, Either<MsgPack<Member>, String>) {
+    if let Err((status, e)) = resolve_list(pool, &user, Some(id), Role::Owner).await {
+        return (status, Either::Right(e));
+    }
+    let member = match db::get_user_by_name(pool, invite.username.trim().to_string()).await {
+        Ok(Some(u)) => u,
+        Ok(None) => return (Status::NotFound, Either::Right("No such user.".to_string())),
+        Err(e) => return (Status::InternalServerError, Either::Right(e.to_string())),
+    };
+    if invite.role != Role::Owner {
+        if let Err((status, e)) = keep_an_owner(pool, id, *member.id()).await {
+            return (status, Either::Right(e));
+        }
+    }
+    match db::set_member(pool, id, *member.id(), invite.role).await {
+        Ok(_) => (
+            Status::Ok,
+            Either::Left(MsgPack(Member::new(
+                *member.id(),
+                member.username().clone(),
+                invite.role,
+            ))),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+/// Revokes the membership of `member`, owners can revoke anyone and every
+/// member can leave.
+#[delete("/list/member?<id>&<member>")]
+async fn revoke_member(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    id: Uuid,
+    member: Uuid,
+) -> (Status, String) {
+    let min = if member == *user.id() {
+        Role::Viewer
+    } else {
+        Role::Owner
+    };
+    if let Err(e) = resolve_list(pool, &user, Some(id), min).await {
+        return e;
+    }
+    if let Err(e) = keep_an_owner(pool, id, member).await {
+        return e;
+    }
+    match db::remove_member(pool, id, member).await {
+        Ok(0) => (Status::NotFound, "No such member.".to_string()),
         Ok(_) => (Status::Ok, "Acknowledged".to_string()),
-        Err(e) => (Status::NotFound, e.to_string()),
+        Err(e) => (Status::InternalServerError, e.to_string()),
     }
 }
 
-#[delete("/task?<id>")]
-async fn delete_task(pool: &State<Arc<PgPool>>, id: Uuid) -> (Status, String) {
-    match db::remove_task(pool, id).await {
+fn to_share_link(key: &ShareKey, l: &db::ShareLinkRow) -> ShareLink {
+    ShareLink::new(*l.id(), key.sign(*l.id()), *l.expires_at())
+}
+
+#[get("/list/shares?<id>")]
+async fn get_share_links(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    key: &State<ShareKey>,
+    id: Uuid,
+) -> (Status, Either<MsgPack<ShareLinks>, String>) {
+    if let Err((status, e)) = resolve_list(pool, &user, Some(id), Role::Owner).await {
+        return (status, Either::Right(e));
+    }
+    match db::get_share_links(pool, *user.id(), id).await {
+        Ok(v) => (
+            Status::Ok,
+            Either::Left(MsgPack(v.iter().map(|l| to_share_link(key, l)).collect())),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[post(
+    "/list/shares?<id>",
+    format = "application/msgpack",
+    data = "<share_req>"
+)]
+async fn create_share_link(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    key: &State<ShareKey>,
+    id: Uuid,
+    share_req: MsgPack<ShareRequest>,
+) -> (Status, Either<MsgPack<ShareLink>, String>) {
+    if let Err((status, e)) = resolve_list(pool, &user, Some(id), Role::Owner).await {
+        return (status, Either::Right(e));
+    }
+    let expires_at = match expiry(share_req.expires_in) {
+        Ok(at) => at,
+        Err((status, e)) => return (status, Either::Right(e)),
+    };
+    let link = db::ShareLinkRow::new(id, *user.id(), expires_at);
+    match db::create_share_link(pool, &link).await {
+        Ok(_) => (Status::Ok, Either::Left(MsgPack(to_share_link(key, &link)))),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[delete("/list/share?<id>&<share>")]
+async fn revoke_share_link(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    id: Uuid,
+    share: Uuid,
+) -> (Status, String) {
+    if let Err(e) = resolve_list(pool, &user, Some(id), Role::Owner).await {
+        return e;
+    }
+    match db::remove_share_link(pool, *user.id(), id, share).await {
+        Ok(0) => (Status::NotFound, "No such link.".to_string()),
         Ok(_) => (Status::Ok, "Acknowledged".to_string()),
-        Err(e) => (Status::NotFound, e.to_string()),
+        Err(e) => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Read-only view of a list for anyone holding a valid share token.
+#[get("/shared?<token>")]
+async fn get_shared(
+    _limit: ReadLimit,
+    pool: &State<Arc<PgPool>>,
+    key: &State<ShareKey>,
+    token: &str,
+) -> (Status, Either<MsgPack<SharedList>, String>) {
+    let id = match key.verify(token) {
+        Some(id) => id,
+        None => return (Status::NotFound, Either::Right("No such link.".to_string())),
+    };
+    match db::get_shared_list(pool, id).await {
+        Ok(Some((list, entries))) => (
+            Status::Ok,
+            Either::Left(MsgPack(SharedList::new(list.name().clone(), entries))),
+        ),
+        Ok(None) => (Status::NotFound, Either::Right("No such link.".to_string())),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[put("/task/edit?<id>&<session>")]
+async fn acquire_edit(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: WriteUser,
+    pool: &State<Arc<PgPool>>,
+    presence: &State<Presence>,
+    id: Uuid,
+    session: Uuid,
+) -> (Status, String) {
+    if let Err(e) = check_task(pool, &user, id, Role::Editor).await {
+        return e;
+    }
+    if presence.acquire(id, session) {
+        (Status::Ok, "Acknowledged".to_string())
+    } else {
+        (
+            Status::Conflict,
+            "Task is being edited by another session.".to_string(),
+        )
+    }
+}
+
+#[delete("/task/edit?<id>&<session>")]
+async fn release_edit(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    _user: AuthUser,
+    presence: &State<Presence>,
+    id: Uuid,
+    session: Uuid,
+) -> (Status, String) {
+    presence.release(id, session);
+    (Status::Ok, "Acknowledged".to_string())
+}
+
+#[get("/edits?<session>")]
+async fn get_edits(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    presence: &State<Presence>,
+    session: Uuid,
+) -> (Status, Either<MsgPack<Editing>, String>) {
+    let edited = presence.edited_by_others(session);
+    if edited.is_empty() {
+        return (Status::Ok, Either::Left(MsgPack(edited)));
+    }
+    match db::visible_tasks(pool, *user.id(), edited).await {
+        Ok(v) => (Status::Ok, Either::Left(MsgPack(v))),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[post("/register", format = "application/msgpack", data = "<cred>")]
+async fn register(
+    _limit: AuthLimit,
+    pool: &State<Arc<PgPool>>,
+    cookies: &CookieJar<'_>,
+    cred: MsgPack<Credentials<'_>>,
+) -> (Status, Either<MsgPack<UserInfo>, String>) {
+    let username = cred.username.trim().to_string();
+    if username.is_empty() || cred.password.is_empty() {
+        return (
+            Status::BadRequest,
+            Either::Right("Username and password are required.".to_string()),
+        );
+    }
+    match db::get_user_by_name(pool.as_ref(), username.clone()).await {
+        Ok(Some(_)) => {
+            return (
+                Status::Conflict,
+                Either::Right("Username is already taken.".to_string()),
+            )
+        }
+        Ok(None) => {}
+        Err(e) => return (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+    let hash = match auth::hash_password(cred.password.to_string()).await {
+        Ok(h) => h,
+        Err(e) => return (Status::InternalServerError, Either::Right(e.to_string())),
+    };
+    let user = db::User::new(username, hash);
+    match db::create_user(pool.as_ref(), &user).await {
+        // registered at the same time by someone else
+        Err(e) if e.is_unique_violation() => {
+            return (
+                Status::Conflict,
+                Either::Right("Username is already taken.".to_string()),
+            )
+        }
+        Err(e) => return (Status::InternalServerError, Either::Right(e.to_string())),
+        Ok(_) => {}
+    }
+    match auth::login(pool, cookies, &user).await {
+        Ok(_) => (Status::Ok, Either::Left(MsgPack(user.to_user_info()))),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[post("/login", format = "application/msgpack", data = "<cred>")]
+async fn login(
+    _limit: AuthLimit,
+    pool: &State<Arc<PgPool>>,
+    cookies: &CookieJar<'_>,
+    cred: MsgPack<Credentials<'_>>,
+) -> (Status, Either<MsgPack<UserInfo>, String>) {
+    let found = match db::get_user_by_name(pool.as_ref(), cred.username.trim().to_string()).await {
+        Ok(found) => found,
+        Err(e) => return (Status::InternalServerError, Either::Right(e.to_string())),
+    };
+    // unknown users are checked against a dummy hash, taking as long
+    let hash = found.as_ref().map(|u| u.password_hash().clone());
+    let verified = auth::verify_password(cred.password.to_string(), hash).await;
+    let user = match found {
+        Some(u) if verified => u,
+        _ => {
+            return (
+                Status::Unauthorized,
+                Either::Right("Invalid username or password.".to_string()),
+            )
+        }
+    };
+    if *user.disabled() {
+        return (
+            Status::Forbidden,
+            Either::Right("Account is disabled.".to_string()),
+        );
+    }
+    match auth::login(pool, cookies, &user).await {
+        Ok(_) => (Status::Ok, Either::Left(MsgPack(user.to_user_info()))),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[post("/logout")]
+async fn logout(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    pool: &State<Arc<PgPool>>,
+    cookies: &CookieJar<'_>,
+) -> (Status, String) {
+    match auth::logout(pool, cookies).await {
+        Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+        Err(e) => internal_error(e),
+    }
+}
+
+#[get("/me")]
+async fn me(_limit: ReadLimit, user: AuthUser, cookies: &CookieJar<'_>) -> MsgPack<UserInfo> {
+    if user.token().is_none() && cookies.get(CSRF_COOKIE).is_none() {
+        csrf::issue(cookies);
+    }
+    MsgPack(UserInfo::new(
+        *user.id(),
+        user.username().clone(),
+        *user.admin(),
+    ))
+}
+
+#[get("/login/options")]
+async fn login_options(_limit: ReadLimit, oidc: &State<Oidc>) -> MsgPack<LoginOptions> {
+    MsgPack(LoginOptions {
+        sso: oidc.name().map(str::to_string),
+    })
+}
+
+/// Sends the browser to the provider, remembering the login in a private
+/// cookie.
+#[get("/oidc/login")]
+async fn oidc_login(
+    _limit: AuthLimit,
+    oidc: &State<Oidc>,
+    cookies: &CookieJar<'_>,
+) -> Result<Redirect, (Status, String)> {
+    if oidc.name().is_none() {
+        return Err((Status::NotFound, "Single sign-on is off.".to_string()));
+    }
+    let attempt = LoginAttempt::start();
+    match oidc.authorize_url(&attempt).await {
+        Ok(url) => {
+            cookies.add_private(attempt.to_cookie());
+            Ok(Redirect::to(url))
+        }
+        Err(e) => Err((Status::BadGateway, e.to_string())),
+    }
+}
+
+/// Where the provider sends the browser back to. The first login with an
+/// identity creates a user for it, named after the identity if that name
+/// is free. Such users have no password.
+#[get("/oidc/callback?<code>&<state>&<error>")]
+async fn oidc_callback(
+    _limit: AuthLimit,
+    oidc: &State<Oidc>,
+    pool: &State<Arc<PgPool>>,
+    cookies: &CookieJar<'_>,
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+) -> Result<Redirect, (Status, String)> {
+    let attempt = cookies
+        .get_private(LOGIN_COOKIE)
+        .and_then(|c| LoginAttempt::from_cookie(&c));
+    cookies.remove_private(Cookie::named(LOGIN_COOKIE));
+    if let Some(error) = error {
+        return Err((Status::Unauthorized, format!("Login failed: {}", error)));
+    }
+    let (attempt, code) = match (attempt, code, state) {
+        (Some(attempt), Some(code), Some(state)) if state == attempt.state => (attempt, code),
+        _ => return Err((Status::BadRequest, "Unknown or expired login.".to_string())),
+    };
+    let claims = oidc
+        .exchange(&attempt, &code)
+        .await
+        .map_err(|e| (Status::BadGateway, e.to_string()))?;
+    let internal = |e: Error| (Status::InternalServerError, e.to_string());
+    let found = db::get_user_by_identity(pool.as_ref(), claims.iss.clone(), claims.sub.clone())
+        .await
+        .map_err(internal)?;
+    let user = match found {
+        Some(user) => user,
+        None => {
+            let wanted = claims
+                .preferred_username
+                .as_deref()
+                .or(claims.email.as_deref())
+                .unwrap_or(&claims.sub)
+                .trim()
+                .to_string();
+            let suffixed = || {
+                let suffix = Uuid::new_v4().to_simple().to_string();
+                format!("{}-{}", wanted, &suffix[..8])
+            };
+            let taken = db::get_user_by_name(pool.as_ref(), wanted.clone())
+                .await
+                .map_err(internal)?
+                .is_some();
+            let mut username = if taken || wanted.is_empty() {
+                suffixed()
+            } else {
+                wanted.clone()
+            };
+            let mut retried = false;
+            loop {
+                // argon2 never produces an empty hash, so password logins fail
+                let user = db::User::new(username, String::new());
+                let created = db::create_user_with_identity(
+                    pool.as_ref(),
+                    &user,
+                    claims.iss.clone(),
+                    claims.sub.clone(),
+                )
+                .await;
+                match created {
+                    Ok(_) => break user,
+                    // the name or the identity was taken since we looked
+                    Err(e) if e.is_unique_violation() && !retried => {
+                        let found = db::get_user_by_identity(
+                            pool.as_ref(),
+                            claims.iss.clone(),
+                            claims.sub.clone(),
+                        )
+                        .await
+                        .map_err(internal)?;
+                        if let Some(user) = found {
+                            break user;
+                        }
+                        username = suffixed();
+                        retried = true;
+                    }
+                    Err(e) => return Err(internal(e)),
+                }
+            }
+        }
+    };
+    if *user.disabled() {
+        return Err((Status::Forbidden, "Account is disabled.".to_string()));
+    }
+    auth::login(pool, cookies, &user).await.map_err(internal)?;
+    Ok(Redirect::to(uri!("/")))
+}
+
+/// Tokens can only be managed from a browser session, so a leaked token
+/// cannot mint new ones.
+fn session_only(user: &AuthUser) -> Result<(), (Status, String)> {
+    match user.token() {
+        Some(_) => Err((
+            Status::Forbidden,
+            "API tokens cannot manage tokens.".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[get("/tokens")]
+async fn get_tokens(
+    _limit: ReadLimit,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+) -> (Status, Either<MsgPack<Tokens>, String>) {
+    if let Err((status, e)) = session_only(&user) {
+        return (status, Either::Right(e));
+    }
+    match db::get_api_tokens(pool.as_ref(), *user.id()).await {
+        Ok(v) => (
+            Status::Ok,
+            Either::Left(MsgPack(v.iter().map(db::ApiToken::to_token_info).collect())),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[post("/tokens", format = "application/msgpack", data = "<token_req>")]
+async fn create_token(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    token_req: MsgPack<TokenRequest<'_>>,
+) -> (Status, Either<MsgPack<NewToken>, String>) {
+    if let Err((status, e)) = session_only(&user) {
+        return (status, Either::Right(e));
+    }
+    let name = token_req.name.trim().to_string();
+    if name.is_empty() {
+        return (
+            Status::BadRequest,
+            Either::Right("Token name is required.".to_string()),
+        );
+    }
+    let expires_at = match expiry(token_req.expires_in) {
+        Ok(at) => at,
+        Err((status, e)) => return (status, Either::Right(e)),
+    };
+    let secret = auth::generate_token();
+    let token = db::ApiToken::new(
+        *user.id(),
+        name,
+        auth::hash_token(&secret),
+        token_req.read_only,
+        expires_at,
+    );
+    match db::create_api_token(pool.as_ref(), &token).await {
+        Ok(_) => (
+            Status::Ok,
+            Either::Left(MsgPack(NewToken::new(token.to_token_info(), secret))),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+#[delete("/token?<id>")]
+async fn revoke_token(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    user: AuthUser,
+    pool: &State<Arc<PgPool>>,
+    id: Uuid,
+) -> (Status, String) {
+    if let Err(e) = session_only(&user) {
+        return e;
+    }
+    match db::remove_api_token(pool.as_ref(), *user.id(), id).await {
+        Ok(0) => (Status::NotFound, "No such token.".to_string()),
+        Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+        Err(e) => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+#[get("/admin/users")]
+async fn get_users(
+    _limit: ReadLimit,
+    _admin: AdminUser,
+    pool: &State<Arc<PgPool>>,
+) -> (Status, Either<MsgPack<Users>, String>) {
+    match db::get_user_stats(pool.as_ref()).await {
+        Ok(v) => (
+            Status::Ok,
+            Either::Left(MsgPack(v.iter().map(|u| u.to_user_stats()).collect())),
+        ),
+        Err(e) => (Status::InternalServerError, Either::Right(e.to_string())),
+    }
+}
+
+/// Disabled users can neither log in nor use their sessions and tokens.
+#[put("/admin/user/disabled?<id>&<disabled>")]
+async fn disable_user(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    admin: AdminUser,
+    pool: &State<Arc<PgPool>>,
+    id: Uuid,
+    disabled: bool,
+) -> (Status, String) {
+    if id == *admin.id() {
+        return (
+            Status::Conflict,
+            "Admins cannot disable themselves.".to_string(),
+        );
+    }
+    match db::set_user_disabled(pool.as_ref(), id, disabled).await {
+        Ok(0) => (Status::NotFound, "No such user.".to_string()),
+        Ok(_) => (Status::Ok, "Acknowledged".to_string()),
+        Err(e) => (Status::InternalServerError, e.to_string()),
+    }
+}
+
+/// Deletes the account and all data it owns.
+#[delete("/admin/user?<id>")]
+async fn purge_user(
+    _limit: WriteLimit,
+    _csrf: Csrf,
+    admin: AdminUser,
+    pool: &State<Arc<PgPool>>,
+    store: &State<Attachments>,
+    id: Uuid,
+) -> (Status, String) {
+    if id == *admin.id() {
+        return (
+            Status::Conflict,
+            "Admins cannot purge themselves.".to_string(),
+        );
+    }
+    let hashes = match db::get_user_content_hashes(pool, id).await {
+        Ok(hashes) => hashes,
+        Err(e) => return internal_error(e),
+    };
+    match db::remove_user(pool.as_ref(), id).await {
+        Ok(0) => (Status::NotFound, "No such user.".to_string()),
+        Ok(_) => {
+            forget_contents(store, pool, hashes).await;
+            (Status::Ok, "Acknowledged".to_string())
+        }
+        Err(e) => (Status::InternalServerError, e.to_string()),
     }
 }
@@ -0,0 +1,391 @@
+use super::{Error, MyResult as Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hyper::{body, client::conn, header, Body, Method, Request, Uri};
+use rocket::{
+    error,
+    fairing::AdHoc,
+    http::{Cookie, SameSite},
+    serde::json::serde_json,
+    tokio::{
+        self,
+        io::{AsyncRead, AsyncWrite},
+        net::TcpStream,
+    },
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::BufReader,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio_rustls::{rustls::ClientConfig, webpki::DNSNameRef, TlsConnector};
+
+/// Private cookie carrying a login from `/oidc/login` to `/oidc/callback`.
+pub const LOGIN_COOKIE: &str = "oidc_login";
+
+/// The `oidc` table of `Rocket.toml`, logging in with a provider is off
+/// without it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfig {
+    /// Shown on the login button.
+    #[serde(default = "default_name")]
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// `/oidc/callback` of this server, as registered with the provider.
+    pub redirect_uri: String,
+    #[serde(default = "default_scopes")]
+    pub scopes: String,
+    /// Certificate authorities trusted for an `https` issuer, as a PEM
+    /// bundle.
+    #[serde(default = "default_ca_certs")]
+    pub ca_certs: String,
+}
+
+fn default_name() -> String {
+    "single sign-on".to_string()
+}
+
+fn default_scopes() -> String {
+    "openid profile email".to_string()
+}
+
+fn default_ca_certs() -> String {
+    "/etc/ssl/certs/ca-certificates.crt".to_string()
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The claims of an id token this server looks at.
+#[derive(Deserialize)]
+pub struct Claims {
+    pub iss: String,
+    pub sub: String,
+    aud: Audience,
+    exp: u64,
+    nonce: Option<String>,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+}
+
+/// How long a login may take at the provider.
+const LOGIN_TTL_SECS: u64 = 10 * 60;
+
+/// The secrets of one login, kept in `LOGIN_COOKIE` until the provider
+/// redirects back.
+pub struct LoginAttempt {
+    started: u64,
+    pub state: String,
+    nonce: String,
+    /// PKCE code verifier, the provider only sees its hash until the code
+    /// is exchanged.
+    verifier: String,
+}
+
+impl LoginAttempt {
+    pub fn start() -> Self {
+        Self {
+            started: now(),
+            state: random_string(),
+            nonce: random_string(),
+            verifier: random_string(),
+        }
+    }
+
+    /// The callback is a cross-site navigation from the provider, so the
+    /// cookie has to be `Lax` instead of the default `Strict`.
+    pub fn to_cookie(&self) -> Cookie<'static> {
+        let value = format!(
+            "{} {} {} {}",
+            self.started, self.state, self.nonce, self.verifier
+        );
+        Cookie::build(LOGIN_COOKIE, value)
+            .path("/oidc")
+            .same_site(SameSite::Lax)
+            .finish()
+    }
+
+    /// `None` for malformed or expired logins.
+    pub fn from_cookie(cookie: &Cookie<'_>) -> Option<Self> {
+        let mut parts = cookie.value().split(' ');
+        let attempt = Self {
+            started: parts.next()?.parse().ok()?,
+            state: parts.next()?.to_string(),
+            nonce: parts.next()?.to_string(),
+            verifier: parts.next()?.to_string(),
+        };
+        Some(attempt).filter(|a| now() < a.started + LOGIN_TTL_SECS)
+    }
+
+    fn challenge(&self) -> String {
+        base64::encode_config(
+            Sha256::digest(self.verifier.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+}
+
+/// Client for the OpenID Connect provider configured in `Rocket.toml`,
+/// using the authorization code flow with PKCE.
+pub struct Oidc {
+    config: Option<OidcConfig>,
+    tls: TlsConnector,
+}
+
+impl Oidc {
+    pub fn fairing() -> AdHoc {
+        AdHoc::try_on_ignite("OpenID Connect", |rocket| async move {
+            let config = if rocket.figment().find_value("oidc").is_ok() {
+                match rocket.figment().extract_inner::<OidcConfig>("oidc") {
+                    Ok(config) => Some(config),
+                    Err(e) => {
+                        error!("Invalid oidc: {}", e);
+                        return Err(rocket);
+                    }
+                }
+            } else {
+                None
+            };
+            let mut tls = ClientConfig::new();
+            if let Some(config) = config.as_ref().filter(|c| c.issuer.starts_with("https:")) {
+                let roots = File::open(&config.ca_certs)
+                    .map_err(|e| e.to_string())
+                    .and_then(|f| {
+                        tls.root_store
+                            .add_pem_file(&mut BufReader::new(f))
+                            .map_err(|_| "not a PEM bundle".to_string())
+                    });
+                if let Err(e) = roots {
+                    error!("Cannot load oidc.ca_certs {}: {}", config.ca_certs, e);
+                    return Err(rocket);
+                }
+            }
+            let oidc = Oidc {
+                config,
+                tls: TlsConnector::from(Arc::new(tls)),
+            };
+            Ok(rocket.manage(oidc))
+        })
+    }
+
+    /// Name of the provider, `None` if none is configured.
+    pub fn name(&self) -> Option<&str> {
+        self.config.as_ref().map(|c| c.name.as_str())
+    }
+
+    /// Where to send the browser to log in for `attempt`.
+    pub async fn authorize_url(&self, attempt: &LoginAttempt) -> Result<String> {
+        let config = self.config.as_ref().ok_or_else(not_configured)?;
+        let metadata = self.discover().await?;
+        let params = [
+            ("response_type", "code"),
+            ("client_id", &config.client_id),
+            ("redirect_uri", &config.redirect_uri),
+            ("scope", &config.scopes),
+            ("state", &attempt.state),
+            ("nonce", &attempt.nonce),
+            ("code_challenge", &attempt.challenge()),
+            ("code_challenge_method", "S256"),
+        ];
+        let sep = if metadata.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        Ok(format!(
+            "{}{}{}",
+            metadata.authorization_endpoint,
+            sep,
+            form_encode(&params)
+        ))
+    }
+
+    /// Redeems the authorization `code` the provider sent back for
+    /// `attempt`, returning the claims of the id token.
+    ///
+    /// The id token comes straight from the token endpoint over TLS, which
+    /// OpenID Connect accepts in place of checking its signature.
+    pub async fn exchange(&self, attempt: &LoginAttempt, code: &str) -> Result<Claims> {
+        let config = self.config.as_ref().ok_or_else(not_configured)?;
+        let metadata = self.discover().await?;
+        let body = form_encode(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &config.redirect_uri),
+            ("code_verifier", &attempt.verifier),
+        ]);
+        let basic = base64::encode(format!(
+            "{}:{}",
+            form_encode_value(&config.client_id),
+            form_encode_value(&config.client_secret)
+        ));
+        let request = Request::builder()
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::AUTHORIZATION, format!("Basic {}", basic));
+        let token: TokenResponse = self
+            .send(&metadata.token_endpoint, request, Body::from(body))
+            .await?;
+        let payload = token
+            .id_token
+            .split('.')
+            .nth(1)
+            .and_then(|p| base64::decode_config(p, base64::URL_SAFE_NO_PAD).ok())
+            .ok_or_else(|| Error::Oidc("Malformed id token.".to_string()))?;
+        let claims: Claims = serde_json::from_slice(&payload)
+            .map_err(|e| Error::Oidc(format!("Malformed id token: {}", e)))?;
+        let audience_ok = match &claims.aud {
+            Audience::One(aud) => *aud == config.client_id,
+            Audience::Many(auds) => auds.contains(&config.client_id),
+        };
+        if claims.iss != config.issuer {
+            Err(Error::Oidc("Id token is from another issuer.".to_string()))
+        } else if !audience_ok {
+            Err(Error::Oidc("Id token is for another client.".to_string()))
+        } else if claims.exp <= now() {
+            Err(Error::Oidc("Id token has expired.".to_string()))
+        } else if claims.nonce.as_deref() != Some(attempt.nonce.as_str()) {
+            Err(Error::Oidc("Id token is for another login.".to_string()))
+        } else {
+            Ok(claims)
+        }
+    }
+
+    async fn discover(&self) -> Result<Metadata> {
+        let config = self.config.as_ref().ok_or_else(not_configured)?;
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        );
+        let metadata: Metadata = self
+            .send(&url, Request::builder().method(Method::GET), Body::empty())
+            .await?;
+        if metadata.issuer != config.issuer {
+            return Err(Error::Oidc(format!(
+                "Provider claims to be {}.",
+                metadata.issuer
+            )));
+        }
+        Ok(metadata)
+    }
+
+    /// Sends `request` to `url` and parses the json it answers with. Plain
+    /// http is only allowed to this machine.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        url: &str,
+        request: hyper::http::request::Builder,
+        body: Body,
+    ) -> Result<T> {
+        let uri: Uri = url
+            .parse()
+            .map_err(|_| Error::Oidc(format!("Invalid url: {}", url)))?;
+        let host = uri.host().unwrap_or_default().to_string();
+        let https = match uri.scheme_str() {
+            Some("https") => true,
+            Some("http") if is_loopback(&host) => false,
+            _ => return Err(Error::Oidc(format!("Refusing to connect to {}.", url))),
+        };
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let target = uri.path_and_query().map_or("/", |p| p.as_str());
+        let request = request
+            .uri(target)
+            .header(header::HOST, uri.authority().map_or("", |a| a.as_str()))
+            .header(header::ACCEPT, "application/json")
+            .body(body)
+            .map_err(|e| Error::Oidc(e.to_string()))?;
+        let tcp = TcpStream::connect((host.as_str(), port)).await?;
+        let (status, bytes) = if https {
+            let name = DNSNameRef::try_from_ascii_str(&host)
+                .map_err(|_| Error::Oidc(format!("Invalid host: {}", host)))?;
+            round_trip(self.tls.connect(name, tcp).await?, request).await?
+        } else {
+            round_trip(tcp, request).await?
+        };
+        if !status.is_success() {
+            return Err(Error::Oidc(format!("{} answered {}.", url, status)));
+        }
+        serde_json::from_slice(&bytes)
+            .map_err(|e| Error::Oidc(format!("Unexpected answer from {}: {}", url, e)))
+    }
+}
+
+fn not_configured() -> Error {
+    Error::Oidc("No provider is configured.".to_string())
+}
+
+async fn round_trip<T>(io: T, request: Request<Body>) -> Result<(hyper::StatusCode, Vec<u8>)>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let http = |e: hyper::Error| Error::Oidc(e.to_string());
+    let (mut sender, connection) = conn::handshake(io).await.map_err(http)?;
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+    let response = sender.send_request(request).await.map_err(http)?;
+    let status = response.status();
+    let bytes = body::to_bytes(response.into_body()).await.map_err(http)?;
+    Ok((status, bytes.to_vec()))
+}
+
+fn is_loopback(host: &str) -> bool {
+    let ip = host
+        .trim_matches(|c| c == '[' || c == ']')
+        .parse::<std::net::IpAddr>();
+    host == "localhost" || matches!(ip, Ok(ip) if ip.is_loopback())
+}
+
+/// Seconds since the epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn form_encode(params: &[(&str, &str)]) -> String {
+    params
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, form_encode_value(v)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn form_encode_value(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,66 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// How long an edit lease lasts unless it is renewed.
+pub const LEASE_TTL: Duration = Duration::from_secs(30);
+
+struct Lease {
+    session: Uuid,
+    expires: Instant,
+}
+
+/// Which session is editing which task.
+///
+/// Leases only live in memory and are never written to the task table, a
+/// client that goes away without releasing its lease lets it expire.
+#[derive(Default)]
+pub struct Presence {
+    leases: Mutex<HashMap<Uuid, Lease>>,
+}
+
+impl Presence {
+    /// Takes or renews the lease on `task` for `session`. Returns `false` if
+    /// another session holds it.
+    pub fn acquire(&self, task: Uuid, session: Uuid) -> bool {
+        let now = Instant::now();
+        let mut leases = self.leases.lock().unwrap();
+        match leases.get(&task) {
+            Some(l) if l.session != session && l.expires > now => false,
+            _ => {
+                leases.insert(
+                    task,
+                    Lease {
+                        session,
+                        expires: now + LEASE_TTL,
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    /// Gives up the lease on `task` if `session` holds it.
+    pub fn release(&self, task: Uuid, session: Uuid) {
+        let mut leases = self.leases.lock().unwrap();
+        if matches!(leases.get(&task), Some(l) if l.session == session) {
+            leases.remove(&task);
+        }
+    }
+
+    /// Tasks currently being edited by sessions other than `session`, callers
+    /// filter out the tasks the user cannot see.
+    pub fn edited_by_others(&self, session: Uuid) -> Vec<Uuid> {
+        let now = Instant::now();
+        let mut leases = self.leases.lock().unwrap();
+        leases.retain(|_, l| l.expires > now);
+        leases
+            .iter()
+            .filter(|(_, l)| l.session != session)
+            .map(|(task, _)| *task)
+            .collect()
+    }
+}
@@ -0,0 +1,262 @@
+use super::auth::AuthUser;
+use rocket::{
+    error,
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Status},
+    outcome::Outcome,
+    request::{self, FromRequest, Request},
+    Build, Response, Rocket, State,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Idle buckets are dropped once this many are tracked.
+const MAX_BUCKETS: usize = 10_000;
+
+/// A token bucket: up to `burst` requests at once, refilled with
+/// `per_minute` requests a minute.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Rate {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl Rate {
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+/// Rates per route class, read from the `rate_limits` table of `Rocket.toml`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    pub read: Rate,
+    pub write: Rate,
+    pub auth: Rate,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            read: Rate {
+                burst: 120,
+                per_minute: 600,
+            },
+            write: Rate {
+                burst: 60,
+                per_minute: 120,
+            },
+            auth: Rate {
+                burst: 10,
+                per_minute: 10,
+            },
+        }
+    }
+}
+
+/// A class of routes sharing one rate.
+pub trait RouteClass: Send + Sync + 'static {
+    const NAME: &'static str;
+
+    fn rate(limits: &RateLimits) -> Rate;
+}
+
+pub enum Read {}
+pub enum Write {}
+pub enum Auth {}
+
+impl RouteClass for Read {
+    const NAME: &'static str = "read";
+
+    fn rate(limits: &RateLimits) -> Rate {
+        limits.read
+    }
+}
+
+impl RouteClass for Write {
+    const NAME: &'static str = "write";
+
+    fn rate(limits: &RateLimits) -> Rate {
+        limits.write
+    }
+}
+
+impl RouteClass for Auth {
+    const NAME: &'static str = "auth";
+
+    fn rate(limits: &RateLimits) -> Rate {
+        limits.auth
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    User(Uuid),
+}
+
+struct Bucket {
+    rate: Rate,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.rate.per_second()).min(f64::from(self.rate.burst));
+        self.updated = now;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= f64::from(self.rate.burst)
+    }
+}
+
+/// Token buckets per client ip and per logged in user.
+///
+/// Buckets only live in memory, every server instance limits on its own.
+pub struct RateLimiter {
+    limits: RateLimits,
+    buckets: Mutex<HashMap<(&'static str, Client), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of `client`, or tells how long until
+    /// one is available.
+    fn take(&self, class: &'static str, rate: Rate, client: Client) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_BUCKETS {
+            buckets.retain(|_, b| {
+                b.refill(now);
+                !b.is_full()
+            });
+        }
+        let bucket = buckets.entry((class, client)).or_insert(Bucket {
+            rate,
+            tokens: f64::from(rate.burst),
+            updated: now,
+        });
+        bucket.refill(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / rate.per_second(),
+            ))
+        }
+    }
+}
+
+/// Seconds until a limited request may be retried, left for the fairing to
+/// turn into a `Retry-After` header.
+struct RetryAfter(Option<u64>);
+
+/// Request guard limiting a route to the rate of its class `C`, fails with
+/// `429` once the client ip or the logged in user is out of tokens.
+///
+/// Anonymous requests are only limited by ip, the login routes only by ip
+/// so that nobody can lock a user out.
+pub struct Limit<C>(PhantomData<C>);
+
+pub type ReadLimit = Limit<Read>;
+pub type WriteLimit = Limit<Write>;
+pub type AuthLimit = Limit<Auth>;
+
+#[rocket::async_trait]
+impl<'r, C: RouteClass> FromRequest<'r> for Limit<C> {
+    type Error = String;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let limiter = match req.guard::<&State<RateLimiter>>().await {
+            Outcome::Success(limiter) => limiter,
+            _ => {
+                return Outcome::Failure((
+                    Status::InternalServerError,
+                    "Rate limiter is not managed.".to_string(),
+                ))
+            }
+        };
+        let rate = C::rate(&limiter.limits);
+        let mut clients = Vec::with_capacity(2);
+        if let Some(ip) = req.client_ip() {
+            clients.push(Client::Ip(ip));
+        }
+        if C::NAME != Auth::NAME {
+            if let Outcome::Success(user) = req.guard::<AuthUser>().await {
+                clients.push(Client::User(*user.id()));
+            }
+        }
+        for client in clients {
+            if let Err(wait) = limiter.take(C::NAME, rate, client) {
+                let secs = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                req.local_cache(|| RetryAfter(Some(secs.max(1))));
+                return Outcome::Failure((
+                    Status::TooManyRequests,
+                    "Too many requests.".to_string(),
+                ));
+            }
+        }
+        Outcome::Success(Self(PhantomData))
+    }
+}
+
+/// Manages the `RateLimiter` and adds `Retry-After` to its `429` responses.
+pub struct RateLimitFairing;
+
+#[rocket::async_trait]
+impl Fairing for RateLimitFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Rate limits",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> rocket::fairing::Result {
+        let limits = if rocket.figment().find_value("rate_limits").is_ok() {
+            match rocket.figment().extract_inner::<RateLimits>("rate_limits") {
+                Ok(limits) => limits,
+                Err(e) => {
+                    error!("Invalid rate_limits: {}", e);
+                    return Err(rocket);
+                }
+            }
+        } else {
+            RateLimits::default()
+        };
+        let rates = [limits.read, limits.write, limits.auth];
+        if rates.iter().any(|r| r.burst == 0 || r.per_minute == 0) {
+            error!("Invalid rate_limits: burst and per_minute must be positive.");
+            return Err(rocket);
+        }
+        Ok(rocket.manage(RateLimiter::new(limits)))
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if res.status() != Status::TooManyRequests {
+            return;
+        }
+        if let RetryAfter(Some(secs)) = req.local_cache(|| RetryAfter(None)) {
+            res.set_header(Header::new("Retry-After", secs.to_string()));
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac, NewMac};
+use rocket::{fairing::AdHoc, Config};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// Keeps signatures of share links apart from anything else signed with the
+/// same secret.
+const CONTEXT: &[u8] = b"todomvc share link";
+
+/// Signs share link tokens with the configured `secret_key`.
+///
+/// A token is the id of the link and its signature, the link itself lives in
+/// the database so that it can expire and be revoked.
+pub struct ShareKey(Vec<u8>);
+
+impl ShareKey {
+    /// Without a configured `secret_key` links only stay valid until the
+    /// server restarts, like private cookies.
+    pub fn fairing() -> AdHoc {
+        AdHoc::on_ignite("Share links", |rocket| async move {
+            let key = match rocket.figment().extract_inner::<String>(Config::SECRET_KEY) {
+                Ok(k) => k.into_bytes(),
+                Err(_) => {
+                    let mut k = vec![0u8; 32];
+                    OsRng.fill_bytes(&mut k);
+                    k
+                }
+            };
+            rocket.manage(ShareKey(key))
+        })
+    }
+
+    fn mac(&self, id: Uuid) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.0).expect("hmac takes keys of any length");
+        mac.update(CONTEXT);
+        mac.update(id.as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, id: Uuid) -> String {
+        let sig = self.mac(id).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            id.to_simple(),
+            base64::encode_config(sig, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// The id of the link behind `token`, if the signature holds.
+    pub fn verify(&self, token: &str) -> Option<Uuid> {
+        let mut parts = token.splitn(2, '.');
+        let id = Uuid::parse_str(parts.next()?).ok()?;
+        let sig = base64::decode_config(parts.next()?, base64::URL_SAFE_NO_PAD).ok()?;
+        self.mac(id).verify(&sig).ok().map(|_| id)
+    }
+}
@@ -0,0 +1,1711 @@
+//! Route tests. They run against the database in `DATABASE_URL`, which must
+//! have all migrations applied, so they are ignored by default:
+//!
+//! ```sh
+//! DATABASE_URL=postgres://... cargo test -- --ignored
+//! ```
+use super::{
+    auth::SESSION_COOKIE,
+    csrf::{CSRF_COOKIE, CSRF_HEADER},
+    db,
+    idempotency::IDEMPOTENCY_KEY_HEADER,
+};
+use hmac::{Hmac, Mac, NewMac};
+use hyper::{server::conn::Http, service::service_fn, Body, Request, Response};
+use rocket::{
+    http::{ContentType, Header, Status},
+    local::asynchronous::Client,
+    serde::json::serde_json::{self, json},
+    tokio::{self, net::TcpListener},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use todomvc_shared::{
+    Attachment, Credentials, Entries, Entry, InviteRequest, ListRequest, NewToken, Priority,
+    Recurrence, Role, SearchHit, ShareLink, ShareRequest, SharedList, Stats, Tag, TagRequest,
+    TaskList, TaskRequest, TokenRequest, UserInfo, STATS_DAYS, STATS_WEEKS,
+};
+use uuid::Uuid;
+
+async fn client() -> Client {
+    let pool = db::establish_connection().expect("DATABASE_URL is not reachable");
+    Client::tracked(super::rocket(pool))
+        .await
+        .expect("valid rocket instance")
+}
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    rmp_serde::to_vec(value).unwrap()
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> T {
+    rmp_serde::from_slice(bytes).unwrap()
+}
+
+/// A client logged in as a freshly registered user.
+async fn user() -> Client {
+    let client = client().await;
+    let username = format!("user-{}", Uuid::new_v4());
+    let cred = Credentials {
+        username: &username,
+        password: "correct horse battery staple",
+    };
+    let status = client
+        .post("/register")
+        .header(ContentType::MsgPack)
+        .body(encode(&cred))
+        .dispatch()
+        .await
+        .status();
+    assert_eq!(status, Status::Ok);
+    client
+}
+
+/// The CSRF header for requests that change something.
+fn csrf(client: &Client) -> Header<'static> {
+    let token = client
+        .cookies()
+        .get(CSRF_COOKIE)
+        .map(|c| c.value().to_string())
+        .expect("no CSRF cookie");
+    Header::new(CSRF_HEADER, token)
+}
+
+async fn me(client: &Client) -> UserInfo {
+    let resp = client.get("/me").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    decode(&resp.into_bytes().await.unwrap())
+}
+
+async fn create(client: &Client, content: &str) -> Entry {
+    let req = TaskRequest {
+        content,
+        id: None,
+        priority: Priority::None,
+        parent: None,
+    };
+    let resp = client
+        .post("/task")
+        .header(csrf(client))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    decode(&resp.into_bytes().await.unwrap())
+}
+
+async fn create_list(client: &Client, name: &str) -> TaskList {
+    let req = ListRequest { name, id: None };
+    let resp = client
+        .post("/lists")
+        .header(csrf(client))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    decode(&resp.into_bytes().await.unwrap())
+}
+
+async fn tasks(client: &Client) -> Entries {
+    let resp = client.get("/tasks").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    decode(&resp.into_bytes().await.unwrap())
+}
+
+async fn task(client: &Client, id: Uuid) -> Option<Entry> {
+    let resp = client.get(format!("/task?id={}", id)).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    decode(&resp.into_bytes().await.unwrap())
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn task_routes_require_login() {
+    let client = client().await;
+    let resp = client.get("/tasks").dispatch().await;
+    assert_eq!(resp.status(), Status::Unauthorized);
+    let resp = client
+        .get(format!("/task?id={}", Uuid::new_v4()))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn users_only_read_their_own_tasks() {
+    let alice = user().await;
+    let bob = user().await;
+    let secret = create(&alice, "alice's secret").await;
+
+    assert!(tasks(&bob).await.iter().all(|e| e.id() != secret.id()));
+    assert!(task(&bob, *secret.id()).await.is_none());
+    assert!(tasks(&alice).await.iter().any(|e| e.id() == secret.id()));
+    assert!(task(&alice, *secret.id()).await.is_some());
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn users_cannot_change_others_tasks() {
+    let alice = user().await;
+    let bob = user().await;
+    let secret = create(&alice, "alice's secret").await;
+    let mut forged = secret.clone();
+    forged.set_content("changed by bob".to_string());
+
+    let resp = bob
+        .put(format!("/task?id={}", secret.id()))
+        .header(csrf(&bob))
+        .header(ContentType::MsgPack)
+        .body(encode(&forged))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+
+    let resp = bob
+        .post("/tasks")
+        .header(csrf(&bob))
+        .header(ContentType::MsgPack)
+        .body(encode(&vec![forged.clone()]))
+        .dispatch()
+        .await;
+    assert_ne!(resp.status(), Status::Ok);
+
+    let req = TaskRequest {
+        content: "changed by bob",
+        id: Some(*secret.id()),
+        priority: Priority::None,
+        parent: None,
+    };
+    let resp = bob
+        .post("/task")
+        .header(csrf(&bob))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_ne!(resp.status(), Status::Ok);
+
+    let resp = bob
+        .delete(format!("/task?id={}", secret.id()))
+        .header(csrf(&bob))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+
+    let kept = task(&alice, *secret.id()).await.expect("task was deleted");
+    assert_eq!(kept.content(), secret.content());
+}
+
+async fn post_with_id(client: &Client, id: Uuid, content: &str) -> Status {
+    let req = TaskRequest {
+        content,
+        id: Some(id),
+        priority: Priority::None,
+        parent: None,
+    };
+    client
+        .post("/task")
+        .header(csrf(client))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn task_ids_taken_by_another_user_conflict() {
+    let alice = user().await;
+    let bob = user().await;
+    let id = Uuid::new_v4();
+    assert_eq!(post_with_id(&alice, id, "alice's").await, Status::Ok);
+    assert_eq!(post_with_id(&bob, id, "bob's").await, Status::Conflict);
+    // a retry of the owner still gets the task back
+    assert_eq!(post_with_id(&alice, id, "alice's").await, Status::Ok);
+
+    // a bulk update touching the task changes nothing at all
+    let theirs = task(&alice, id).await.expect("task was deleted");
+    let mut forged = theirs.clone();
+    forged.set_content("changed by bob".to_string());
+    let mut own = create(&bob, "bob's own").await;
+    own.set_content("renamed by bob".to_string());
+    let resp = bob
+        .post("/tasks")
+        .header(csrf(&bob))
+        .header(ContentType::MsgPack)
+        .body(encode(&vec![own.clone(), forged]))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    assert_eq!(task(&bob, *own.id()).await.unwrap().content(), "bob's own");
+    assert_eq!(task(&alice, id).await.unwrap().content(), theirs.content());
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn due_dates_can_be_set_and_cleared() {
+    let client = user().await;
+    let entry = create(&client, "file taxes").await;
+    assert_eq!(*entry.due_at(), None);
+
+    let due = UNIX_EPOCH + Duration::from_secs(1_632_000_000);
+    let mut updated = entry.clone();
+    updated.set_due_at(Some(due));
+    let resp = client
+        .put(format!("/task?id={}", entry.id()))
+        .header(csrf(&client))
+        .header(ContentType::MsgPack)
+        .body(encode(&updated))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let fetched = task(&client, *entry.id()).await.unwrap();
+    assert_eq!(*fetched.due_at(), Some(due));
+
+    updated.set_due_at(None);
+    let resp = client
+        .put(format!("/task?id={}", entry.id()))
+        .header(csrf(&client))
+        .header(ContentType::MsgPack)
+        .body(encode(&updated))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(*task(&client, *entry.id()).await.unwrap().due_at(), None);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn tasks_filter_and_sort_by_priority() {
+    let client = user().await;
+    for (content, priority) in [
+        ("someday", Priority::Low),
+        ("now", Priority::High),
+        ("whenever", Priority::None),
+        ("soon", Priority::Medium),
+    ] {
+        let req = TaskRequest {
+            content,
+            id: None,
+            priority,
+            parent: None,
+        };
+        let resp = client
+            .post("/task")
+            .header(csrf(&client))
+            .header(ContentType::MsgPack)
+            .body(encode(&req))
+            .dispatch()
+            .await;
+        assert_eq!(resp.status(), Status::Ok);
+        let entry: Entry = decode(&resp.into_bytes().await.unwrap());
+        assert_eq!(*entry.priority(), priority);
+    }
+
+    let resp = client
+        .get("/tasks?priority=medium&sort=priority")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let entries: Entries = decode(&resp.into_bytes().await.unwrap());
+    let contents: Vec<_> = entries.iter().map(|e| e.content().as_str()).collect();
+    assert_eq!(contents, ["now", "soon"]);
+
+    let resp = client.get("/tasks?priority=urgent").dispatch().await;
+    assert_eq!(resp.status(), Status::BadRequest);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn tags_are_assigned_and_filter_tasks() {
+    let alice = user().await;
+    let bob = user().await;
+    let tagged = create(&alice, "buy milk").await;
+    create(&alice, "call mom").await;
+
+    let resp = alice
+        .post("/tags")
+        .header(csrf(&alice))
+        .header(ContentType::MsgPack)
+        .body(encode(&TagRequest { name: " #errands" }))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let tag: Tag = decode(&resp.into_bytes().await.unwrap());
+    assert_eq!(tag.name(), "errands");
+
+    let uri = format!("/task/tag?id={}&tag={}", tagged.id(), tag.id());
+    let resp = alice.put(uri.clone()).header(csrf(&alice)).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    let resp = alice
+        .get(format!("/tasks?tag={}", tag.id()))
+        .dispatch()
+        .await;
+    let entries: Entries = decode(&resp.into_bytes().await.unwrap());
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].id(), tagged.id());
+    assert_eq!(entries[0].tags(), &vec![tag.clone()]);
+
+    // alice's tags are hers alone
+    let other = create(&bob, "bob's task").await;
+    let resp = bob
+        .put(format!("/task/tag?id={}&tag={}", other.id(), tag.id()))
+        .header(csrf(&bob))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+
+    let resp = alice.delete(uri).header(csrf(&alice)).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(task(&alice, *tagged.id()).await.unwrap().tags().is_empty());
+}
+
+async fn create_subtask(client: &Client, content: &str, parent: Uuid) -> (Status, Option<Entry>) {
+    let req = TaskRequest {
+        content,
+        id: None,
+        priority: Priority::None,
+        parent: Some(parent),
+    };
+    let resp = client
+        .post("/task")
+        .header(csrf(client))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    let status = resp.status();
+    let entry = if status == Status::Ok {
+        Some(decode(&resp.into_bytes().await.unwrap()))
+    } else {
+        None
+    };
+    (status, entry)
+}
+
+async fn put_task(client: &Client, entry: &Entry) -> Status {
+    client
+        .put(format!("/task?id={}", entry.id()))
+        .header(csrf(client))
+        .header(ContentType::MsgPack)
+        .body(encode(entry))
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn subtasks_nest_without_cycles() {
+    let client = user().await;
+    let top = create(&client, "move house").await;
+    let (_, packing) = create_subtask(&client, "pack", *top.id()).await;
+    let packing = packing.unwrap();
+    assert_eq!(*packing.parent_id(), Some(*top.id()));
+    let (_, books) = create_subtask(&client, "books", *packing.id()).await;
+    let (_, shelf) = create_subtask(&client, "top shelf", *books.unwrap().id()).await;
+    let (status, _) = create_subtask(&client, "left side", *shelf.unwrap().id()).await;
+    assert_eq!(status, Status::Conflict);
+
+    let resp = client
+        .put(format!(
+            "/task/parent?id={}&parent={}",
+            top.id(),
+            packing.id()
+        ))
+        .header(csrf(&client))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Conflict);
+
+    let resp = client
+        .get(format!("/task/subtasks?id={}", top.id()))
+        .dispatch()
+        .await;
+    let below: Entries = decode(&resp.into_bytes().await.unwrap());
+    let contents: Vec<_> = below.iter().map(|e| e.content().as_str()).collect();
+    assert_eq!(contents, ["pack", "books", "top shelf"]);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn parents_complete_after_their_subtasks() {
+    let client = user().await;
+    let mut top = create(&client, "bake a cake").await;
+    let (_, flour) = create_subtask(&client, "buy flour", *top.id()).await;
+    let mut flour = flour.unwrap();
+
+    top.set_completed(true);
+    assert_eq!(put_task(&client, &top).await, Status::Conflict);
+
+    top.set_completed(false);
+    top.set_auto_complete(true);
+    assert_eq!(put_task(&client, &top).await, Status::Ok);
+    flour.set_completed(true);
+    assert_eq!(put_task(&client, &flour).await, Status::Ok);
+    assert!(*task(&client, *top.id()).await.unwrap().completed());
+
+    // an open subtask reopens it
+    create_subtask(&client, "buy eggs", *top.id()).await;
+    assert!(!*task(&client, *top.id()).await.unwrap().completed());
+}
+
+async fn post_tasks(client: &Client, entries: &[Entry]) -> Status {
+    client
+        .post("/tasks")
+        .header(csrf(client))
+        .header(ContentType::MsgPack)
+        .body(encode(&entries))
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn bulk_updates_complete_parents_after_their_subtasks() {
+    let client = user().await;
+    let mut top = create(&client, "plan the trip").await;
+    let (_, train) = create_subtask(&client, "book the train", *top.id()).await;
+    let mut train = train.unwrap();
+    let mut note = create(&client, "water the plants").await;
+
+    // refused as a whole, like completing it on its own
+    top.set_completed(true);
+    note.set_completed(true);
+    let batch = [note.clone(), top.clone()];
+    assert_eq!(post_tasks(&client, &batch).await, Status::Conflict);
+    assert!(!*task(&client, *top.id()).await.unwrap().completed());
+    assert!(!*task(&client, *note.id()).await.unwrap().completed());
+
+    // done along with its subtasks, in any order
+    train.set_completed(true);
+    let batch = [top.clone(), note.clone(), train.clone()];
+    assert_eq!(post_tasks(&client, &batch).await, Status::Ok);
+    assert!(*task(&client, *top.id()).await.unwrap().completed());
+    assert!(*task(&client, *train.id()).await.unwrap().completed());
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn completing_a_recurring_task_creates_the_next_one() {
+    let client = user().await;
+    let mut chore = create(&client, "water plants").await;
+    // a Monday, 2021-09-20 08:00 UTC
+    let monday = UNIX_EPOCH + Duration::from_secs(1_632_124_800);
+    chore.set_due_at(Some(monday));
+    chore.set_recurrence(Some(Recurrence::Weekly(0)));
+    assert_eq!(put_task(&client, &chore).await, Status::BadRequest);
+
+    // mondays and thursdays
+    chore.set_recurrence(Some(Recurrence::Weekly(0b1001)));
+    assert_eq!(put_task(&client, &chore).await, Status::Ok);
+    chore.set_completed(true);
+    assert_eq!(put_task(&client, &chore).await, Status::Ok);
+    // completing it again changes nothing
+    assert_eq!(put_task(&client, &chore).await, Status::Ok);
+
+    let entries = tasks(&client).await;
+    assert_eq!(entries.len(), 2);
+    let done = entries.iter().find(|e| e.id() == chore.id()).unwrap();
+    assert_eq!(*done.recurrence(), None);
+    let next = entries.iter().find(|e| e.id() != chore.id()).unwrap();
+    assert_eq!(next.content(), "water plants");
+    assert!(!*next.completed());
+    assert_eq!(*next.recurrence(), Some(Recurrence::Weekly(0b1001)));
+    let due = next.due_at().unwrap();
+    let days = due.duration_since(monday).unwrap().as_secs() / (24 * 60 * 60);
+    assert_eq!(days % 7, 0, "due on a monday, was {:?}", due);
+    assert!(due > SystemTime::now());
+
+    let mut bad = next.clone();
+    bad.set_recurrence(Some(Recurrence::AfterCompletion(0)));
+    let resp = client
+        .post("/tasks")
+        .header(csrf(&client))
+        .header(ContentType::MsgPack)
+        .body(encode(&vec![bad]))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    // a parent with open subtasks stays open and does not recur yet
+    let mut parent = next.clone();
+    let (status, _) = create_subtask(&client, "repot", *parent.id()).await;
+    assert_eq!(status, Status::Ok);
+    parent.set_completed(true);
+    for _ in 0..2 {
+        assert_eq!(
+            post_tasks(&client, &[parent.clone()]).await,
+            Status::Conflict
+        );
+    }
+    let entries = tasks(&client).await;
+    assert_eq!(entries.len(), 3);
+    let open = entries.iter().find(|e| e.id() == parent.id()).unwrap();
+    assert!(!*open.completed());
+    assert_eq!(*open.recurrence(), Some(Recurrence::Weekly(0b1001)));
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn notes_are_saved() {
+    let client = user().await;
+    let mut entry = create(&client, "write report").await;
+    let notes = "See [the draft](https://example.com/draft) and [this](javascript:alert(1)).\n\n\
+                 - `<script>`\n- <img src=x onerror=alert(1)>";
+    entry.set_notes(notes.to_string());
+    assert_eq!(put_task(&client, &entry).await, Status::Ok);
+    let fetched = task(&client, *entry.id()).await.unwrap();
+    assert_eq!(fetched.notes(), notes);
+    assert_eq!(fetched.content(), "write report");
+}
+
+async fn search(client: &Client, q: &str) -> Vec<SearchHit> {
+    let resp = client
+        .get(format!("/tasks/search?q={}", q.replace(' ', "+")))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    decode(&resp.into_bytes().await.unwrap())
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn search_ranks_content_over_notes_and_highlights_matches() {
+    let client = user().await;
+    let report = create(&client, "Write the quarterly report").await;
+    let mut call = create(&client, "Call the accountant").await;
+    call.set_notes("Ask about the reports for the audit.".to_string());
+    assert_eq!(put_task(&client, &call).await, Status::Ok);
+    create(&client, "Water the plants").await;
+    let stranger = user().await;
+    create(&stranger, "My own report").await;
+
+    let hits = search(&client, "repo").await;
+    let ids: Vec<_> = hits.iter().map(|h| *h.id()).collect();
+    assert_eq!(ids, vec![*report.id(), *call.id()]);
+    assert!(hits[0].rank() > hits[1].rank());
+    assert!(hits[0].snippet().contains(&("report".to_string(), true)));
+    assert!(hits[1].snippet().contains(&("reports".to_string(), true)));
+    assert_eq!(hits[1].content(), "Call the accountant");
+
+    let hits = search(&client, "quarterly repo").await;
+    assert_eq!(hits.len(), 1);
+    assert!(search(&client, " & ! ").await.is_empty());
+    assert!(search(&stranger, "quarterly").await.is_empty());
+}
+
+/// Uploads `content` as the `file` field of a multipart form.
+async fn upload(
+    client: &Client,
+    task: Uuid,
+    name: &str,
+    content_type: &str,
+    content: &[u8],
+) -> (Status, Option<Attachment>) {
+    let boundary = "attachment-boundary";
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+         Content-Type: {}\r\n\r\n",
+        boundary, name, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(content);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    let resp = client
+        .post(format!("/task/attachments?id={}", task))
+        .header(csrf(client))
+        .header(ContentType::with_params(
+            "multipart",
+            "form-data",
+            ("boundary", boundary),
+        ))
+        .body(body)
+        .dispatch()
+        .await;
+    let status = resp.status();
+    let attachment = if status == Status::Ok {
+        Some(decode(&resp.into_bytes().await.unwrap()))
+    } else {
+        None
+    };
+    (status, attachment)
+}
+
+async fn delete_attachment(client: &Client, id: Uuid) -> Status {
+    client
+        .delete(format!("/attachment?id={}", id))
+        .header(csrf(client))
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn attachments_are_stored_once_per_content() {
+    let client = user().await;
+    let first = create(&client, "fix the login page").await;
+    let second = create(&client, "write the release notes").await;
+    let content = format!("screenshot {}", Uuid::new_v4()).into_bytes();
+    let stored = Path::new("attachments").join(format!("{:x}", Sha256::digest(&content)));
+
+    let (status, a) = upload(&client, *first.id(), "shot.txt", "text/plain", &content).await;
+    assert_eq!(status, Status::Ok);
+    let a = a.unwrap();
+    assert_eq!(a.name(), "shot.txt");
+    assert_eq!(a.size(), content.len() as u64);
+    let (_, b) = upload(&client, *second.id(), "copy.txt", "text/plain", &content).await;
+    let b = b.unwrap();
+    assert!(stored.exists());
+
+    let (status, _) = upload(&client, *first.id(), "x.svg", "image/svg+xml", b"<svg/>").await;
+    assert_eq!(status, Status::UnsupportedMediaType);
+    let stranger = user().await;
+    let (status, _) = upload(&stranger, *first.id(), "y.txt", "text/plain", b"y").await;
+    assert_eq!(status, Status::NotFound);
+
+    let resp = client
+        .get(format!("/task/attachments?id={}", first.id()))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let listed: Vec<Attachment> = decode(&resp.into_bytes().await.unwrap());
+    assert_eq!(listed, vec![a.clone()]);
+
+    let resp = client
+        .get(format!("/attachment?id={}", a.id()))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(
+        resp.headers().get_one("Content-Disposition"),
+        Some("attachment; filename=\"shot.txt\"; filename*=UTF-8''shot.txt")
+    );
+    assert_eq!(resp.into_bytes().await.unwrap(), content);
+    let resp = stranger
+        .get(format!("/attachment?id={}", a.id()))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+
+    // the content stays until the last attachment with it is gone
+    assert_eq!(delete_attachment(&client, *a.id()).await, Status::Ok);
+    assert!(stored.exists());
+    assert_eq!(delete_attachment(&client, *b.id()).await, Status::Ok);
+    assert!(!stored.exists());
+    assert_eq!(delete_attachment(&client, *b.id()).await, Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn attachment_contents_go_with_their_tasks_and_lists() {
+    let client = user().await;
+    let parent = create(&client, "move house").await;
+    let (_, child) = create_subtask(&client, "pack the books", *parent.id()).await;
+    let content = format!("checklist {}", Uuid::new_v4()).into_bytes();
+    let stored = Path::new("attachments").join(format!("{:x}", Sha256::digest(&content)));
+    upload(
+        &client,
+        *child.unwrap().id(),
+        "list.txt",
+        "text/plain",
+        &content,
+    )
+    .await;
+    assert!(stored.exists());
+    let resp = client
+        .delete(format!("/task?id={}", parent.id()))
+        .header(csrf(&client))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(!stored.exists());
+
+    let list = create_list(&client, "moving").await;
+    let req = TaskRequest {
+        content: "book the van",
+        id: None,
+        priority: Priority::None,
+        parent: None,
+    };
+    let resp = client
+        .post(format!("/task?list={}", list.id()))
+        .header(csrf(&client))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    let entry: Entry = decode(&resp.into_bytes().await.unwrap());
+    upload(&client, *entry.id(), "list.txt", "text/plain", &content).await;
+    assert!(stored.exists());
+    let resp = client
+        .delete(format!("/list?id={}", list.id()))
+        .header(csrf(&client))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(!stored.exists());
+}
+
+async fn depend(client: &Client, id: Uuid, on: Uuid) -> Status {
+    client
+        .put(format!("/task/dependency?id={}&on={}", id, on))
+        .header(csrf(client))
+        .dispatch()
+        .await
+        .status()
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn dependencies_block_tasks_without_cycles() {
+    let client = user().await;
+    let mut paint = create(&client, "paint the walls").await;
+    let sand = create(&client, "sand the walls").await;
+    let hang = create(&client, "hang pictures").await;
+    assert_eq!(depend(&client, *hang.id(), *paint.id()).await, Status::Ok);
+    assert_eq!(depend(&client, *paint.id(), *sand.id()).await, Status::Ok);
+    // adding it again changes nothing
+    assert_eq!(depend(&client, *paint.id(), *sand.id()).await, Status::Ok);
+
+    let hang = task(&client, *hang.id()).await.unwrap();
+    assert_eq!(*hang.depends_on(), vec![*paint.id()]);
+    assert!(*hang.blocked());
+    paint.set_completed(true);
+    assert_eq!(put_task(&client, &paint).await, Status::Ok);
+    let paint = task(&client, *paint.id()).await.unwrap();
+    assert!(*paint.blocked());
+    assert!(!*task(&client, *hang.id()).await.unwrap().blocked());
+
+    assert_eq!(
+        depend(&client, *sand.id(), *hang.id()).await,
+        Status::Conflict
+    );
+    assert_eq!(
+        depend(&client, *sand.id(), *sand.id()).await,
+        Status::Conflict
+    );
+    let elsewhere = create_list(&client, "garden").await;
+    let resp = client
+        .put(format!(
+            "/task/list?id={}&list={}",
+            sand.id(),
+            elsewhere.id()
+        ))
+        .header(csrf(&client))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    // moving a task takes it out of the dependencies of its old list
+    assert!(task(&client, *paint.id())
+        .await
+        .unwrap()
+        .depends_on()
+        .is_empty());
+    assert_eq!(
+        depend(&client, *paint.id(), *sand.id()).await,
+        Status::BadRequest
+    );
+
+    let path = format!("/task/dependency?id={}&on={}", hang.id(), paint.id());
+    let resp = client
+        .delete(path.clone())
+        .header(csrf(&client))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let resp = client.delete(path).header(csrf(&client)).dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+
+    // of two tasks waiting for each other at the same time, one is refused
+    for _ in 0..5 {
+        let a = create(&client, "a").await;
+        let b = create(&client, "b").await;
+        let (ab, ba) = tokio::join!(
+            depend(&client, *a.id(), *b.id()),
+            depend(&client, *b.id(), *a.id())
+        );
+        let mut statuses = vec![ab, ba];
+        statuses.sort_by_key(|s| s.code);
+        assert_eq!(statuses, vec![Status::Ok, Status::Conflict]);
+    }
+}
+
+async fn stats(client: &Client) -> Stats {
+    let resp = client.get("/stats").dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    decode(&resp.into_bytes().await.unwrap())
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn stats_count_created_completed_and_open_tasks() {
+    let client = user().await;
+    let mut done = create(&client, "water plants").await;
+    let mut late = create(&client, "return books").await;
+    let waiting = create(&client, "read new books").await;
+    done.set_completed(true);
+    assert_eq!(put_task(&client, &done).await, Status::Ok);
+    late.set_due_at(Some(UNIX_EPOCH + Duration::from_secs(1_632_000_000)));
+    assert_eq!(put_task(&client, &late).await, Status::Ok);
+    assert_eq!(depend(&client, *waiting.id(), *late.id()).await, Status::Ok);
+
+    let s = stats(&client).await;
+    assert_eq!(s.days().len(), STATS_DAYS as usize);
+    assert_eq!(s.weeks().len(), STATS_WEEKS as usize);
+    let today = s.days().last().unwrap();
+    assert_eq!((today.created(), today.completed()), (3, 1));
+    let week = s.weeks().last().unwrap();
+    assert_eq!((week.created(), week.completed()), (3, 1));
+    assert!(s.days()[..STATS_DAYS as usize - 1]
+        .iter()
+        .all(|p| p.created() == 0 && p.completed() == 0));
+    assert_eq!(
+        (s.open(), s.overdue(), s.blocked(), s.completed()),
+        (2, 1, 1, 1)
+    );
+    assert!(s.average_completion().is_some());
+
+    // reopening takes it out of the completed ones
+    done.set_completed(false);
+    assert_eq!(put_task(&client, &done).await, Status::Ok);
+    let s = stats(&client).await;
+    assert_eq!(s.days().last().unwrap().completed(), 0);
+    assert_eq!(s.average_completion(), None);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn idempotency_keys_are_per_user() {
+    let alice = user().await;
+    let bob = user().await;
+    let key = Uuid::new_v4().to_string();
+    let req = TaskRequest {
+        content: "alice's secret",
+        id: None,
+        priority: Priority::None,
+        parent: None,
+    };
+    let resp = alice
+        .post("/task")
+        .header(csrf(&alice))
+        .header(ContentType::MsgPack)
+        .header(Header::new(IDEMPOTENCY_KEY_HEADER, key.clone()))
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let secret: Entry = decode(&resp.into_bytes().await.unwrap());
+
+    let req = TaskRequest {
+        content: "bob's task",
+        id: None,
+        priority: Priority::None,
+        parent: None,
+    };
+    let resp = bob
+        .post("/task")
+        .header(csrf(&bob))
+        .header(ContentType::MsgPack)
+        .header(Header::new(IDEMPOTENCY_KEY_HEADER, key))
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let replayed: Entry = decode(&resp.into_bytes().await.unwrap());
+    assert_ne!(replayed.id(), secret.id());
+    assert_eq!(replayed.content(), "bob's task");
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn idempotency_keys_run_a_request_once() {
+    let client = user().await;
+    let post = |key: &str, content: &'static str, parent: Option<Uuid>| {
+        let req = TaskRequest {
+            content,
+            id: None,
+            priority: Priority::None,
+            parent,
+        };
+        client
+            .post("/task")
+            .header(csrf(&client))
+            .header(ContentType::MsgPack)
+            .header(Header::new(IDEMPOTENCY_KEY_HEADER, key.to_string()))
+            .body(encode(&req))
+    };
+    // concurrent retries run it once, the late one is refused or replayed
+    let key = Uuid::new_v4().to_string();
+    let (first, second) = tokio::join!(
+        post(&key, "raced", None).dispatch(),
+        post(&key, "raced", None).dispatch()
+    );
+    for status in [first.status(), second.status()] {
+        assert!(status == Status::Ok || status == Status::Conflict);
+    }
+    let raced = tasks(&client).await;
+    assert_eq!(raced.iter().filter(|e| e.content() == "raced").count(), 1);
+
+    // a failed request frees its key for the retry
+    let key = Uuid::new_v4().to_string();
+    let resp = post(&key, "orphan", Some(Uuid::new_v4())).dispatch().await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let resp = post(&key, "orphan", None).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn read_only_tokens_cannot_modify_tasks() {
+    let alice = user().await;
+    let secret = create(&alice, "alice's secret").await;
+    let req = TokenRequest {
+        name: "forever",
+        read_only: true,
+        expires_in: Some(u64::MAX),
+    };
+    let resp = alice
+        .post("/tokens")
+        .header(csrf(&alice))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    let req = TokenRequest {
+        name: "backup script",
+        read_only: true,
+        expires_in: None,
+    };
+    let resp = alice
+        .post("/tokens")
+        .header(csrf(&alice))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let token: NewToken = decode(&resp.into_bytes().await.unwrap());
+    let bearer = || Header::new("Authorization", format!("Bearer {}", token.token()));
+
+    let script = Client::untracked(super::rocket(db::establish_connection().unwrap()))
+        .await
+        .unwrap();
+    let resp = script.get("/tasks").header(bearer()).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    let entries: Entries = decode(&resp.into_bytes().await.unwrap());
+    assert!(entries.iter().any(|e| e.id() == secret.id()));
+
+    let resp = script
+        .delete(format!("/task?id={}", secret.id()))
+        .header(bearer())
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    let resp = script.get("/tokens").header(bearer()).dispatch().await;
+    assert_eq!(resp.status(), Status::Forbidden);
+
+    let resp = alice
+        .delete(format!("/token?id={}", token.info().id()))
+        .header(csrf(&alice))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let resp = script.get("/tasks").header(bearer()).dispatch().await;
+    assert_eq!(resp.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn tasks_move_between_own_lists_only() {
+    let alice = user().await;
+    let bob = user().await;
+    let groceries = create_list(&alice, "groceries").await;
+    let bobs = create_list(&bob, "bob's list").await;
+    let milk = create(&alice, "milk").await;
+
+    let resp = alice
+        .put(format!("/task/list?id={}&list={}", milk.id(), bobs.id()))
+        .header(csrf(&alice))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+    let resp = alice
+        .put(format!(
+            "/task/list?id={}&list={}",
+            milk.id(),
+            groceries.id()
+        ))
+        .header(csrf(&alice))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+
+    let resp = alice
+        .get(format!("/tasks?list={}", groceries.id()))
+        .dispatch()
+        .await;
+    let entries: Entries = decode(&resp.into_bytes().await.unwrap());
+    assert!(entries.iter().any(|e| e.id() == milk.id()));
+    let resp = bob
+        .get(format!("/tasks?list={}", groceries.id()))
+        .dispatch()
+        .await;
+    let entries: Entries = decode(&resp.into_bytes().await.unwrap());
+    assert!(entries.is_empty());
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn list_roles_limit_what_members_can_do() {
+    let alice = user().await;
+    let bob = user().await;
+    let alice_info = me(&alice).await;
+    let bob_info = me(&bob).await;
+    let shared = create_list(&alice, "shared").await;
+    let invite = |role| {
+        let req = InviteRequest {
+            username: bob_info.username(),
+            role,
+        };
+        alice
+            .post(format!("/list/members?id={}", shared.id()))
+            .header(csrf(&alice))
+            .header(ContentType::MsgPack)
+            .body(encode(&req))
+    };
+    assert_eq!(invite(Role::Viewer).dispatch().await.status(), Status::Ok);
+    let milk = TaskRequest {
+        content: "milk",
+        id: None,
+        priority: Priority::None,
+        parent: None,
+    };
+    let resp = alice
+        .post(format!("/task?list={}", shared.id()))
+        .header(csrf(&alice))
+        .header(ContentType::MsgPack)
+        .body(encode(&milk))
+        .dispatch()
+        .await;
+    let milk: Entry = decode(&resp.into_bytes().await.unwrap());
+
+    assert!(task(&bob, *milk.id()).await.is_some());
+    let resp = bob
+        .delete(format!("/task?id={}", milk.id()))
+        .header(csrf(&bob))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    let resp = bob
+        .delete(format!(
+            "/list/member?id={}&member={}",
+            shared.id(),
+            alice_info.id()
+        ))
+        .header(csrf(&bob))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+
+    assert_eq!(invite(Role::Editor).dispatch().await.status(), Status::Ok);
+    let mut done = milk.clone();
+    done.set_completed(true);
+    let resp = bob
+        .put(format!("/task?id={}", milk.id()))
+        .header(csrf(&bob))
+        .header(ContentType::MsgPack)
+        .body(encode(&done))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let resp = bob
+        .delete(format!("/list?id={}", shared.id()))
+        .header(csrf(&bob))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+
+    let resp = alice
+        .delete(format!(
+            "/list/member?id={}&member={}",
+            shared.id(),
+            bob_info.id()
+        ))
+        .header(csrf(&alice))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert!(task(&bob, *milk.id()).await.is_none());
+
+    let resp = alice
+        .delete(format!(
+            "/list/member?id={}&member={}",
+            shared.id(),
+            alice_info.id()
+        ))
+        .header(csrf(&alice))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Conflict);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn share_links_are_read_only_and_revocable() {
+    let alice = user().await;
+    let shared = create_list(&alice, "shared").await;
+    let req = TaskRequest {
+        content: "milk",
+        id: None,
+        priority: Priority::None,
+        parent: None,
+    };
+    let resp = alice
+        .post(format!("/task?list={}", shared.id()))
+        .header(csrf(&alice))
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let forever = ShareRequest {
+        expires_in: Some(u64::MAX),
+    };
+    let resp = alice
+        .post(format!("/list/shares?id={}", shared.id()))
+        .header(csrf(&alice))
+        .header(ContentType::MsgPack)
+        .body(encode(&forever))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+    let resp = alice
+        .post(format!("/list/shares?id={}", shared.id()))
+        .header(csrf(&alice))
+        .header(ContentType::MsgPack)
+        .body(encode(&ShareRequest::default()))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let link: ShareLink = decode(&resp.into_bytes().await.unwrap());
+
+    let anon = client().await;
+    let resp = anon
+        .get(format!("/shared?token={}", link.token()))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let list: SharedList = decode(&resp.into_bytes().await.unwrap());
+    assert_eq!(list.name(), "shared");
+    assert!(list.entries().iter().any(|e| e.content() == "milk"));
+    let forged = format!("{}x", link.token());
+    let resp = anon
+        .get(format!("/shared?token={}", forged))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+
+    let resp = alice
+        .delete(format!(
+            "/list/share?id={}&share={}",
+            shared.id(),
+            link.id()
+        ))
+        .header(csrf(&alice))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let resp = anon
+        .get(format!("/shared?token={}", link.token()))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::NotFound);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn login_attempts_are_rate_limited_per_ip() {
+    let client = client().await;
+    let cred = Credentials {
+        username: "nobody",
+        password: "wrong",
+    };
+    let remote = "192.0.2.1:4000".parse().unwrap();
+    let mut limited = None;
+    for _ in 0..100 {
+        let resp = client
+            .post("/login")
+            .remote(remote)
+            .header(ContentType::MsgPack)
+            .body(encode(&cred))
+            .dispatch()
+            .await;
+        if resp.status() == Status::TooManyRequests {
+            limited = Some(resp);
+            break;
+        }
+    }
+    let resp = limited.expect("login attempts were never limited");
+    let retry_after: u64 = resp
+        .headers()
+        .get_one("Retry-After")
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after >= 1);
+
+    let resp = client
+        .post("/login")
+        .remote("192.0.2.2:4000".parse().unwrap())
+        .header(ContentType::MsgPack)
+        .body(encode(&cred))
+        .dispatch()
+        .await;
+    assert_ne!(resp.status(), Status::TooManyRequests);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn sessions_end_on_logout_and_when_disabled() {
+    let alice = user().await;
+    let session = alice.cookies().get_private(SESSION_COOKIE).unwrap();
+    let thief = client().await;
+    let stolen = || thief.get("/me").private_cookie(session.clone()).dispatch();
+    assert_eq!(stolen().await.status(), Status::Ok);
+    let resp = alice.post("/logout").dispatch().await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    assert_eq!(stolen().await.status(), Status::Ok);
+    let resp = alice.post("/logout").header(csrf(&alice)).dispatch().await;
+    assert_eq!(resp.status(), Status::Ok);
+    assert_eq!(stolen().await.status(), Status::Unauthorized);
+
+    let bob = user().await;
+    let id = *me(&bob).await.id();
+    let session = bob.cookies().get_private(SESSION_COOKIE).unwrap();
+    let pool = db::establish_connection().unwrap();
+    db::set_user_disabled(&pool, id, true).await.unwrap();
+    db::set_user_disabled(&pool, id, false).await.unwrap();
+    let resp = thief.get("/me").private_cookie(session).dispatch().await;
+    assert_eq!(resp.status(), Status::Unauthorized);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn logins_and_registrations_do_not_leak_usernames() {
+    let client = client().await;
+    let username = format!("user-{}", Uuid::new_v4());
+    let cred = Credentials {
+        username: &username,
+        password: "correct horse battery staple",
+    };
+    let register = || {
+        client
+            .post("/register")
+            .header(ContentType::MsgPack)
+            .body(encode(&cred))
+            .dispatch()
+    };
+    let (first, second) = tokio::join!(register(), register());
+    let mut statuses = vec![first.status(), second.status()];
+    statuses.sort_by_key(|s| s.code);
+    assert_eq!(statuses, vec![Status::Ok, Status::Conflict]);
+
+    let login = |username| {
+        let cred = Credentials {
+            username,
+            password: "wrong",
+        };
+        client
+            .post("/login")
+            .header(ContentType::MsgPack)
+            .body(encode(&cred))
+            .dispatch()
+    };
+    let resp = login(&username).await;
+    assert_eq!(resp.status(), Status::Unauthorized);
+    let known = resp.into_string().await;
+    let resp = login("nobody at all").await;
+    assert_eq!(resp.status(), Status::Unauthorized);
+    assert_eq!(resp.into_string().await, known);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn session_mutations_need_the_csrf_token() {
+    let alice = user().await;
+    let req = TaskRequest {
+        content: "milk",
+        id: None,
+        priority: Priority::None,
+        parent: None,
+    };
+    let resp = alice
+        .post("/task")
+        .header(ContentType::MsgPack)
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    let resp = alice
+        .post("/task")
+        .header(ContentType::MsgPack)
+        .header(Header::new(CSRF_HEADER, "forged"))
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    assert!(tasks(&alice).await.is_empty());
+
+    let req = TokenRequest {
+        name: "script",
+        read_only: false,
+        expires_in: None,
+    };
+    let resp = alice
+        .post("/tokens")
+        .header(ContentType::MsgPack)
+        .header(csrf(&alice))
+        .body(encode(&req))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+    let token: NewToken = decode(&resp.into_bytes().await.unwrap());
+    let script = Client::untracked(super::rocket(db::establish_connection().unwrap()))
+        .await
+        .unwrap();
+    let resp = script
+        .post("/task")
+        .header(ContentType::MsgPack)
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {}", token.token()),
+        ))
+        .body(encode(&TaskRequest {
+            content: "milk",
+            id: None,
+            priority: Priority::None,
+            parent: None,
+        }))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Ok);
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn admin_routes_require_an_admin() {
+    let alice = user().await;
+    let bob = user().await;
+    let bob_info = me(&bob).await;
+    assert!(!me(&alice).await.admin());
+
+    let resp = alice.get("/admin/users").dispatch().await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    let resp = alice
+        .put(format!(
+            "/admin/user/disabled?id={}&disabled=true",
+            bob_info.id()
+        ))
+        .header(csrf(&alice))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    let resp = alice
+        .delete(format!("/admin/user?id={}", bob_info.id()))
+        .header(csrf(&alice))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::Forbidden);
+    assert_eq!(me(&bob).await.id(), bob_info.id());
+}
+
+const CLIENT_ID: &str = "todomvc";
+const CLIENT_SECRET: &str = "mock secret";
+
+/// What the mock provider knows about a code it handed out.
+struct Grant {
+    challenge: String,
+    nonce: String,
+    sub: String,
+    username: String,
+}
+
+/// An OpenID Connect provider on a local port, redeeming the codes
+/// registered with `grant`.
+#[derive(Clone)]
+struct MockProvider {
+    issuer: String,
+    codes: Arc<Mutex<HashMap<String, Grant>>>,
+}
+
+impl MockProvider {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let provider = MockProvider {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            codes: Arc::default(),
+        };
+        let server = provider.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let provider = server.clone();
+                let service = service_fn(move |req| {
+                    let provider = provider.clone();
+                    async move { Ok::<_, Infallible>(provider.handle(req).await) }
+                });
+                tokio::spawn(Http::new().serve_connection(stream, service));
+            }
+        });
+        provider
+    }
+
+    /// Issues `code` as if the user `sub` had logged in at the provider.
+    fn grant(&self, code: &str, login: &HashMap<String, String>, sub: &str, username: &str) {
+        let grant = Grant {
+            challenge: login["code_challenge"].clone(),
+            nonce: login["nonce"].clone(),
+            sub: sub.to_string(),
+            username: username.to_string(),
+        };
+        self.codes.lock().unwrap().insert(code.to_string(), grant);
+    }
+
+    fn config(&self) -> serde_json::Value {
+        json!({
+            "name": "Mock",
+            "issuer": self.issuer,
+            "client_id": CLIENT_ID,
+            "client_secret": CLIENT_SECRET,
+            "redirect_uri": "http://localhost/oidc/callback",
+        })
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        match req.uri().path() {
+            "/.well-known/openid-configuration" => json_response(json!({
+                "issuer": self.issuer,
+                "authorization_endpoint": format!("{}/authorize", self.issuer),
+                "token_endpoint": format!("{}/token", self.issuer),
+            })),
+            "/token" => self.token(req).await,
+            _ => status_response(404),
+        }
+    }
+
+    async fn token(&self, req: Request<Body>) -> Response<Body> {
+        let basic = format!(
+            "Basic {}",
+            base64::encode(format!(
+                "{}:{}",
+                CLIENT_ID,
+                CLIENT_SECRET.replace(' ', "%20")
+            ))
+        );
+        if req.headers().get("Authorization").map(|h| h.as_bytes()) != Some(basic.as_bytes()) {
+            return status_response(401);
+        }
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        let form = query_pairs(std::str::from_utf8(&body).unwrap());
+        let grant = match form
+            .get("code")
+            .and_then(|c| self.codes.lock().unwrap().remove(c))
+        {
+            Some(grant) => grant,
+            None => return status_response(400),
+        };
+        let challenge = base64::encode_config(
+            Sha256::digest(form["code_verifier"].as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        );
+        if form.get("grant_type").map(String::as_str) != Some("authorization_code")
+            || challenge != grant.challenge
+        {
+            return status_response(400);
+        }
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 60;
+        let claims = json!({
+            "iss": self.issuer,
+            "aud": CLIENT_ID,
+            "sub": grant.sub,
+            "exp": exp,
+            "nonce": grant.nonce,
+            "preferred_username": grant.username,
+        });
+        json_response(json!({
+            "access_token": "unused",
+            "token_type": "Bearer",
+            "id_token": sign_jwt(&claims),
+        }))
+    }
+}
+
+/// An HS256 token keyed with the client secret.
+fn sign_jwt(claims: &serde_json::Value) -> String {
+    let encode = |v: &[u8]| base64::encode_config(v, base64::URL_SAFE_NO_PAD);
+    let signed = format!(
+        "{}.{}",
+        encode(br#"{"alg":"HS256","typ":"JWT"}"#),
+        encode(claims.to_string().as_bytes())
+    );
+    let mut mac = Hmac::<Sha256>::new_varkey(CLIENT_SECRET.as_bytes()).unwrap();
+    mac.update(signed.as_bytes());
+    format!("{}.{}", signed, encode(&mac.finalize().into_bytes()))
+}
+
+fn json_response(value: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap()
+}
+
+fn status_response(status: u16) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Decodes `a=b&c=d`, enough for the values the server sends.
+fn query_pairs(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_at(pair.find('=')?);
+            let value = value[1..].replace("%3A", ":").replace("%2F", "/");
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+async fn oidc_client(provider: &MockProvider) -> Client {
+    let pool = db::establish_connection().expect("DATABASE_URL is not reachable");
+    let figment = rocket::Config::figment().merge(("oidc", provider.config()));
+    Client::tracked(super::custom(figment, pool))
+        .await
+        .expect("valid rocket instance")
+}
+
+/// Starts a login, returning the parameters sent to the provider.
+async fn start_login(client: &Client) -> HashMap<String, String> {
+    let resp = client.get("/oidc/login").dispatch().await;
+    assert_eq!(resp.status(), Status::SeeOther);
+    let location = resp.headers().get_one("Location").unwrap().to_string();
+    let (_, query) = location.split_at(location.find('?').unwrap() + 1);
+    query_pairs(query)
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn oidc_logins_map_the_subject_to_one_user() {
+    let provider = MockProvider::start().await;
+    let sub = Uuid::new_v4().to_string();
+    let username = format!("sso-{}", Uuid::new_v4());
+
+    let client = oidc_client(&provider).await;
+    let login = start_login(&client).await;
+    assert_eq!(login["code_challenge_method"], "S256");
+    provider.grant("first", &login, &sub, &username);
+    let resp = client
+        .get(format!(
+            "/oidc/callback?code=first&state={}",
+            login["state"]
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::SeeOther);
+    let first = me(&client).await;
+    assert_eq!(first.username(), &username);
+
+    // a replayed callback has no login cookie left
+    let resp = client
+        .get(format!(
+            "/oidc/callback?code=first&state={}",
+            login["state"]
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+
+    let again = oidc_client(&provider).await;
+    let login = start_login(&again).await;
+    provider.grant("second", &login, &sub, "renamed");
+    let resp = again
+        .get("/oidc/callback?code=second&state=forged")
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::BadRequest);
+    let login = start_login(&again).await;
+    provider.grant("third", &login, &sub, "renamed");
+    let resp = again
+        .get(format!(
+            "/oidc/callback?code=third&state={}",
+            login["state"]
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(resp.status(), Status::SeeOther);
+    assert_eq!(me(&again).await.id(), first.id());
+}
+
+/// Logs in at a new client as `username` of the provider, subject `sub`.
+async fn oidc_login(provider: &MockProvider, sub: &str, username: &str) -> Client {
+    let client = oidc_client(provider).await;
+    let login = start_login(&client).await;
+    let code = Uuid::new_v4().to_string();
+    provider.grant(&code, &login, sub, username);
+    let status = client
+        .get(format!(
+            "/oidc/callback?code={}&state={}",
+            code, login["state"]
+        ))
+        .dispatch()
+        .await
+        .status();
+    assert_eq!(status, Status::SeeOther);
+    client
+}
+
+#[rocket::async_test]
+#[ignore]
+async fn oidc_first_logins_get_a_free_username() {
+    let provider = MockProvider::start().await;
+    let local = user().await;
+    let taken = me(&local).await.username().clone();
+    let client = oidc_login(&provider, &Uuid::new_v4().to_string(), &taken).await;
+    let info = me(&client).await;
+    assert!(info.username().starts_with(&format!("{}-", taken)));
+    assert_ne!(info.id(), me(&local).await.id());
+
+    // two new subjects wanting one free name at once
+    let wanted = format!("sso-{}", Uuid::new_v4());
+    let (first, second) = (Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+    let (a, b) = tokio::join!(
+        oidc_login(&provider, &first, &wanted),
+        oidc_login(&provider, &second, &wanted)
+    );
+    let (a, b) = (me(&a).await, me(&b).await);
+    assert_ne!(a.id(), b.id());
+    assert!(a.username() == &wanted || b.username() == &wanted);
+    assert_ne!(a.username(), b.username());
+}
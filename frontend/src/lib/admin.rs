@@ -0,0 +1,224 @@
+use super::build_request;
+use anyhow::Error;
+use todomvc_shared::{UserStats, Users};
+use uuid::Uuid;
+use yew::{
+    format::{Binary, MsgPack, Nothing},
+    html,
+    services::{
+        fetch::{FetchTask, Request, Response},
+        FetchService,
+    },
+    Callback, Component, ComponentLink, Html, Properties, ShouldRender,
+};
+
+pub enum Msg {
+    Fetched(Users),
+    SetDisabled(Uuid, bool),
+    Purge(Uuid),
+    Done,
+    Failed(String),
+    Close,
+}
+
+#[derive(Clone, Properties)]
+pub struct Props {
+    /// The logged in admin.
+    pub me: Uuid,
+    pub on_close: Callback<()>,
+}
+
+/// Page listing every account with what it owns, admins can disable
+/// accounts or purge them with all their data.
+pub struct AdminPanel {
+    link: ComponentLink<Self>,
+    props: Props,
+    users: Users,
+    error: Option<String>,
+    ft: Option<FetchTask>,
+}
+
+impl Component for AdminPanel {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut panel = Self {
+            link,
+            props,
+            users: Vec::new(),
+            error: None,
+            ft: None,
+        };
+        panel.fetch_users();
+        panel
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Fetched(users) => {
+                self.ft = None;
+                self.users = users;
+                true
+            }
+            Msg::SetDisabled(id, disabled) => {
+                let uri = format!("/admin/user/disabled?id={}&disabled={}", id, disabled);
+                self.send(build_request("PUT", uri, Nothing));
+                true
+            }
+            Msg::Purge(id) => {
+                let username = match self.users.iter().find(|u| *u.id() == id) {
+                    Some(u) => u.username().clone(),
+                    None => return false,
+                };
+                let question = format!("Delete {} and all their lists and tasks?", username);
+                if !yew::utils::window()
+                    .confirm_with_message(&question)
+                    .unwrap_or(false)
+                {
+                    return false;
+                }
+                self.send(build_request(
+                    "DELETE",
+                    format!("/admin/user?id={}", id),
+                    Nothing,
+                ));
+                true
+            }
+            Msg::Done => {
+                self.error = None;
+                self.fetch_users();
+                true
+            }
+            Msg::Failed(reason) => {
+                self.ft = None;
+                self.error = Some(reason);
+                true
+            }
+            Msg::Close => {
+                self.props.on_close.emit(());
+                false
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        true
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <section class="admin">
+                <header>
+                    <h2>{ "Users" }</h2>
+                    <button class="close" onclick=self.link.callback(|_| Msg::Close)>{ "×" }</button>
+                </header>
+                { self.view_error() }
+                <table>
+                    <thead>
+                        <tr>
+                            <th>{ "User" }</th>
+                            <th>{ "Lists" }</th>
+                            <th>{ "Tasks" }</th>
+                            <th>{ "Storage" }</th>
+                            <th />
+                        </tr>
+                    </thead>
+                    <tbody>
+                        { for self.users.iter().map(|u| self.view_user(u)) }
+                    </tbody>
+                </table>
+            </section>
+        }
+    }
+}
+
+impl AdminPanel {
+    fn view_user(&self, user: &UserStats) -> Html {
+        let id = *user.id();
+        let disabled = user.disabled();
+        let actions = if id == self.props.me {
+            html! { <td /> }
+        } else {
+            html! {
+                <td>
+                    <button onclick=self.link.callback(move |_| Msg::SetDisabled(id, !disabled))>
+                        { if disabled { "Enable" } else { "Disable" } }
+                    </button>
+                    <button onclick=self.link.callback(move |_| Msg::Purge(id))>{ "Purge" }</button>
+                </td>
+            }
+        };
+        let mut name = user.username().clone();
+        if user.admin() {
+            name.push_str(" (admin)");
+        }
+        html! {
+            <tr class=if disabled { "disabled" } else { "" }>
+                <td>{ name }</td>
+                <td>{ user.lists() }</td>
+                <td>{ user.tasks() }</td>
+                <td>{ format_bytes(user.storage_bytes()) }</td>
+                { actions }
+            </tr>
+        }
+    }
+
+    fn view_error(&self) -> Html {
+        match &self.error {
+            Some(error) => html! { <div class="notice">{ error }</div> },
+            None => html! {},
+        }
+    }
+
+    fn fetch_users(&mut self) {
+        let callback = self
+            .link
+            .callback(|resp: Response<MsgPack<Result<Users, Error>>>| {
+                let (meta, MsgPack(data)) = resp.into_parts();
+                match data {
+                    Ok(users) if meta.status.is_success() => Msg::Fetched(users),
+                    _ => Msg::Failed(format!(
+                        "Could not load users, status code: {}",
+                        meta.status.as_u16()
+                    )),
+                }
+            });
+        self.start(FetchService::fetch_binary(
+            build_request("GET", "/admin/users", Nothing),
+            callback,
+        ));
+    }
+
+    fn send<IN: Into<Binary>>(&mut self, request: Request<IN>) {
+        let callback = self.link.callback(|resp: Response<Binary>| {
+            let status = resp.status();
+            if status.is_success() {
+                Msg::Done
+            } else {
+                Msg::Failed(match status.as_u16() {
+                    403 => "Only admins can do that.".to_string(),
+                    404 => "No such user.".to_string(),
+                    code => format!("Something went wrong, status code: {}", code),
+                })
+            }
+        });
+        self.start(FetchService::fetch_binary(request, callback));
+    }
+
+    fn start(&mut self, ft: Result<FetchTask, Error>) {
+        match ft {
+            Ok(ft) => self.ft = Some(ft),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+fn format_bytes(bytes: i64) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KiB", b as f64 / 1024.0),
+        b => format!("{:.1} MiB", b as f64 / (1024.0 * 1024.0)),
+    }
+}
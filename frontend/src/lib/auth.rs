@@ -0,0 +1,229 @@
+use super::build_request;
+use anyhow::Error;
+use todomvc_shared::{Credentials, LoginOptions, UserInfo};
+use yew::{
+    events::KeyboardEvent,
+    format::{MsgPack, Nothing},
+    html,
+    services::{
+        fetch::{FetchTask, Response},
+        FetchService,
+    },
+    Callback, Component, ComponentLink, Html, InputData, Properties, ShouldRender,
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Login,
+    Signup,
+}
+
+pub enum Msg {
+    UpdateUsername(String),
+    UpdatePassword(String),
+    SwitchMode,
+    Submit,
+    Done(UserInfo),
+    Options(LoginOptions),
+    Failed(String),
+}
+
+#[derive(Clone, Properties)]
+pub struct Props {
+    pub on_login: Callback<UserInfo>,
+}
+
+/// Login and signup screen, shown until the user has a session.
+pub struct AuthForm {
+    link: ComponentLink<Self>,
+    props: Props,
+    mode: Mode,
+    username: String,
+    password: String,
+    error: Option<String>,
+    /// Name of the single sign-on provider, if the server has one.
+    sso: Option<String>,
+    ft: Option<FetchTask>,
+    options_ft: Option<FetchTask>,
+}
+
+impl Component for AuthForm {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut form = Self {
+            link,
+            props,
+            mode: Mode::Login,
+            username: String::new(),
+            password: String::new(),
+            error: None,
+            sso: None,
+            ft: None,
+            options_ft: None,
+        };
+        form.fetch_options();
+        form
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::UpdateUsername(val) => {
+                self.username = val;
+                true
+            }
+            Msg::UpdatePassword(val) => {
+                self.password = val;
+                true
+            }
+            Msg::SwitchMode => {
+                self.mode = match self.mode {
+                    Mode::Login => Mode::Signup,
+                    Mode::Signup => Mode::Login,
+                };
+                self.error = None;
+                true
+            }
+            Msg::Submit => {
+                match self.submit() {
+                    Ok(ft) => self.ft = Some(ft),
+                    Err(e) => self.error = Some(e.to_string()),
+                }
+                true
+            }
+            Msg::Done(user) => {
+                self.ft = None;
+                self.password.clear();
+                self.props.on_login.emit(user);
+                false
+            }
+            Msg::Options(options) => {
+                self.options_ft = None;
+                self.sso = options.sso;
+                true
+            }
+            Msg::Failed(reason) => {
+                self.ft = None;
+                self.error = Some(reason);
+                true
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        false
+    }
+
+    fn view(&self) -> Html {
+        let (title, submit, switch) = match self.mode {
+            Mode::Login => ("Log in", "Log in", "Need an account? Sign up"),
+            Mode::Signup => ("Sign up", "Create account", "Have an account? Log in"),
+        };
+        html! {
+            <div class="todomvc-wrapper">
+                <section class="todoapp">
+                    <header class="header">
+                        <h1>{ "todos" }</h1>
+                    </header>
+                    <section class="auth">
+                        <h2>{ title }</h2>
+                        <input
+                            class="new-todo"
+                            placeholder="Username"
+                            value=self.username.clone()
+                            oninput=self.link.callback(|e: InputData| Msg::UpdateUsername(e.value))
+                        />
+                        <input
+                            class="new-todo"
+                            type="password"
+                            placeholder="Password"
+                            value=self.password.clone()
+                            oninput=self.link.callback(|e: InputData| Msg::UpdatePassword(e.value))
+                            onkeypress=self.link.batch_callback(|e: KeyboardEvent| {
+                                if e.key() == "Enter" { Some(Msg::Submit) } else { None }
+                            })
+                        />
+                        { self.view_error() }
+                        { self.view_sso() }
+                        <footer class="footer">
+                            <button class="auth-submit" disabled=self.ft.is_some() onclick=self.link.callback(|_| Msg::Submit)>
+                                { submit }
+                            </button>
+                            <button class="clear-completed" onclick=self.link.callback(|_| Msg::SwitchMode)>
+                                { switch }
+                            </button>
+                        </footer>
+                    </section>
+                </section>
+            </div>
+        }
+    }
+}
+
+impl AuthForm {
+    fn view_error(&self) -> Html {
+        match &self.error {
+            Some(error) => html! { <div class="notice">{ error }</div> },
+            None => html! {},
+        }
+    }
+
+    fn view_sso(&self) -> Html {
+        match (&self.sso, self.mode) {
+            (Some(name), Mode::Login) => html! {
+                <a class="sso" href="/oidc/login">{ format!("Log in with {}", name) }</a>
+            },
+            _ => html! {},
+        }
+    }
+
+    /// Without options the form just offers passwords.
+    fn fetch_options(&mut self) {
+        let callback =
+            self.link
+                .batch_callback(|resp: Response<MsgPack<Result<LoginOptions, Error>>>| {
+                    let (meta, MsgPack(data)) = resp.into_parts();
+                    match data {
+                        Ok(options) if meta.status.is_success() => Some(Msg::Options(options)),
+                        _ => None,
+                    }
+                });
+        self.options_ft =
+            FetchService::fetch_binary(build_request("GET", "/login/options", Nothing), callback)
+                .ok();
+    }
+
+    fn submit(&self) -> Result<FetchTask, Error> {
+        let uri = match self.mode {
+            Mode::Login => "/login",
+            Mode::Signup => "/register",
+        };
+        let cred = Credentials {
+            username: &self.username,
+            password: &self.password,
+        };
+        let request = build_request("POST", uri, MsgPack(&cred));
+        let callback =
+            self.link
+                .callback(move |resp: Response<MsgPack<Result<UserInfo, Error>>>| {
+                    let (meta, MsgPack(data)) = resp.into_parts();
+                    if meta.status.is_success() {
+                        match data {
+                            Ok(user) => Msg::Done(user),
+                            Err(e) => Msg::Failed(e.to_string()),
+                        }
+                    } else {
+                        Msg::Failed(match meta.status.as_u16() {
+                            400 => "Username and password are required.".to_string(),
+                            401 => "Invalid username or password.".to_string(),
+                            403 => "This account is disabled.".to_string(),
+                            409 => "Username is already taken.".to_string(),
+                            code => format!("Something went wrong, status code: {}", code),
+                        })
+                    }
+                });
+        FetchService::fetch_binary(request, callback)
+    }
+}
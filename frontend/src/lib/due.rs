@@ -0,0 +1,73 @@
+//! Due dates are instants, these helpers put them in the browser's time zone.
+use js_sys::Date;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use todomvc_shared::Entry;
+
+/// Milliseconds since the epoch, what `Date` works with.
+pub fn to_millis(at: SystemTime) -> f64 {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as f64
+}
+
+/// `SystemTime::now` is not available in the browser.
+pub fn now() -> f64 {
+    Date::now()
+}
+
+/// Start of the local day `days` after today, in milliseconds.
+pub fn start_of_day(days: u32) -> f64 {
+    let date = Date::new_0();
+    date.set_hours(0);
+    date.set_minutes(0);
+    date.set_seconds(0);
+    date.set_milliseconds(0);
+    date.set_date(date.get_date() + days)
+}
+
+/// Whether an unfinished entry is past its due date.
+pub fn is_overdue(entry: &Entry) -> bool {
+    !*entry.completed() && matches!(*entry.due_at(), Some(at) if to_millis(at) < now())
+}
+
+/// The `YYYY-MM-DDTHH:MM` value of a `datetime-local` input, in local time.
+pub fn to_input_value(at: SystemTime) -> String {
+    let date = Date::new(&to_millis(at).into());
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}",
+        date.get_full_year(),
+        date.get_month() + 1,
+        date.get_date(),
+        date.get_hours(),
+        date.get_minutes()
+    )
+}
+
+/// Reads a `datetime-local` input, `None` if it was cleared.
+pub fn from_input_value(value: &str) -> Option<SystemTime> {
+    // without an offset `Date` takes the value as local time
+    let millis = Date::parse(value);
+    if value.is_empty() || millis.is_nan() || millis < 0.0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_millis(millis as u64))
+}
+
+/// Weekday of a due date, or of today without one, 0 for Monday. In UTC,
+/// like the server works out recurrences.
+pub fn weekday(at: Option<SystemTime>) -> u8 {
+    let date = match at {
+        Some(at) => Date::new(&to_millis(at).into()),
+        None => Date::new_0(),
+    };
+    ((date.get_utc_day() + 6) % 7) as u8
+}
+
+/// How the due date reads in the list.
+pub fn describe(at: SystemTime) -> String {
+    let date = Date::new(&to_millis(at).into());
+    format!(
+        "due {}",
+        date.to_locale_string("default", &Default::default())
+    )
+}
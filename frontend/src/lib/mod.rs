@@ -1,10 +1,33 @@
+mod admin;
+mod auth;
+mod due;
+mod requests;
+mod retry;
+mod share;
+mod shared_list;
 mod state;
+mod stats;
 
+use admin::AdminPanel;
 use anyhow::Error;
-use state::{Filter, State};
-use std::string::ToString;
+use auth::AuthForm;
+use requests::{Job, RequestManager, Target};
+use retry::RetryPolicy;
+use share::ShareDialog;
+use shared_list::SharedListView;
+use state::{Filter, Inverse, Sort, State};
+use stats::StatsPage;
+use std::{
+    collections::HashMap,
+    string::ToString,
+    time::{Duration, SystemTime},
+};
 use strum::IntoEnumIterator;
-use todomvc_shared::{Entries, Entry, TaskRequest};
+use todomvc_shared::{
+    markdown, Attachment, Attachments, Editing, Entries, Entry, ListRequest, Lists, Priority,
+    Recurrence, Role, SearchHits, Tag, TagRequest, Tags, TaskList, TaskRequest, UserInfo,
+    MAX_RECURRENCE_DAYS, MAX_SUBTASK_DEPTH,
+};
 use uuid::Uuid;
 use yew::{
     classes,
@@ -12,11 +35,15 @@ use yew::{
     format::{MsgPack, Nothing},
     html,
     services::{
-        fetch::{FetchTask, Request, Response},
-        ConsoleService, FetchService,
+        fetch::{FetchTask, HeaderMap, Request, Response, StatusCode},
+        interval::IntervalTask,
+        reader::{File, FileData, ReaderService, ReaderTask},
+        timeout::TimeoutTask,
+        ConsoleService, FetchService, IntervalService, TimeoutService,
     },
     web_sys::HtmlInputElement as InputElement,
-    Callback, Classes, Component, ComponentLink, Html, InputData, NodeRef, ShouldRender,
+    Callback, ChangeData, Classes, Component, ComponentLink, Html, InputData, NodeRef,
+    ShouldRender,
 };
 
 type FetchResponse<T> = Response<MsgPack<Result<T, Error>>>;
@@ -30,28 +57,158 @@ pub enum Msg {
     ToggleAll,
     ToggleEdit(usize),
     Toggle(usize),
+    SetDue(usize, Option<SystemTime>),
+    SetRecurrence(usize, Option<Recurrence>),
+    CyclePriority(usize),
+    SetSort(Sort),
+    SetMinPriority(Priority),
+    StartTagging(Option<Uuid>),
+    UpdateTag(String),
+    AddTag,
+    Untag(Uuid, Uuid),
+    SetTagFilter(Option<Uuid>),
+    ToggleCollapsed(Uuid),
+    ToggleAutoComplete(usize),
+    ShowDetails(Option<Uuid>),
+    /// Opens the notes editor, or closes it without saving.
+    EditNotes(bool),
+    UpdateNotes(String),
+    SaveNotes,
+    /// Reads a file to attach to the entry whose details are shown.
+    Attach(File),
+    /// Uploads a file read for an entry, with its content type.
+    Upload(Uuid, String, FileData),
+    /// Deletes an attachment of an entry.
+    Detach(Uuid, Uuid),
+    /// Makes the first entry wait for the second.
+    AddDependency(Uuid, Uuid),
+    RemoveDependency(Uuid, Uuid),
+    UpdateSearch(String),
+    /// Runs the search once typing paused.
+    Search,
+    /// Shows the task with the given id in the given list.
+    OpenHit(Uuid, Uuid),
+    StartSubtask(Option<Uuid>),
+    UpdateSubtask(String),
+    AddSubtask,
     ClearCompleted,
     Focus,
-    FetchError(FetchErrorType),
-    FetchOk(FetchOkType),
+    DismissNotice,
+    Retry(usize),
+    Heartbeat,
+    LoggedIn(UserInfo),
+    Logout,
+    SelectList(Uuid),
+    UpdateList(String),
+    AddList,
+    RemoveList(Uuid),
+    MoveTask(usize, Uuid),
+    Share(Option<Uuid>),
+    ShowAdmin(bool),
+    ShowStats(bool),
+    LeftList(Uuid),
+    EditTaken(usize, Uuid),
+    FetchError(usize, FetchErrorType),
+    FetchOk(usize, FetchOkType),
 }
 
 pub enum FetchErrorType {
-    Meta(u16, Option<String>),
+    /// Status code, its reason and how long the server asked to wait before
+    /// trying again.
+    Meta(u16, Option<String>, Option<Duration>),
     Data(String),
 }
 
+/// Entries carry the list they were fetched for, responses for a list that
+/// is no longer shown are dropped.
 pub enum FetchOkType {
-    Entries(Entries),
-    Entry(Entry),
+    Entries(Uuid, Entries),
+    Entry(Uuid, Entry),
+    Lists(Lists),
+    List(TaskList),
+    Tags(Tags),
+    /// Attachments of the entry with the given id.
+    Attachments(Uuid, Attachments),
+    Attachment(Uuid, Attachment),
+    /// An attachment removed from the entry with the given id.
+    Detached(Uuid, Uuid),
+    /// Hits for the given search.
+    SearchHits(String, SearchHits),
+    /// A tag created to put on the entry with the given id.
+    Tag(Uuid, Tag),
+    /// A change that made the server create tasks in the given list.
+    Reload(Uuid),
+    Editing(Editing),
+    User(UserInfo),
     NoData,
 }
 
+/// How often edit leases are renewed and other sessions' edits refreshed,
+/// well within the server's lease ttl.
+const HEARTBEAT: Duration = Duration::from_secs(10);
+
+/// How long typing has to pause before a search is sent.
+const SEARCH_DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// A request the server has not answered yet, with what it takes to undo
+/// its optimistic changes to `State`.
+struct Pending {
+    desc: &'static str,
+    undo: Vec<Inverse>,
+}
+
 pub struct Model {
     link: ComponentLink<Self>,
     state: State,
     focus_ref: NodeRef,
-    ft: Option<FetchTask>,
+    requests: RequestManager,
+    pending: HashMap<usize, Pending>,
+    next_op: usize,
+    notice: Option<String>,
+    /// `None` until the session is known to belong to a user.
+    user: Option<UserInfo>,
+    /// Whether the session has been checked, the login screen is only shown
+    /// after that.
+    auth_checked: bool,
+    lists: Lists,
+    /// The list whose tasks are in `state`.
+    list: Option<Uuid>,
+    list_value: String,
+    /// Tags of the user, offered when tagging entries.
+    tags: Tags,
+    /// The entry whose tag input is open.
+    tagging: Option<Uuid>,
+    tag_value: String,
+    tag_ref: NodeRef,
+    /// The entry whose details are shown.
+    details: Option<Uuid>,
+    /// Notes being edited in the details, `None` while they are only shown.
+    notes_value: Option<String>,
+    /// Attachments of the entry whose details are shown.
+    attachments: Attachments,
+    /// Reads the file being attached, dropping it aborts the read.
+    reading: Option<ReaderTask>,
+    search_value: String,
+    /// Hits for `search_value`, `None` until they arrive.
+    search_hits: Option<SearchHits>,
+    /// Fires `Msg::Search`, replaced on every keystroke.
+    search_timeout: Option<TimeoutTask>,
+    /// The entry whose subtask input is open.
+    subtask_parent: Option<Uuid>,
+    subtask_value: String,
+    subtask_ref: NodeRef,
+    /// The list whose sharing dialog is open.
+    sharing: Option<Uuid>,
+    /// Whether the admin page is shown instead of the lists.
+    admin: bool,
+    /// Whether the stats page is shown instead of the lists.
+    stats: bool,
+    /// Identifies this client to the edit presence routes.
+    session: Uuid,
+    /// Token of the share link the page was opened with, such a page only
+    /// shows that list and needs no login.
+    share: Option<String>,
+    _heartbeat: IntervalTask,
 }
 
 impl Component for Model {
@@ -60,52 +217,98 @@ impl Component for Model {
 
     fn create(_props: Self::Properties, link: ComponentLink<Self>) -> Self {
         let entries = Vec::new();
-        let _task = match fetch_all_tasks(&link) {
-            Ok(t) => Some(t),
-            Err(e) => {
+        let pending = HashMap::new();
+        let mut requests = RequestManager::new(RetryPolicy::default());
+        // a shared list is loaded by `SharedListView` instead
+        let share = share_token();
+        if share.is_none() {
+            if let Err(e) = requests.submit(0, Target::Read, fetch_me(&link, 0)) {
                 ConsoleService::error(
                     format!("Initial fetch task failed, reason: {}", e.to_string()).as_str(),
                 );
-                None
             }
-        };
+        }
         let state = State {
             entries,
             filter: Filter::All,
+            sort: Sort::Unsorted,
+            min_priority: Priority::None,
+            tag_filter: None,
+            collapsed: Vec::new(),
             value: "".into(),
             edit_value: "".into(),
+            editing: None,
+            edited_elsewhere: Vec::new(),
         };
         let focus_ref = NodeRef::default();
+        let heartbeat = IntervalService::spawn(HEARTBEAT, link.callback(|_| Msg::Heartbeat));
         Self {
             link,
             state,
             focus_ref,
-            ft: _task,
+            requests,
+            pending,
+            next_op: 1,
+            notice: None,
+            user: None,
+            auth_checked: false,
+            lists: Vec::new(),
+            list: None,
+            list_value: String::new(),
+            tags: Vec::new(),
+            tagging: None,
+            tag_value: String::new(),
+            tag_ref: NodeRef::default(),
+            details: None,
+            notes_value: None,
+            attachments: Vec::new(),
+            reading: None,
+            search_value: String::new(),
+            search_hits: None,
+            search_timeout: None,
+            subtask_parent: None,
+            subtask_value: String::new(),
+            subtask_ref: NodeRef::default(),
+            sharing: None,
+            admin: false,
+            stats: false,
+            session: Uuid::new_v4(),
+            share,
+            _heartbeat: heartbeat,
         }
     }
 
     fn update(&mut self, msg: Self::Message) -> ShouldRender {
         match msg {
             Msg::Add => {
-                let content = self.state.value.trim();
+                let content = self.state.value.trim().to_string();
                 let mut sr = false;
-                if !content.is_empty() {
-                    let ft = self.create_task(content);
-                    sr = self.handle_ft(ft, "create task");
+                if let (false, Some(list)) = (content.is_empty(), self.list) {
+                    let op = self.track("create task", Vec::new());
+                    let id = Uuid::new_v4();
+                    let job = self.create_task(list, id, &content, None, op);
+                    sr = self.send(op, Target::Task(id), job);
                 }
                 self.state.value = "".to_string();
                 sr
             }
             Msg::Edit(idx) => {
+                let editing = match self.state.editing {
+                    Some(id) => id,
+                    None => return false,
+                };
+                self.release_edit(editing);
                 let edit_value = self.state.edit_value.trim().to_string();
-                let (e, is_remove) = self.state.complete_edit(idx, edit_value);
-                let (ft, desc) = if is_remove {
-                    (self.remove_task(*e.id()), "remove task")
+                let (e, is_remove, undo) = self.state.complete_edit(idx, edit_value);
+                let (job, op) = if is_remove {
+                    let op = self.track("remove task", undo);
+                    (self.remove_task(*e.id(), op), op)
                 } else {
-                    (self.update_task(&e), "finish edit task")
+                    let op = self.track("finish edit task", undo);
+                    (self.update_task(&e, op), op)
                 };
                 self.state.edit_value = "".to_string();
-                self.handle_ft(ft, desc)
+                self.send(op, Target::Task(*e.id()), job)
             }
             Msg::Update(val) => {
                 self.state.value = val;
@@ -116,32 +319,255 @@ impl Component for Model {
                 true
             }
             Msg::Remove(idx) => {
-                let e = self.state.remove(idx);
-                let id = *e.id();
-                self.handle_ft(self.remove_task(id), "remove task")
+                let (e, undo) = self.state.remove(idx);
+                let op = self.track("remove task", undo);
+                self.send(op, Target::Task(*e.id()), self.remove_task(*e.id(), op))
             }
             Msg::SetFilter(filter) => {
                 self.state.filter = filter;
                 true
             }
             Msg::ToggleEdit(idx) => {
-                self.state.edit_value = self.state.entries[idx].content().clone();
-                self.state.clear_all_edit();
+                if let Some(id) = self.state.editing {
+                    self.release_edit(id);
+                }
                 let e = self.state.toggle_edit(idx);
-                self.handle_ft(self.update_task(&e), "toggle edit")
+                self.state.edit_value = e.content().clone();
+                if self.state.is_editing(&e) {
+                    self.acquire_edit(*e.id());
+                }
+                true
             }
             Msg::ToggleAll => {
                 let status = !self.state.is_all_completed();
-                self.state.toggle_all(status);
-                self.handle_ft(self.update_all_tasks(), "toggle all tasks as completed")
+                let undo = self.state.toggle_all(status);
+                let op = self.track("toggle all tasks as completed", undo);
+                self.send(op, Target::All, self.update_all_tasks(op))
             }
             Msg::Toggle(idx) => {
-                let e = self.state.toggle(idx);
-                self.handle_ft(self.update_task(&e), "toggle completed")
+                if !self.confirm_blocked(idx) {
+                    // puts the checkbox back
+                    return true;
+                }
+                match self.state.toggle(idx) {
+                    Some((e, undo)) => {
+                        let op = self.track("toggle completed", undo);
+                        self.send(op, Target::Task(*e.id()), self.update_task(&e, op))
+                    }
+                    None => {
+                        self.notice = Some("Finish its subtasks first.".to_string());
+                        true
+                    }
+                }
+            }
+            Msg::SetDue(idx, due_at) => {
+                let (e, inverse) = self.state.set_due(idx, due_at);
+                let op = self.track("set due date", vec![inverse]);
+                self.send(op, Target::Task(*e.id()), self.update_task(&e, op))
+            }
+            Msg::SetRecurrence(idx, r) => {
+                let (e, inverse) = self.state.set_recurrence(idx, r);
+                let op = self.track("change recurrence", vec![inverse]);
+                self.send(op, Target::Task(*e.id()), self.update_task(&e, op))
+            }
+            Msg::CyclePriority(idx) => {
+                let (e, inverse) = self.state.cycle_priority(idx);
+                let op = self.track("change priority", vec![inverse]);
+                self.send(op, Target::Task(*e.id()), self.update_task(&e, op))
+            }
+            Msg::SetSort(sort) => {
+                self.state.sort = sort;
+                self.state.sort();
+                true
+            }
+            Msg::SetMinPriority(priority) => {
+                self.state.min_priority = priority;
+                true
+            }
+            Msg::StartTagging(id) => {
+                self.tagging = id;
+                self.tag_value.clear();
+                true
+            }
+            Msg::UpdateTag(val) => {
+                self.tag_value = val;
+                true
+            }
+            Msg::AddTag => {
+                let name = self.tag_value.trim().trim_start_matches('#').to_string();
+                self.tag_value.clear();
+                let id = match self.tagging.take() {
+                    Some(id) if !name.is_empty() => id,
+                    _ => return true,
+                };
+                match self.tags.iter().find(|t| *t.name() == name) {
+                    Some(tag) => self.assign_tag(id, tag.clone()),
+                    None => {
+                        let op = self.track("create tag", Vec::new());
+                        self.send(op, Target::All, self.create_tag(id, &name, op))
+                    }
+                }
+            }
+            Msg::AddDependency(id, on) => match self.state.add_dependency(id, on) {
+                Some((_, inverse)) => {
+                    let op = self.track("add dependency", vec![inverse]);
+                    let job = self.dependency_request("PUT", id, on, op);
+                    self.send(op, Target::Task(id), job)
+                }
+                None => true,
+            },
+            Msg::RemoveDependency(id, on) => match self.state.remove_dependency(id, on) {
+                Some((_, inverse)) => {
+                    let op = self.track("remove dependency", vec![inverse]);
+                    let job = self.dependency_request("DELETE", id, on, op);
+                    self.send(op, Target::Task(id), job)
+                }
+                None => false,
+            },
+            Msg::Untag(id, tag) => match self.state.remove_tag(id, tag) {
+                Some((_, inverse)) => {
+                    let op = self.track("untag task", vec![inverse]);
+                    let job = self.tag_request("DELETE", id, tag, op);
+                    self.send(op, Target::Task(id), job)
+                }
+                None => false,
+            },
+            Msg::SetTagFilter(tag) => {
+                self.state.tag_filter = tag;
+                true
+            }
+            Msg::ToggleCollapsed(id) => {
+                self.state.toggle_collapsed(id);
+                true
+            }
+            Msg::ToggleAutoComplete(idx) => {
+                let (e, inverse) = self.state.toggle_auto_complete(idx);
+                let mut undo = self.state.settle(Some(*e.id()));
+                undo.push(inverse);
+                let op = self.track("toggle auto-complete", undo);
+                self.send(op, Target::Task(*e.id()), self.update_task(&e, op))
+            }
+            Msg::ShowDetails(id) => {
+                self.details = id;
+                self.notes_value = None;
+                self.attachments.clear();
+                self.reading = None;
+                match id {
+                    Some(id) => self.fetch_attachments(id),
+                    None => true,
+                }
+            }
+            Msg::EditNotes(edit) => {
+                let notes = self
+                    .state
+                    .entries
+                    .iter()
+                    .find(|e| Some(*e.id()) == self.details)
+                    .map(|e| e.notes().clone());
+                self.notes_value = notes.filter(|_| edit);
+                true
+            }
+            Msg::UpdateNotes(val) => {
+                self.notes_value = Some(val);
+                true
+            }
+            Msg::SaveNotes => {
+                let (id, notes) = match (self.details, self.notes_value.take()) {
+                    (Some(id), Some(notes)) => (id, notes),
+                    _ => return false,
+                };
+                match self.state.set_notes(id, notes) {
+                    Some((e, inverse)) => {
+                        let op = self.track("save notes", vec![inverse]);
+                        self.send(op, Target::Task(id), self.update_task(&e, op))
+                    }
+                    None => true,
+                }
+            }
+            Msg::Attach(file) => {
+                let id = match self.details {
+                    Some(id) => id,
+                    None => return false,
+                };
+                let content_type = file.type_();
+                let callback = self
+                    .link
+                    .callback(move |data| Msg::Upload(id, content_type.clone(), data));
+                match ReaderService::read_file(file, callback) {
+                    Ok(task) => self.reading = Some(task),
+                    Err(e) => {
+                        ConsoleService::error(format!("Cannot read file: {}", e).as_str());
+                        self.notice = Some("Could not read the file.".to_string());
+                    }
+                }
+                true
+            }
+            Msg::Upload(id, content_type, data) => {
+                self.reading = None;
+                let op = self.track("upload attachment", Vec::new());
+                let job = self.upload_attachment(id, &content_type, data, op);
+                self.send(op, Target::Task(id), job)
+            }
+            Msg::Detach(id, attachment) => {
+                let op = self.track("delete attachment", Vec::new());
+                let job = self.delete_attachment(id, attachment, op);
+                self.send(op, Target::Task(id), job)
+            }
+            Msg::UpdateSearch(val) => {
+                self.search_value = val;
+                self.search_hits = None;
+                self.search_timeout = if self.search_value.trim().is_empty() {
+                    None
+                } else {
+                    let callback = self.link.callback(|_| Msg::Search);
+                    Some(TimeoutService::spawn(SEARCH_DEBOUNCE, callback))
+                };
+                true
+            }
+            Msg::Search => {
+                self.search_timeout = None;
+                let op = self.next_op();
+                self.send(op, Target::Read, self.search(op))
+            }
+            Msg::OpenHit(list, id) => {
+                self.search_value.clear();
+                self.search_hits = None;
+                self.search_timeout = None;
+                // the task could be hidden by the filters
+                self.state.filter = Filter::All;
+                self.state.min_priority = Priority::None;
+                self.state.tag_filter = None;
+                if self.list != Some(list) {
+                    self.select_list(list);
+                }
+                self.link.send_message(Msg::ShowDetails(Some(id)));
+                true
+            }
+            Msg::StartSubtask(parent) => {
+                self.subtask_parent = parent;
+                self.subtask_value.clear();
+                true
+            }
+            Msg::UpdateSubtask(val) => {
+                self.subtask_value = val;
+                true
+            }
+            Msg::AddSubtask => {
+                let content = self.subtask_value.trim().to_string();
+                self.subtask_value.clear();
+                let parent = match (self.subtask_parent.take(), self.list) {
+                    (Some(parent), Some(list)) if !content.is_empty() => (parent, list),
+                    _ => return true,
+                };
+                let op = self.track("create subtask", Vec::new());
+                let id = Uuid::new_v4();
+                let job = self.create_task(parent.1, id, &content, Some(parent.0), op);
+                self.send(op, Target::Task(id), job)
             }
             Msg::ClearCompleted => {
-                self.state.clear_completed();
-                self.handle_ft(self.update_all_tasks(), "clear all completed tasks")
+                let undo = self.state.clear_completed();
+                let op = self.track("clear all completed tasks", undo);
+                self.send(op, Target::All, self.update_all_tasks(op))
             }
             Msg::Focus => {
                 if let Some(input) = self.focus_ref.cast::<InputElement>() {
@@ -149,28 +575,215 @@ impl Component for Model {
                 }
                 true
             }
-            Msg::FetchOk(t) => match t {
-                FetchOkType::Entries(es) => {
-                    ConsoleService::info("Fetch entries success.");
-                    self.state.entries = es;
-                    true
+            Msg::DismissNotice => {
+                self.notice = None;
+                true
+            }
+            Msg::FetchOk(op, t) => {
+                self.settle(op);
+                self.pending.remove(&op);
+                match t {
+                    FetchOkType::Entries(list, es) => {
+                        ConsoleService::info("Fetch entries success.");
+                        if self.list != Some(list) {
+                            return false;
+                        }
+                        self.state.entries = es;
+                        self.state.sort();
+                        true
+                    }
+                    FetchOkType::Entry(list, e) => {
+                        ConsoleService::info("Create entry success.");
+                        if self.list != Some(list) {
+                            return false;
+                        }
+                        let parent = *e.parent_id();
+                        self.state.entries.push(e);
+                        // the server reopened the parents of a new subtask too
+                        self.state.settle(parent);
+                        self.state.sort();
+                        true
+                    }
+                    FetchOkType::Lists(lists) => {
+                        self.lists = lists;
+                        match self.list {
+                            Some(id) if self.lists.iter().any(|l| *l.id() == id) => true,
+                            _ => match self.lists.first() {
+                                Some(l) => self.select_list(*l.id()),
+                                None => true,
+                            },
+                        }
+                    }
+                    FetchOkType::List(list) => {
+                        let id = *list.id();
+                        self.lists.push(list);
+                        self.select_list(id)
+                    }
+                    FetchOkType::Tags(tags) => {
+                        self.tags = tags;
+                        true
+                    }
+                    FetchOkType::Tag(id, tag) => {
+                        if !self.tags.contains(&tag) {
+                            self.tags.push(tag.clone());
+                            self.tags.sort_by(|a, b| a.name().cmp(b.name()));
+                        }
+                        self.assign_tag(id, tag)
+                    }
+                    FetchOkType::Attachments(id, attachments) => {
+                        if self.details != Some(id) {
+                            return false;
+                        }
+                        self.attachments = attachments;
+                        true
+                    }
+                    FetchOkType::Attachment(id, attachment) => {
+                        if self.details != Some(id) {
+                            return false;
+                        }
+                        self.attachments.push(attachment);
+                        true
+                    }
+                    FetchOkType::Detached(id, attachment) => {
+                        if self.details != Some(id) {
+                            return false;
+                        }
+                        self.attachments.retain(|a| *a.id() != attachment);
+                        true
+                    }
+                    FetchOkType::SearchHits(q, hits) => {
+                        if q != self.search_value {
+                            return false;
+                        }
+                        self.search_hits = Some(hits);
+                        true
+                    }
+                    FetchOkType::Reload(list) => self.reload_tasks(list),
+                    FetchOkType::Editing(ids) => {
+                        self.state.edited_elsewhere = ids;
+                        true
+                    }
+                    FetchOkType::User(user) => self.logged_in(user),
+                    FetchOkType::NoData => {
+                        ConsoleService::info("Fetch success");
+                        false
+                    }
                 }
-                FetchOkType::Entry(e) => {
-                    ConsoleService::info("Create entry success.");
-                    self.state.entries.push(e);
-                    true
+            }
+            Msg::LoggedIn(user) => self.logged_in(user),
+            Msg::Logout => {
+                let op = self.next_op();
+                let callback = self.fetch_callback(op);
+                let job = Job::new("POST", true, move || {
+                    let request = build_request("POST", "/logout", Nothing);
+                    FetchService::fetch_binary(request, callback.clone())
+                });
+                self.send(op, Target::All, job);
+                self.logged_out()
+            }
+            Msg::SelectList(id) => self.select_list(id),
+            Msg::UpdateList(val) => {
+                self.list_value = val;
+                true
+            }
+            Msg::AddList => {
+                let name = self.list_value.trim().to_string();
+                self.list_value.clear();
+                if name.is_empty() {
+                    return true;
                 }
-                FetchOkType::NoData => {
-                    ConsoleService::info("Fetch success");
-                    false
+                let op = self.track("create list", Vec::new());
+                self.send(op, Target::All, self.create_list(&name, op))
+            }
+            Msg::RemoveList(id) => {
+                self.lists.retain(|l| *l.id() != id);
+                let op = self.track("remove list", Vec::new());
+                let sr = self.send(op, Target::All, self.remove_list(id, op));
+                if self.list == Some(id) {
+                    self.list = None;
+                    match self.lists.first() {
+                        Some(l) => self.select_list(*l.id()),
+                        None => self.fetch_lists(),
+                    }
+                } else {
+                    sr
+                }
+            }
+            Msg::Share(list) => {
+                self.sharing = list;
+                true
+            }
+            Msg::ShowAdmin(admin) => {
+                self.admin = admin;
+                true
+            }
+            Msg::ShowStats(stats) => {
+                self.stats = stats;
+                true
+            }
+            Msg::LeftList(id) => {
+                self.sharing = None;
+                self.lists.retain(|l| *l.id() != id);
+                if self.list == Some(id) {
+                    self.list = None;
+                }
+                self.fetch_lists()
+            }
+            Msg::MoveTask(idx, list) => {
+                let (e, undo) = self.state.remove(idx);
+                let op = self.track("move task", undo);
+                self.send(op, Target::Task(*e.id()), self.move_task(*e.id(), list, op))
+            }
+            Msg::Heartbeat => {
+                if self.user.is_none() {
+                    return false;
+                }
+                if let Some(id) = self.state.editing {
+                    self.acquire_edit(id);
+                }
+                let op = self.next_op();
+                self.send(op, Target::Read, self.fetch_edits(op))
+            }
+            Msg::EditTaken(op, id) => {
+                self.settle(op);
+                if !self.state.edited_elsewhere.contains(&id) {
+                    self.state.edited_elsewhere.push(id);
+                }
+                self.notice = Some("This task is being edited by another session.".to_string());
+                true
+            }
+            Msg::Retry(op) => {
+                if let Err(e) = self.requests.restart(op) {
+                    self.fail_to_start(op, e);
+                    self.settle(op);
+                }
+                true
+            }
+            Msg::FetchError(op, kind) => {
+                if let FetchErrorType::Meta(401, ..) = kind {
+                    self.settle(op);
+                    self.pending.remove(&op);
+                    return self.logged_out();
+                }
+                if let FetchErrorType::Meta(code, _, retry_after) = kind {
+                    let callback = self.link.callback(move |_| Msg::Retry(op));
+                    if let Some(delay) = self.requests.retry(op, code, retry_after, callback) {
+                        ConsoleService::warn(
+                            format!(
+                                "Got status code: {}, retrying in {} ms.",
+                                code,
+                                delay.as_millis()
+                            )
+                            .as_str(),
+                        );
+                        return true;
+                    }
                 }
-            },
-            Msg::FetchError(kind) => {
                 match kind {
                     FetchErrorType::Data(reason) => ConsoleService::error(
                         format!("Error fetching data, reason: {}", reason).as_str(),
                     ),
-                    FetchErrorType::Meta(code, reason) => ConsoleService::error(
+                    FetchErrorType::Meta(code, reason, _) => ConsoleService::error(
                         format!(
                             "Got status code: {}, reason: {}",
                             code,
@@ -182,7 +795,9 @@ impl Component for Model {
                         .as_str(),
                     ),
                 };
-                false
+                self.fail(op);
+                self.settle(op);
+                true
             }
         }
     }
@@ -191,7 +806,43 @@ impl Component for Model {
         false
     }
 
+    fn rendered(&mut self, _first_render: bool) {
+        // these inputs only exist once `StartTagging` or `StartSubtask` is
+        // rendered
+        if let Some(input) = self.tag_ref.cast::<InputElement>() {
+            if self.tag_value.is_empty() {
+                let _ = input.focus();
+            }
+        }
+        if let Some(input) = self.subtask_ref.cast::<InputElement>() {
+            if self.subtask_value.is_empty() {
+                let _ = input.focus();
+            }
+        }
+    }
+
     fn view(&self) -> Html {
+        if let Some(token) = &self.share {
+            return view_shared(token);
+        }
+        let user = match &self.user {
+            Some(user) => user,
+            None => return self.view_auth(),
+        };
+        if self.admin && user.admin() {
+            return self.view_admin(user);
+        }
+        if self.stats {
+            return self.view_stats();
+        }
+        let shows = self.state.shows();
+        let shown: Vec<_> = self
+            .state
+            .entries
+            .iter()
+            .filter(|e| shows(e))
+            .enumerate()
+            .collect();
         let hidden_class = if self.state.entries.is_empty() {
             "hidden"
         } else {
@@ -199,11 +850,15 @@ impl Component for Model {
         };
         html! {
             <div class="todomvc-wrapper">
+                { self.view_lists() }
+                { self.view_share(user) }
                 <section class="todoapp">
                     <header class="header">
                         <h1>{ "todos" }</h1>
+                        { self.view_search() }
                         { self.view_input() }
                     </header>
+                    { self.view_notice() }
                     <section class=classes!("main", hidden_class)>
                         <input
                             type="checkbox"
@@ -214,7 +869,7 @@ impl Component for Model {
                         />
                         <label for="toggle-all" />
                         <ul class="todo-list">
-                            { for self.state.entries.iter().filter(|e| self.state.filter.fits(e)).enumerate().map(|e| self.view_entry(e)) }
+                            { for shown.iter().filter(|(_, e)| is_root(&shown, e)).map(|e| self.view_entry(&shown, *e)) }
                         </ul>
                     </section>
                     <footer class=classes!("footer", hidden_class)>
@@ -222,15 +877,23 @@ impl Component for Model {
                             <strong>{ self.state.total() }</strong>
                             { " item(s) left" }
                         </span>
+                        { self.view_sync_status() }
                         <ul class="filters">
                             { for Filter::iter().map(|flt| self.view_filter(flt)) }
                         </ul>
+                        { self.view_sort() }
                         <button class="clear-completed" onclick=self.link.callback(|_| Msg::ClearCompleted)>
                             { format!("Clear completed ({})", self.state.total_completed()) }
                         </button>
                     </footer>
                 </section>
                 <footer class="info">
+                    <p>
+                        { format!("Logged in as {} ", user.username()) }
+                        <a href="#" onclick=self.link.callback(|_| Msg::Logout)>{ "(log out)" }</a>
+                        <a href="#" onclick=self.link.callback(|_| Msg::ShowStats(true))>{ " (stats)" }</a>
+                        { self.view_admin_link(user) }
+                    </p>
                     <p>{ "Double-click to edit a todo" }</p>
                     <p>{ "Written by " }<a href="https://github.com/DenisKolodin/" target="_blank">{ "Denis Kolodin" }</a></p>
                     <p>{ "Part of " }<a href="http://todomvc.com/" target="_blank">{ "TodoMVC" }</a></p>
@@ -259,7 +922,109 @@ impl Model {
         }
     }
 
+    /// Pickers for the order of the entries and the lowest priority shown.
+    fn view_sort(&self) -> Html {
+        let sort = self.state.sort;
+        let min_priority = self.state.min_priority;
+        html! {
+            <span class="sort">
+                <select
+                    title="Sort by"
+                    onchange=self.link.batch_callback(|e: ChangeData| match e {
+                        ChangeData::Select(el) => el.value().parse().ok().map(Msg::SetSort),
+                        _ => None,
+                    })
+                >
+                    { for Sort::iter().map(|s| html! {
+                        <option value=s.to_string() selected=s == sort>{ s }</option>
+                    }) }
+                </select>
+                <select
+                    title="Lowest priority shown"
+                    onchange=self.link.batch_callback(|e: ChangeData| match e {
+                        ChangeData::Select(el) => Priority::parse(&el.value()).map(Msg::SetMinPriority),
+                        _ => None,
+                    })
+                >
+                    { for Priority::ALL.iter().map(|p| html! {
+                        <option value=p.as_str() selected=*p == min_priority>
+                            { if *p == Priority::None { "Any priority".to_string() } else { format!("{} and up", p.as_str()) } }
+                        </option>
+                    }) }
+                </select>
+                <select
+                    title="Only tasks tagged"
+                    onchange=self.link.batch_callback(|e: ChangeData| match e {
+                        ChangeData::Select(el) => Some(Msg::SetTagFilter(el.value().parse().ok())),
+                        _ => None,
+                    })
+                >
+                    <option value="" selected=self.state.tag_filter.is_none()>{ "Any tag" }</option>
+                    { for self.tags.iter().map(|t| html! {
+                        <option value=t.id().to_string() selected=self.state.tag_filter == Some(*t.id())>
+                            { format!("#{}", t.name()) }
+                        </option>
+                    }) }
+                </select>
+            </span>
+        }
+    }
+
+    /// Search box with the hits for what was typed below it.
+    fn view_search(&self) -> Html {
+        let results = match &self.search_hits {
+            None => html! {},
+            Some(hits) if hits.is_empty() => html! {
+                <ul class="search-results"><li class="no-hits">{ "No matching tasks." }</li></ul>
+            },
+            Some(hits) => html! {
+                <ul class="search-results">
+                    { for hits.iter().map(|hit| {
+                        let (list, id) = (*hit.list_id(), *hit.id());
+                        let list_name = match self.lists.iter().find(|l| *l.id() == list) {
+                            Some(l) if self.list != Some(list) => html! {
+                                <span class="hit-list">{ l.name() }</span>
+                            },
+                            _ => html! {},
+                        };
+                        html! {
+                            <li onclick=self.link.callback(move |_| Msg::OpenHit(list, id))>
+                                <span class="hit-content">{ hit.content() }</span>
+                                { list_name }
+                                <p class="snippet">
+                                    { for hit.snippet().iter().map(|(text, matched)| if *matched {
+                                        html! { <mark>{ text }</mark> }
+                                    } else {
+                                        html! { { text } }
+                                    }) }
+                                </p>
+                            </li>
+                        }
+                    }) }
+                </ul>
+            },
+        };
+        html! {
+            <div class="search">
+                <input
+                    class="search-input"
+                    type="search"
+                    placeholder="Search tasks"
+                    value=self.search_value.clone()
+                    oninput=self.link.callback(|e: InputData| Msg::UpdateSearch(e.value))
+                    onkeydown=self.link.batch_callback(|e: KeyboardEvent| {
+                        if e.key() == "Escape" { Some(Msg::UpdateSearch(String::new())) } else { None }
+                    })
+                />
+                { results }
+            </div>
+        }
+    }
+
     fn view_input(&self) -> Html {
+        if !self.can_edit() {
+            return html! { <p class="read-only">{ "You can only view this list." }</p> };
+        }
         html! {
             // You can use standard Rust comments. One line:
             // <li></li>
@@ -280,16 +1045,29 @@ impl Model {
         }
     }
 
-    fn view_entry(&self, (idx, entry): (usize, &Entry)) -> Html {
+    /// An entry with its shown subtasks nested below it, `shown` are all
+    /// shown entries with their index.
+    fn view_entry(&self, shown: &[(usize, &Entry)], (idx, entry): (usize, &Entry)) -> Html {
         let mut class = Classes::from("todo");
-        if *entry.editing() {
+        if self.state.is_editing(entry) {
             class.push(" editing");
         }
         if *entry.completed() {
             class.push(" completed");
         }
+        if due::is_overdue(entry) {
+            class.push(" overdue");
+        }
+        let onkeydown = self.link.batch_callback(move |e: KeyboardEvent| {
+            // only when the entry itself has focus, not one of its inputs
+            if e.key() == "p" && e.target() == e.current_target() {
+                Some(Msg::CyclePriority(idx))
+            } else {
+                None
+            }
+        });
         html! {
-            <li class=class>
+            <li class=class tabindex="0" onkeydown=onkeydown>
                 <div class="view">
                     <input
                         type="checkbox"
@@ -297,16 +1075,649 @@ impl Model {
                         checked=*entry.completed()
                         onclick=self.link.callback(move |_| Msg::Toggle(idx))
                     />
+                    { self.view_priority((idx, entry)) }
+                    { self.view_blocked(entry) }
                     <label ondblclick=self.link.callback(move |_| Msg::ToggleEdit(idx))>{ entry.content() }</label>
+                    { self.view_edited_elsewhere(entry) }
+                    { self.view_due((idx, entry)) }
+                    { self.view_move(idx) }
+                    { self.view_details_toggle(entry) }
                     <button class="destroy" onclick=self.link.callback(move |_| Msg::Remove(idx)) />
+                    { self.view_subtask_controls((idx, entry)) }
+                    { self.view_recurrence((idx, entry)) }
+                    { self.view_tags(entry) }
                 </div>
                 { self.view_entry_edit_input((idx, entry)) }
+                { self.view_details(entry) }
+                { self.view_subtasks(shown, entry) }
+            </li>
+        }
+    }
+
+    fn view_details_toggle(&self, entry: &Entry) -> Html {
+        let id = *entry.id();
+        let open = self.details == Some(id);
+        let mut class = Classes::from("details-toggle");
+        if !entry.notes().is_empty() {
+            class.push(" has-notes");
+        }
+        let msg = if open { None } else { Some(id) };
+        html! {
+            <button class=class title="Details" onclick=self.link.callback(move |_| Msg::ShowDetails(msg))>
+                { if open { "▴" } else { "…" } }
+            </button>
+        }
+    }
+
+    /// The notes of the entry whose details are shown, rendered or in an
+    /// editor.
+    fn view_details(&self, entry: &Entry) -> Html {
+        if self.details != Some(*entry.id()) {
+            return html! {};
+        }
+        let notes = match &self.notes_value {
+            Some(value) => html! {
+                <>
+                    <textarea
+                        class="notes-input"
+                        placeholder="Notes, in markdown"
+                        value=value.clone()
+                        oninput=self.link.callback(|e: InputData| Msg::UpdateNotes(e.value))
+                    />
+                    <button onclick=self.link.callback(|_| Msg::SaveNotes)>{ "Save" }</button>
+                    <button onclick=self.link.callback(|_| Msg::EditNotes(false))>{ "Cancel" }</button>
+                </>
+            },
+            None => {
+                let edit = if self.can_edit() {
+                    html! { <button onclick=self.link.callback(|_| Msg::EditNotes(true))>{ "Edit notes" }</button> }
+                } else {
+                    html! {}
+                };
+                let rendered = if entry.notes().is_empty() {
+                    html! { <p class="no-notes">{ "No notes." }</p> }
+                } else {
+                    view_markdown(entry.notes())
+                };
+                html! {
+                    <>
+                        { rendered }
+                        { edit }
+                    </>
+                }
+            }
+        };
+        html! {
+            <div class="details">
+                { notes }
+                { self.view_dependencies(entry) }
+                { self.view_attachments(entry) }
+            </div>
+        }
+    }
+
+    /// The entries the entry whose details are shown waits for, with a
+    /// picker adding one of the same list.
+    fn view_dependencies(&self, entry: &Entry) -> Html {
+        let id = *entry.id();
+        let can_edit = self.can_edit();
+        let prerequisites = self
+            .state
+            .entries
+            .iter()
+            .filter(|e| entry.depends_on().contains(e.id()));
+        let mut candidates = self
+            .state
+            .entries
+            .iter()
+            .filter(|e| {
+                *e.id() != id
+                    && !entry.depends_on().contains(e.id())
+                    && !self.state.depends_transitively(*e.id(), id)
+            })
+            .peekable();
+        let picker = if can_edit && candidates.peek().is_some() {
+            html! {
+                <select
+                    class="add-dependency"
+                    onchange=self.link.batch_callback(move |e: ChangeData| match e {
+                        ChangeData::Select(el) => el.value().parse().ok().map(|on| Msg::AddDependency(id, on)),
+                        _ => None,
+                    })
+                >
+                    <option value="" selected=true>{ "Blocked by..." }</option>
+                    { for candidates.map(|e| html! { <option value=e.id().to_string()>{ e.content() }</option> }) }
+                </select>
+            }
+        } else {
+            html! {}
+        };
+        html! {
+            <div class="dependencies">
+                <ul>
+                    { for prerequisites.map(|e| {
+                        let on = *e.id();
+                        let remove = if can_edit {
+                            html! {
+                                <button class="remove-dependency" title="Remove" onclick=self.link.callback(move |_| Msg::RemoveDependency(id, on))>
+                                    { "×" }
+                                </button>
+                            }
+                        } else {
+                            html! {}
+                        };
+                        let class = if *e.completed() { "done" } else { "open" };
+                        html! {
+                            <li class=class>
+                                { "Blocked by " }{ e.content() }
+                                { remove }
+                            </li>
+                        }
+                    }) }
+                </ul>
+                { picker }
+            </div>
+        }
+    }
+
+    /// Files of the entry whose details are shown, with a picker adding one.
+    fn view_attachments(&self, entry: &Entry) -> Html {
+        let id = *entry.id();
+        let can_edit = self.can_edit();
+        let picker = if !can_edit {
+            html! {}
+        } else if self.reading.is_some() {
+            html! { <span class="uploading">{ "Reading file..." }</span> }
+        } else {
+            html! {
+                <label class="attach">
+                    { "Attach a file " }
+                    <input
+                        type="file"
+                        onchange=self.link.batch_callback(|e: ChangeData| match e {
+                            ChangeData::Files(files) => files.get(0).map(Msg::Attach),
+                            _ => None,
+                        })
+                    />
+                </label>
+            }
+        };
+        html! {
+            <div class="attachments">
+                <ul>
+                    { for self.attachments.iter().map(|a| {
+                        let aid = *a.id();
+                        let detach = if can_edit {
+                            html! {
+                                <button class="detach" title="Delete" onclick=self.link.callback(move |_| Msg::Detach(id, aid))>
+                                    { "×" }
+                                </button>
+                            }
+                        } else {
+                            html! {}
+                        };
+                        html! {
+                            <li>
+                                <a href=format!("/attachment?id={}", aid) download=a.name().clone()>{ a.name() }</a>
+                                <span class="size">{ describe_size(a.size()) }</span>
+                                { detach }
+                            </li>
+                        }
+                    }) }
+                </ul>
+                { picker }
+            </div>
+        }
+    }
+
+    /// Progress of the subtasks, the toggle that hides them and the button
+    /// adding one.
+    fn view_subtask_controls(&self, (idx, entry): (usize, &Entry)) -> Html {
+        let id = *entry.id();
+        let (done, total) = self.state.progress(id);
+        let progress = if total > 0 {
+            let collapsed = self.state.collapsed.contains(&id);
+            let auto_class = if *entry.auto_complete() {
+                "auto-complete on"
+            } else {
+                "auto-complete"
+            };
+            html! {
+                <>
+                    <button class="collapse" onclick=self.link.callback(move |_| Msg::ToggleCollapsed(id))>
+                        { if collapsed { "▸" } else { "▾" } }
+                    </button>
+                    <span class="progress">{ format!("{}/{}", done, total) }</span>
+                    <button
+                        class=auto_class
+                        title="Complete once all subtasks are done"
+                        disabled=!self.can_edit()
+                        onclick=self.link.callback(move |_| Msg::ToggleAutoComplete(idx))
+                    >
+                        { "auto" }
+                    </button>
+                </>
+            }
+        } else {
+            html! {}
+        };
+        let add = if self.can_edit() && self.state.depth(id) < MAX_SUBTASK_DEPTH {
+            html! {
+                <button class="add-subtask" onclick=self.link.callback(move |_| Msg::StartSubtask(Some(id)))>
+                    { "+ subtask" }
+                </button>
+            }
+        } else {
+            html! {}
+        };
+        html! {
+            <span class="subtask-controls">
+                { progress }
+                { add }
+            </span>
+        }
+    }
+
+    /// Shown subtasks of an entry unless they are collapsed, and the input
+    /// adding one.
+    fn view_subtasks(&self, shown: &[(usize, &Entry)], entry: &Entry) -> Html {
+        let id = *entry.id();
+        let input = if self.subtask_parent == Some(id) {
+            html! {
+                <li>
+                    <input
+                        class="new-subtask"
+                        placeholder="Subtask"
+                        ref=self.subtask_ref.clone()
+                        value=self.subtask_value.clone()
+                        oninput=self.link.callback(|e: InputData| Msg::UpdateSubtask(e.value))
+                        onblur=self.link.callback(|_| Msg::StartSubtask(None))
+                        onkeypress=self.link.batch_callback(|e: KeyboardEvent| {
+                            if e.key() == "Enter" { Some(Msg::AddSubtask) } else { None }
+                        })
+                    />
+                </li>
+            }
+        } else {
+            html! {}
+        };
+        let subtasks: Vec<_> = if self.state.collapsed.contains(&id) {
+            Vec::new()
+        } else {
+            shown
+                .iter()
+                .filter(|(_, e)| *e.parent_id() == Some(id))
+                .collect()
+        };
+        if subtasks.is_empty() && self.subtask_parent != Some(id) {
+            return html! {};
+        }
+        html! {
+            <ul class="subtasks">
+                { for subtasks.into_iter().map(|e| self.view_entry(shown, *e)) }
+                { input }
+            </ul>
+        }
+    }
+
+    /// Tag chips of an entry, clicking one shows only entries with that tag.
+    fn view_tags(&self, entry: &Entry) -> Html {
+        let id = *entry.id();
+        let can_edit = self.can_edit();
+        let adder = if self.tagging == Some(id) {
+            html! {
+                <>
+                    <input
+                        class="add-tag"
+                        list="tag-names"
+                        placeholder="#tag"
+                        ref=self.tag_ref.clone()
+                        value=self.tag_value.clone()
+                        oninput=self.link.callback(|e: InputData| Msg::UpdateTag(e.value))
+                        onblur=self.link.callback(|_| Msg::StartTagging(None))
+                        onkeypress=self.link.batch_callback(|e: KeyboardEvent| {
+                            if e.key() == "Enter" { Some(Msg::AddTag) } else { None }
+                        })
+                    />
+                    <datalist id="tag-names">
+                        { for self.tags.iter().map(|t| html! { <option value=t.name().clone() /> }) }
+                    </datalist>
+                </>
+            }
+        } else if can_edit {
+            html! {
+                <button class="add-tag" onclick=self.link.callback(move |_| Msg::StartTagging(Some(id)))>
+                    { "+ tag" }
+                </button>
+            }
+        } else {
+            html! {}
+        };
+        html! {
+            <div class="tags">
+                { for entry.tags().iter().map(|t| self.view_tag(id, t, can_edit)) }
+                { adder }
+            </div>
+        }
+    }
+
+    fn view_tag(&self, entry: Uuid, tag: &Tag, can_edit: bool) -> Html {
+        let tid = *tag.id();
+        let untag = if can_edit {
+            html! {
+                <button class="untag" onclick=self.link.callback(move |_| Msg::Untag(entry, tid))>{ "×" }</button>
+            }
+        } else {
+            html! {}
+        };
+        html! {
+            <span class="tag">
+                <a href="#" onclick=self.link.callback(move |_| Msg::SetTagFilter(Some(tid)))>
+                    { format!("#{}", tag.name()) }
+                </a>
+                { untag }
+            </span>
+        }
+    }
+
+    /// Marker of the priority, clicking it or pressing `p` on a focused
+    /// entry moves to the next priority.
+    fn view_priority(&self, (idx, entry): (usize, &Entry)) -> Html {
+        let priority = *entry.priority();
+        html! {
+            <button
+                class=classes!("priority", priority.as_str())
+                title=format!("Priority: {}", priority.as_str())
+                disabled=!self.can_edit()
+                onclick=self.link.callback(move |_| Msg::CyclePriority(idx))
+            >
+                { priority_marker(priority) }
+            </button>
+        }
+    }
+
+    /// Picker of the recurrence rule, with the weekdays of a weekly one or
+    /// the days to wait after completion.
+    fn view_recurrence(&self, (idx, entry): (usize, &Entry)) -> Html {
+        let current = *entry.recurrence();
+        let can_edit = self.can_edit();
+        let weekday = due::weekday(*entry.due_at());
+        let onchange = self.link.batch_callback(move |e: ChangeData| match e {
+            ChangeData::Select(el) => {
+                let r = match el.value().as_str() {
+                    "daily" => Some(Recurrence::Daily),
+                    "weekly" => Some(Recurrence::Weekly(1 << weekday)),
+                    "monthly" => Some(Recurrence::Monthly),
+                    "after" => Some(Recurrence::AfterCompletion(1)),
+                    _ => None,
+                };
+                Some(Msg::SetRecurrence(idx, r))
+            }
+            _ => None,
+        });
+        let kind = match current {
+            None => "",
+            Some(Recurrence::Daily) => "daily",
+            Some(Recurrence::Weekly(_)) => "weekly",
+            Some(Recurrence::Monthly) => "monthly",
+            Some(Recurrence::AfterCompletion(_)) => "after",
+        };
+        let options = [
+            ("", "Does not repeat"),
+            ("daily", "Daily"),
+            ("weekly", "Weekly"),
+            ("monthly", "Monthly"),
+            ("after", "Days after done"),
+        ];
+        let detail = match current {
+            Some(Recurrence::Weekly(mask)) => html! {
+                { for Recurrence::WEEKDAYS.iter().enumerate().map(|(i, day)| {
+                    let bit = 1 << i;
+                    let class = if mask & bit != 0 { "weekday on" } else { "weekday" };
+                    let next = mask ^ bit;
+                    html! {
+                        <button
+                            class=class
+                            title=*day
+                            disabled=!can_edit || next == 0
+                            onclick=self.link.callback(move |_| Msg::SetRecurrence(idx, Some(Recurrence::Weekly(next))))
+                        >
+                            { &day[..1] }
+                        </button>
+                    }
+                }) }
+            },
+            Some(Recurrence::AfterCompletion(days)) => html! {
+                <input
+                    class="recur-days"
+                    type="number"
+                    min="1"
+                    max=MAX_RECURRENCE_DAYS.to_string()
+                    value=days.to_string()
+                    disabled=!can_edit
+                    onchange=self.link.batch_callback(move |e: ChangeData| match e {
+                        ChangeData::Value(v) => v
+                            .parse()
+                            .ok()
+                            .filter(|d| (1..=MAX_RECURRENCE_DAYS).contains(d))
+                            .map(|d| Msg::SetRecurrence(idx, Some(Recurrence::AfterCompletion(d)))),
+                        _ => None,
+                    })
+                />
+            },
+            _ => html! {},
+        };
+        html! {
+            <span class="recurrence">
+                <select title="Repeats" disabled=!can_edit onchange=onchange>
+                    { for options.iter().map(|(value, label)| html! {
+                        <option value=*value selected=*value == kind>{ label }</option>
+                    }) }
+                </select>
+                { detail }
+            </span>
+        }
+    }
+
+    /// The due date, editable for those who can edit the list.
+    /// A lock on entries waiting for others, naming them.
+    fn view_blocked(&self, entry: &Entry) -> Html {
+        let open = self.state.open_prerequisites(entry);
+        if *entry.completed() || open.is_empty() {
+            return html! {};
+        }
+        let names: Vec<&str> = open.iter().map(|e| e.content().as_str()).collect();
+        html! {
+            <span class="blocked" title=format!("Waiting for {}", names.join(", "))>{ "🔒" }</span>
+        }
+    }
+
+    /// Asks before completing the `idx`th shown entry while it waits for
+    /// others, `true` if it can go ahead.
+    fn confirm_blocked(&self, idx: usize) -> bool {
+        let shows = self.state.shows();
+        let entry = match self.state.entries.iter().filter(|e| shows(e)).nth(idx) {
+            Some(e) if !*e.completed() => e,
+            _ => return true,
+        };
+        let open = self.state.open_prerequisites(entry);
+        if open.is_empty() {
+            return true;
+        }
+        let names: Vec<&str> = open.iter().map(|e| e.content().as_str()).collect();
+        let question = format!(
+            "\"{}\" is waiting for {}. Complete it anyway?",
+            entry.content(),
+            names.join(", ")
+        );
+        yew::utils::window()
+            .confirm_with_message(&question)
+            .unwrap_or(false)
+    }
+
+    fn view_due(&self, (idx, entry): (usize, &Entry)) -> Html {
+        if !self.can_edit() {
+            return match *entry.due_at() {
+                Some(at) => html! { <span class="due">{ due::describe(at) }</span> },
+                None => html! {},
+            };
+        }
+        let value = entry.due_at().map(due::to_input_value).unwrap_or_default();
+        html! {
+            <input
+                class="due"
+                type="datetime-local"
+                title="Due date"
+                value=value
+                onchange=self.link.batch_callback(move |e: ChangeData| match e {
+                    ChangeData::Value(value) => Some(Msg::SetDue(idx, due::from_input_value(&value))),
+                    _ => None,
+                })
+            />
+        }
+    }
+
+    fn view_lists(&self) -> Html {
+        html! {
+            <aside class="lists">
+                <ul>
+                    { for self.lists.iter().map(|l| self.view_list(l)) }
+                </ul>
+                <input
+                    class="new-list"
+                    placeholder="New list"
+                    value=self.list_value.clone()
+                    oninput=self.link.callback(|e: InputData| Msg::UpdateList(e.value))
+                    onkeypress=self.link.batch_callback(|e: KeyboardEvent| {
+                        if e.key() == "Enter" { Some(Msg::AddList) } else { None }
+                    })
+                />
+            </aside>
+        }
+    }
+
+    fn view_list(&self, list: &TaskList) -> Html {
+        let id = *list.id();
+        let cls = if self.list == Some(id) {
+            "selected"
+        } else {
+            "not-selected"
+        };
+        html! {
+            <li>
+                <a class=cls href="#" onclick=self.link.callback(move |_| Msg::SelectList(id))>
+                    { list.name() }
+                </a>
+                <button class="share-list" onclick=self.link.callback(move |_| Msg::Share(Some(id)))>
+                    { "share" }
+                </button>
+                { self.view_remove_list(list) }
             </li>
         }
     }
 
+    fn view_remove_list(&self, list: &TaskList) -> Html {
+        if list.role() != Role::Owner {
+            return html! {};
+        }
+        let id = *list.id();
+        html! {
+            <button class="remove-list" onclick=self.link.callback(move |_| Msg::RemoveList(id))>
+                { "×" }
+            </button>
+        }
+    }
+
+    // yew's props checks expand to a bare expression statement
+    #[allow(clippy::unnecessary_operation)]
+    fn view_share(&self, user: &UserInfo) -> Html {
+        let list = match self.lists.iter().find(|l| Some(*l.id()) == self.sharing) {
+            Some(list) => list.clone(),
+            None => return html! {},
+        };
+        html! {
+            <ShareDialog
+                list=list
+                me=*user.id()
+                on_close=self.link.callback(|_| Msg::Share(None))
+                on_leave=self.link.callback(Msg::LeftList)
+            />
+        }
+    }
+
+    // yew's props checks expand to a bare expression statement
+    #[allow(clippy::unnecessary_operation)]
+    fn view_admin(&self, user: &UserInfo) -> Html {
+        html! {
+            <div class="todomvc-wrapper">
+                <AdminPanel me=*user.id() on_close=self.link.callback(|_| Msg::ShowAdmin(false)) />
+            </div>
+        }
+    }
+
+    // yew's props checks expand to a bare expression statement
+    #[allow(clippy::unnecessary_operation)]
+    fn view_stats(&self) -> Html {
+        html! {
+            <div class="todomvc-wrapper">
+                <StatsPage list=self.list on_close=self.link.callback(|_| Msg::ShowStats(false)) />
+            </div>
+        }
+    }
+
+    fn view_admin_link(&self, user: &UserInfo) -> Html {
+        if !user.admin() {
+            return html! {};
+        }
+        html! {
+            <a href="#" onclick=self.link.callback(|_| Msg::ShowAdmin(true))>{ " (admin)" }</a>
+        }
+    }
+
+    /// Whether the shown list can be changed, viewers only read it.
+    fn can_edit(&self) -> bool {
+        self.lists
+            .iter()
+            .any(|l| Some(*l.id()) == self.list && l.role() >= Role::Editor)
+    }
+
+    /// Picker to move an entry to another list the user can edit.
+    fn view_move(&self, idx: usize) -> Html {
+        if !self.can_edit() {
+            return html! {};
+        }
+        let mut others = self
+            .lists
+            .iter()
+            .filter(|l| Some(*l.id()) != self.list && l.role() >= Role::Editor)
+            .peekable();
+        if others.peek().is_none() {
+            return html! {};
+        }
+        html! {
+            <select
+                class="move-task"
+                onchange=self.link.batch_callback(move |e: ChangeData| match e {
+                    ChangeData::Select(el) => el.value().parse().ok().map(|list| Msg::MoveTask(idx, list)),
+                    _ => None,
+                })
+            >
+                <option value="" selected=true>{ "Move to..." }</option>
+                { for others.map(|l| html! { <option value=l.id().to_string()>{ l.name() }</option> }) }
+            </select>
+        }
+    }
+
+    fn view_edited_elsewhere(&self, entry: &Entry) -> Html {
+        if self.state.is_edited_elsewhere(entry) {
+            html! { <span class="edited-elsewhere">{ "being edited by another session" }</span> }
+        } else {
+            html! {}
+        }
+    }
+
     fn view_entry_edit_input(&self, (idx, entry): (usize, &Entry)) -> Html {
-        if *entry.editing() {
+        if self.state.is_editing(entry) {
             html! {
                 <input
                     class="edit"
@@ -325,109 +1736,641 @@ impl Model {
             html! { <input type="hidden" /> }
         }
     }
-    fn update_all_tasks(&self) -> Result<FetchTask, Error> {
-        let data = MsgPack(&self.state.entries);
-        let request = build_request("POST", "/tasks", data);
-        FetchService::fetch_binary(request, self.fetch_callback())
+    fn update_all_tasks(&self, op: usize) -> Job {
+        let entries = self.state.entries.clone();
+        let recurs = entries.iter().any(completes_recurring);
+        let callback = self.recur_callback(op, recurs);
+        let key = Uuid::new_v4().to_string();
+        let uri = match self.list {
+            Some(list) => format!("/tasks?list={}", list),
+            None => "/tasks".to_string(),
+        };
+        Job::new("POST", true, move || {
+            let data = MsgPack(&entries);
+            let request = build_keyed_request("POST", &uri, &key, data);
+            FetchService::fetch_binary(request, callback.clone())
+        })
     }
-    fn update_task(&self, e: &Entry) -> Result<FetchTask, Error> {
+    fn update_task(&self, e: &Entry, op: usize) -> Job {
         let id = e.id().to_hyphenated();
         let id_str = id
             .encode_lower(&mut uuid::Uuid::encode_buffer())
             .to_string();
-        let data = MsgPack(e);
-        let request = build_request("PUT", format!("/task?id={}", id_str), data);
-        FetchService::fetch_binary(request, self.fetch_callback())
-    }
-    fn create_task(&self, content: &str) -> Result<FetchTask, Error> {
-        let tr = TaskRequest { content };
-        let data = MsgPack(&tr);
-        let request = build_request("POST", "/task", data);
-        FetchService::fetch_binary(request, self.create_callback())
-    }
-    fn remove_task(&self, eid: Uuid) -> Result<FetchTask, Error> {
-        let id = eid.to_hyphenated();
-        let id_str = id
-            .encode_lower(&mut uuid::Uuid::encode_buffer())
-            .to_string();
-        let request = build_request("DELETE", format!("/task?id={}", id_str), Nothing);
-        FetchService::fetch_binary(request, self.fetch_callback())
+        let e = e.clone();
+        let callback = self.recur_callback(op, completes_recurring(&e));
+        Job::new("PUT", false, move || {
+            let data = MsgPack(&e);
+            let request = build_request("PUT", format!("/task?id={}", id_str), data);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn create_task(
+        &self,
+        list: Uuid,
+        id: Uuid,
+        content: &str,
+        parent: Option<Uuid>,
+        op: usize,
+    ) -> Job {
+        let content = content.to_string();
+        let callback = self.create_callback(list, op);
+        let uri = format!("/task?list={}", list);
+        let key = Uuid::new_v4().to_string();
+        Job::new("POST", true, move || {
+            let tr = TaskRequest {
+                content: &content,
+                id: Some(id),
+                priority: Priority::None,
+                parent,
+            };
+            let data = MsgPack(&tr);
+            let request = build_keyed_request("POST", &uri, &key, data);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn move_task(&self, id: Uuid, list: Uuid, op: usize) -> Job {
+        let uri = format!("/task/list?id={}&list={}", id, list);
+        let callback = self.fetch_callback(op);
+        let key = Uuid::new_v4().to_string();
+        Job::new("PUT", true, move || {
+            let request = build_keyed_request("PUT", &uri, &key, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn create_list(&self, name: &str, op: usize) -> Job {
+        let name = name.to_string();
+        let id = Uuid::new_v4();
+        let callback = self.link.callback(move |resp: FetchResponse<TaskList>| {
+            let (meta, MsgPack(r)) = resp.into_parts();
+            if meta.status.is_success() {
+                match r {
+                    Ok(l) => Msg::FetchOk(op, FetchOkType::List(l)),
+                    Err(e) => Msg::FetchError(op, FetchErrorType::Data(e.to_string())),
+                }
+            } else {
+                Msg::FetchError(op, status_error(meta.status, &meta.headers))
+            }
+        });
+        let key = Uuid::new_v4().to_string();
+        Job::new("POST", true, move || {
+            let lr = ListRequest {
+                name: &name,
+                id: Some(id),
+            };
+            let request = build_keyed_request("POST", "/lists", &key, MsgPack(&lr));
+            FetchService::fetch_binary(request, callback.clone())
+        })
     }
-    fn fetch_callback(&self) -> Callback<FetchResponse<()>> {
+    fn remove_list(&self, id: Uuid, op: usize) -> Job {
+        let uri = format!("/list?id={}", id);
+        let callback = self.fetch_callback(op);
+        let key = Uuid::new_v4().to_string();
+        Job::new("DELETE", true, move || {
+            let request = build_keyed_request("DELETE", &uri, &key, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn remove_task(&self, eid: Uuid, op: usize) -> Job {
+        let uri = format!("/task?id={}", eid);
+        let callback = self.fetch_callback(op);
+        // a retry after a lost response gets the first answer instead of 404
+        let key = Uuid::new_v4().to_string();
+        Job::new("DELETE", true, move || {
+            let request = build_keyed_request("DELETE", &uri, &key, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn acquire_edit(&mut self, id: Uuid) {
+        let op = self.next_op();
+        let uri = format!("/task/edit?id={}&session={}", id, self.session);
+        let callback = self.link.callback(move |resp: FetchResponse<()>| {
+            let (meta, _) = resp.into_parts();
+            if meta.status.is_success() {
+                Msg::FetchOk(op, FetchOkType::NoData)
+            } else if meta.status.as_u16() == 409 {
+                Msg::EditTaken(op, id)
+            } else {
+                Msg::FetchError(op, status_error(meta.status, &meta.headers))
+            }
+        });
+        let job = Job::new("PUT", false, move || {
+            let request = build_request("PUT", &uri, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        });
+        self.send(op, Target::Read, job);
+    }
+    fn release_edit(&mut self, id: Uuid) {
+        let op = self.next_op();
+        let uri = format!("/task/edit?id={}&session={}", id, self.session);
+        let callback = self.fetch_callback(op);
+        let job = Job::new("DELETE", false, move || {
+            let request = build_request("DELETE", &uri, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        });
+        self.send(op, Target::Read, job);
+    }
+    fn fetch_edits(&self, op: usize) -> Job {
+        let uri = format!("/edits?session={}", self.session);
+        let callback = self.link.callback(move |resp: FetchResponse<Editing>| {
+            let (meta, MsgPack(data)) = resp.into_parts();
+            if meta.status.is_success() {
+                match data {
+                    Ok(ids) => Msg::FetchOk(op, FetchOkType::Editing(ids)),
+                    Err(e) => Msg::FetchError(op, FetchErrorType::Data(e.to_string())),
+                }
+            } else {
+                Msg::FetchError(op, status_error(meta.status, &meta.headers))
+            }
+        });
+        Job::new("GET", false, move || {
+            let request = build_request("GET", &uri, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn fetch_callback(&self, op: usize) -> Callback<FetchResponse<()>> {
+        self.ack_callback(op, || FetchOkType::NoData)
+    }
+    /// Completing a recurring task creates its next occurrence on the
+    /// server, the tasks are fetched again once that is done.
+    fn recur_callback(&self, op: usize, recurs: bool) -> Callback<FetchResponse<()>> {
+        match self.list {
+            Some(list) if recurs => self.ack_callback(op, move || FetchOkType::Reload(list)),
+            _ => self.fetch_callback(op),
+        }
+    }
+    /// Callback for requests answering without data, `ok` tells `Msg::FetchOk`
+    /// what succeeded.
+    fn ack_callback<F>(&self, op: usize, ok: F) -> Callback<FetchResponse<()>>
+    where
+        F: Fn() -> FetchOkType + 'static,
+    {
         self.link.callback(move |resp: FetchResponse<()>| {
             let (meta, _) = resp.into_parts();
             if meta.status.is_success() {
-                Msg::FetchOk(FetchOkType::NoData)
+                Msg::FetchOk(op, ok())
+            } else {
+                Msg::FetchError(op, status_error(meta.status, &meta.headers))
+            }
+        })
+    }
+    /// Callback for requests answering with data, `ok` wraps it for
+    /// `Msg::FetchOk`.
+    fn data_callback<T, F>(&self, op: usize, ok: F) -> Callback<FetchResponse<T>>
+    where
+        T: 'static,
+        F: Fn(T) -> FetchOkType + 'static,
+    {
+        self.link.callback(move |resp: FetchResponse<T>| {
+            let (meta, MsgPack(data)) = resp.into_parts();
+            if meta.status.is_success() {
+                match data {
+                    Ok(v) => Msg::FetchOk(op, ok(v)),
+                    Err(e) => Msg::FetchError(op, FetchErrorType::Data(e.to_string())),
+                }
             } else {
-                let status = meta.status;
-                Msg::FetchError(FetchErrorType::Meta(
-                    status.as_u16(),
-                    status.canonical_reason().map(|s| s.to_string()),
-                ))
+                Msg::FetchError(op, status_error(meta.status, &meta.headers))
             }
         })
     }
-    fn create_callback(&self) -> Callback<FetchResponse<Entry>> {
+    fn create_callback(&self, list: Uuid, op: usize) -> Callback<FetchResponse<Entry>> {
         self.link.callback(move |resp: FetchResponse<Entry>| {
             let (meta, MsgPack(r)) = resp.into_parts();
             if meta.status.is_success() {
                 match r {
-                    Ok(e) => Msg::FetchOk(FetchOkType::Entry(e)),
-                    Err(e) => Msg::FetchError(FetchErrorType::Data(e.to_string())),
+                    Ok(e) => Msg::FetchOk(op, FetchOkType::Entry(list, e)),
+                    Err(e) => Msg::FetchError(op, FetchErrorType::Data(e.to_string())),
                 }
             } else {
-                let status = meta.status;
-                Msg::FetchError(FetchErrorType::Meta(
-                    status.as_u16(),
-                    status.canonical_reason().map(|s| s.to_string()),
-                ))
+                Msg::FetchError(op, status_error(meta.status, &meta.headers))
             }
         })
     }
-    fn handle_ft(&mut self, ft: Result<FetchTask, Error>, task_desc: &str) -> ShouldRender {
-        match ft {
-            Ok(t) => {
-                self.ft = Some(t);
+    /// Hands request `op` to the request manager, which starts it once no
+    /// earlier write to `target` is in flight.
+    fn send(&mut self, op: usize, target: Target, job: Job) -> ShouldRender {
+        if let Err(e) = self.requests.submit(op, target, job) {
+            self.fail_to_start(op, e);
+        }
+        true
+    }
+    /// Reports request `op` as completed, starting the requests queued behind it.
+    fn settle(&mut self, op: usize) {
+        if let Some(p) = self.pending.get(&op) {
+            ConsoleService::info(format!("Request completed: {}", p.desc).as_str());
+        }
+        for (op, e) in self.requests.finish(op) {
+            self.fail_to_start(op, e);
+        }
+    }
+    fn fail_to_start(&mut self, op: usize, e: Error) {
+        let task_desc = self.pending.get(&op).map_or("request", |p| p.desc);
+        ConsoleService::error(
+            format!("Error, occur: {}, reason: {}", task_desc, e.to_string()).as_str(),
+        );
+        self.fail(op);
+    }
+    // yew's props checks expand to a bare expression statement
+    #[allow(clippy::unnecessary_operation)]
+    fn view_auth(&self) -> Html {
+        if self.auth_checked {
+            html! { <AuthForm on_login=self.link.callback(Msg::LoggedIn) /> }
+        } else {
+            html! {}
+        }
+    }
+    fn logged_in(&mut self, user: UserInfo) -> ShouldRender {
+        self.user = Some(user);
+        self.auth_checked = true;
+        self.fetch_tags();
+        self.fetch_lists()
+    }
+    fn logged_out(&mut self) -> ShouldRender {
+        self.user = None;
+        self.auth_checked = true;
+        self.lists.clear();
+        self.list = None;
+        self.tags.clear();
+        self.tagging = None;
+        self.subtask_parent = None;
+        self.details = None;
+        self.notes_value = None;
+        self.attachments.clear();
+        self.reading = None;
+        self.search_value.clear();
+        self.search_hits = None;
+        self.search_timeout = None;
+        self.state.tag_filter = None;
+        self.sharing = None;
+        self.admin = false;
+        self.stats = false;
+        self.state.entries.clear();
+        self.state.editing = None;
+        self.state.edited_elsewhere.clear();
+        true
+    }
+    fn fetch_tags(&mut self) -> ShouldRender {
+        let op = self.track("fetch tags", Vec::new());
+        let callback = self.data_callback(op, FetchOkType::Tags);
+        let job = Job::new("GET", false, move || {
+            let request = build_request("GET", "/tags", Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        });
+        self.send(op, Target::Read, job)
+    }
+    /// Creates tag `name` to put it on entry `id` once it exists.
+    fn create_tag(&self, id: Uuid, name: &str, op: usize) -> Job {
+        let name = name.to_string();
+        let callback = self.data_callback(op, move |tag| FetchOkType::Tag(id, tag));
+        let key = Uuid::new_v4().to_string();
+        Job::new("POST", true, move || {
+            let tr = TagRequest { name: &name };
+            let request = build_keyed_request("POST", "/tags", &key, MsgPack(&tr));
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn assign_tag(&mut self, id: Uuid, tag: Tag) -> ShouldRender {
+        let tid = *tag.id();
+        match self.state.add_tag(id, tag) {
+            Some((_, inverse)) => {
+                let op = self.track("tag task", vec![inverse]);
+                let job = self.tag_request("PUT", id, tid, op);
+                self.send(op, Target::Task(id), job)
+            }
+            None => true,
+        }
+    }
+    /// Puts a tag on a task or takes it off.
+    fn tag_request(&self, method: &'static str, id: Uuid, tag: Uuid, op: usize) -> Job {
+        let uri = format!("/task/tag?id={}&tag={}", id, tag);
+        let callback = self.fetch_callback(op);
+        let key = Uuid::new_v4().to_string();
+        Job::new(method, true, move || {
+            let request = build_keyed_request(method, &uri, &key, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    /// Makes a task wait for another or stop waiting.
+    fn dependency_request(&self, method: &'static str, id: Uuid, on: Uuid, op: usize) -> Job {
+        let uri = format!("/task/dependency?id={}&on={}", id, on);
+        let callback = self.fetch_callback(op);
+        let key = Uuid::new_v4().to_string();
+        Job::new(method, true, move || {
+            let request = build_keyed_request(method, &uri, &key, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    /// Searches every list for `search_value`.
+    fn search(&self, op: usize) -> Job {
+        let q = self.search_value.clone();
+        let callback = {
+            let q = q.clone();
+            self.data_callback(op, move |hits| FetchOkType::SearchHits(q.clone(), hits))
+        };
+        let uri = format!("/tasks/search?q={}", encode_query(&q));
+        Job::new("GET", false, move || {
+            let request = build_request("GET", &uri, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn fetch_attachments(&mut self, id: Uuid) -> ShouldRender {
+        let op = self.track("fetch attachments", Vec::new());
+        let callback = self.data_callback(op, move |v| FetchOkType::Attachments(id, v));
+        let uri = format!("/task/attachments?id={}", id);
+        let job = Job::new("GET", false, move || {
+            let request = build_request("GET", &uri, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        });
+        self.send(op, Target::Read, job)
+    }
+    /// Sends a file to the server as a multipart form, the way a form with
+    /// a file input would.
+    fn upload_attachment(&self, id: Uuid, content_type: &str, data: FileData, op: usize) -> Job {
+        let callback = self.data_callback(op, move |a| FetchOkType::Attachment(id, a));
+        let uri = format!("/task/attachments?id={}", id);
+        let key = Uuid::new_v4().to_string();
+        let boundary = format!("todomvc-{}", Uuid::new_v4().to_simple());
+        let content_type = match content_type {
+            "" => "application/octet-stream",
+            t => t,
+        };
+        let body = multipart_body(&boundary, &data.name, content_type, &data.content);
+        Job::new("POST", true, move || {
+            let mut request = build_keyed_request("POST", &uri, &key, Ok(body.clone()));
+            let form = format!("multipart/form-data; boundary={}", boundary);
+            request
+                .headers_mut()
+                .insert("Content-Type", form.parse().unwrap());
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn delete_attachment(&self, id: Uuid, attachment: Uuid, op: usize) -> Job {
+        let callback = self.ack_callback(op, move || FetchOkType::Detached(id, attachment));
+        let uri = format!("/attachment?id={}", attachment);
+        let key = Uuid::new_v4().to_string();
+        Job::new("DELETE", true, move || {
+            let request = build_keyed_request("DELETE", &uri, &key, Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        })
+    }
+    fn fetch_lists(&mut self) -> ShouldRender {
+        let op = self.track("fetch lists", Vec::new());
+        let callback = self.link.callback(move |resp: FetchResponse<Lists>| {
+            let (meta, MsgPack(data)) = resp.into_parts();
+            if meta.status.is_success() {
+                match data {
+                    Ok(v) => Msg::FetchOk(op, FetchOkType::Lists(v)),
+                    Err(e) => Msg::FetchError(op, FetchErrorType::Data(e.to_string())),
+                }
+            } else {
+                Msg::FetchError(op, status_error(meta.status, &meta.headers))
+            }
+        });
+        let job = Job::new("GET", false, move || {
+            let request = build_request("GET", "/lists", Nothing);
+            FetchService::fetch_binary(request, callback.clone())
+        });
+        self.send(op, Target::Read, job)
+    }
+    /// Shows the tasks of `list`. Pending requests still finish, but their
+    /// rollbacks no longer apply to what is shown.
+    fn select_list(&mut self, list: Uuid) -> ShouldRender {
+        if let Some(id) = self.state.editing.take() {
+            self.release_edit(id);
+        }
+        for p in self.pending.values_mut() {
+            p.undo.clear();
+        }
+        self.list = Some(list);
+        self.state.entries.clear();
+        let op = self.track("fetch tasks", Vec::new());
+        let link = self.link.clone();
+        let job = Job::new("GET", false, move || fetch_all_tasks(&link, op, list));
+        self.send(op, Target::Read, job)
+    }
+    /// Fetches the tasks of `list` again if it is shown, keeping the old ones
+    /// until they arrive.
+    fn reload_tasks(&mut self, list: Uuid) -> ShouldRender {
+        if self.list != Some(list) {
+            return false;
+        }
+        let op = self.track("fetch tasks", Vec::new());
+        let link = self.link.clone();
+        let job = Job::new("GET", false, move || fetch_all_tasks(&link, op, list));
+        self.send(op, Target::Read, job)
+    }
+    /// Allocates an id for a request nobody needs to hear about when it fails.
+    fn next_op(&mut self) -> usize {
+        let op = self.next_op;
+        self.next_op += 1;
+        op
+    }
+    /// Registers a request whose local changes can be undone by `undo`.
+    fn track(&mut self, desc: &'static str, undo: Vec<Inverse>) -> usize {
+        let op = self.next_op();
+        self.pending.insert(op, Pending { desc, undo });
+        op
+    }
+    /// Rolls back the local changes of a failed request and tells the user.
+    fn fail(&mut self, op: usize) -> ShouldRender {
+        match self.pending.remove(&op) {
+            Some(p) if p.undo.is_empty() => {
+                self.notice = Some(format!("Could not {}.", p.desc));
                 true
             }
-            Err(e) => {
-                ConsoleService::error(
-                    format!("Error, occur: {}, reason: {}", task_desc, e.to_string()).as_str(),
-                );
-                self.ft = None;
-                false
+            Some(p) => {
+                self.state.rollback(p.undo);
+                self.notice = Some(format!("Could not {}, changes were reverted.", p.desc));
+                true
+            }
+            None => false,
+        }
+    }
+    fn view_sync_status(&self) -> Html {
+        if self.requests.is_empty() {
+            html! {}
+        } else {
+            let retrying = match self.requests.retrying() {
+                0 => String::new(),
+                n => format!(", retrying {}", n),
+            };
+            html! {
+                <span class="sync-status">
+                    { format!("Syncing {} change(s){}...", self.requests.len(), retrying) }
+                </span>
             }
         }
     }
+    fn view_notice(&self) -> Html {
+        match &self.notice {
+            Some(notice) => html! {
+                <div class="notice" onclick=self.link.callback(|_| Msg::DismissNotice)>
+                    { notice }
+                </div>
+            },
+            None => html! {},
+        }
+    }
+}
+
+/// The `share` query parameter of the page.
+fn share_token() -> Option<String> {
+    let search = yew::utils::window().location().search().ok()?;
+    search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|kv| kv.strip_prefix("share="))
+        .filter(|token| !token.is_empty())
+        .map(str::to_string)
+}
+
+// yew's props checks expand to a bare expression statement
+#[allow(clippy::unnecessary_operation)]
+fn view_shared(token: &str) -> Html {
+    html! { <SharedListView token=token.to_string() /> }
+}
+
+/// Markdown rendered by the shared renderer, which escapes everything it
+/// does not generate itself.
+fn view_markdown(src: &str) -> Html {
+    let div = yew::utils::document().create_element("div").unwrap();
+    div.set_class_name("notes");
+    div.set_inner_html(&markdown::render(src));
+    Html::VRef(div.into())
+}
+
+/// Percent-encodes a query parameter value.
+fn encode_query(value: &str) -> String {
+    String::from(js_sys::encode_uri_component(value))
+}
+
+/// A `multipart/form-data` body with `content` as its `file` field.
+fn multipart_body(boundary: &str, name: &str, content_type: &str, content: &[u8]) -> Vec<u8> {
+    // quotes and line breaks are escaped like browsers do in file names
+    let name = name
+        .replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+         Content-Type: {}\r\n\r\n",
+        boundary, name, content_type
+    )
+    .into_bytes();
+    body.extend_from_slice(content);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    body
 }
 
-fn fetch_all_tasks(link: &ComponentLink<Model>) -> Result<FetchTask, Error> {
+/// Size of an attachment in the largest unit it fills.
+fn describe_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+fn completes_recurring(e: &Entry) -> bool {
+    *e.completed() && e.recurrence().is_some()
+}
+
+/// Whether an entry is shown at the top level, subtasks are shown below
+/// their parent if that is shown.
+fn is_root(shown: &[(usize, &Entry)], entry: &Entry) -> bool {
+    match entry.parent_id() {
+        Some(parent) => !shown.iter().any(|(_, e)| e.id() == parent),
+        None => true,
+    }
+}
+
+/// Exclamation marks for the priority, a faint dot for none so there is
+/// something to click.
+fn priority_marker(priority: Priority) -> &'static str {
+    match priority {
+        Priority::None => "·",
+        Priority::Low => "!",
+        Priority::Medium => "!!",
+        Priority::High => "!!!",
+    }
+}
+
+/// The error of a response that did not succeed.
+fn status_error(status: StatusCode, headers: &HeaderMap) -> FetchErrorType {
+    FetchErrorType::Meta(
+        status.as_u16(),
+        status.canonical_reason().map(|s| s.to_string()),
+        retry::retry_after(headers),
+    )
+}
+
+fn fetch_me(link: &ComponentLink<Model>, op: usize) -> Job {
+    let callback = link.callback(move |resp: FetchResponse<UserInfo>| {
+        let (meta, MsgPack(data)) = resp.into_parts();
+        if meta.status.is_success() {
+            match data {
+                Ok(user) => Msg::FetchOk(op, FetchOkType::User(user)),
+                Err(e) => Msg::FetchError(op, FetchErrorType::Data(e.to_string())),
+            }
+        } else {
+            Msg::FetchError(op, status_error(meta.status, &meta.headers))
+        }
+    });
+    Job::new("GET", false, move || {
+        let request = build_request("GET", "/me", Nothing);
+        FetchService::fetch_binary(request, callback.clone())
+    })
+}
+
+fn fetch_all_tasks(link: &ComponentLink<Model>, op: usize, list: Uuid) -> Result<FetchTask, Error> {
     let callback = link.callback(move |resp: Response<MsgPack<Result<Entries, Error>>>| {
         let (meta, MsgPack(data)) = resp.into_parts();
         // ConsoleService::log(format!("META: {:?}, {:?}", meta, data).as_str());
         if meta.status.is_success() {
             match data {
-                Ok(v) => Msg::FetchOk(FetchOkType::Entries(v)),
-                Err(e) => Msg::FetchError(FetchErrorType::Data(e.to_string())),
+                Ok(v) => Msg::FetchOk(op, FetchOkType::Entries(list, v)),
+                Err(e) => Msg::FetchError(op, FetchErrorType::Data(e.to_string())),
             }
         } else {
-            let status = meta.status;
-            Msg::FetchError(FetchErrorType::Meta(
-                status.as_u16(),
-                status.canonical_reason().map(|s| s.to_string()),
-            ))
+            Msg::FetchError(op, status_error(meta.status, &meta.headers))
         }
     });
     // let request = Request::get("/tasks").body(Nothing).unwrap();
-    let request = build_request("GET", "/tasks", Nothing);
+    let request = build_request("GET", format!("/tasks?list={}", list), Nothing);
     FetchService::fetch_binary(request, callback)
 }
 
+/// Builds a request to the backend, requests that change something repeat
+/// the CSRF cookie in a header.
 fn build_request<T, U: ToString>(method: &str, uri: U, data: T) -> Request<T> {
-    Request::builder()
+    let mut builder = Request::builder()
         .method(method)
         .uri(uri.to_string())
-        .header("Content-Type", "application/msgpack")
-        .body(data)
-        .unwrap()
+        .header("Content-Type", "application/msgpack");
+    if method != "GET" {
+        if let Some(token) = csrf_token() {
+            builder = builder.header("X-CSRF-Token", token);
+        }
+    }
+    builder.body(data).unwrap()
+}
+
+/// The value of the `csrf` cookie the backend sets on login.
+fn csrf_token() -> Option<String> {
+    let cookies = js_sys::Reflect::get(&yew::utils::document(), &"cookie".into())
+        .ok()?
+        .as_string()?;
+    cookies
+        .split(';')
+        .find_map(|c| c.trim().strip_prefix("csrf="))
+        .map(str::to_string)
+}
+
+/// Like `build_request`, but the server runs it at most once per `key`, which
+/// makes it safe to retry.
+fn build_keyed_request<T, U: ToString>(method: &str, uri: U, key: &str, data: T) -> Request<T> {
+    let mut request = build_request(method, uri, data);
+    request
+        .headers_mut()
+        .insert("Idempotency-Key", key.parse().unwrap());
+    request
 }
@@ -0,0 +1,184 @@
+use super::retry::{self, RetryPolicy};
+use anyhow::Error;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+use uuid::Uuid;
+use yew::{
+    services::{fetch::FetchTask, timeout::TimeoutTask, TimeoutService},
+    Callback,
+};
+
+/// Starts a request, called once the request is allowed to run and again
+/// for every retry.
+pub struct Job {
+    start: Box<dyn Fn() -> Result<FetchTask, Error>>,
+    retryable: bool,
+}
+
+impl Job {
+    /// `keyed` tells whether the request carries an `Idempotency-Key`.
+    pub fn new<F>(method: &str, keyed: bool, start: F) -> Self
+    where
+        F: Fn() -> Result<FetchTask, Error> + 'static,
+    {
+        Self {
+            start: Box::new(start),
+            retryable: retry::is_idempotent(method, keyed),
+        }
+    }
+}
+
+/// What a request writes to, writes to the same target run one at a time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// Reads never wait for anything.
+    Read,
+    Task(Uuid),
+    /// Bulk writes wait for, and block, every other write.
+    All,
+}
+
+impl Target {
+    fn conflicts(&self, other: &Target) -> bool {
+        match (self, other) {
+            (Target::Read, _) | (_, Target::Read) => false,
+            (Target::All, _) | (_, Target::All) => true,
+            (Target::Task(a), Target::Task(b)) => a == b,
+        }
+    }
+}
+
+struct InFlight {
+    target: Target,
+    job: Job,
+    attempt: u32,
+    /// `None` while waiting for the next retry.
+    ft: Option<FetchTask>,
+    timeout: Option<TimeoutTask>,
+}
+
+/// Keeps every in-flight `FetchTask` alive until its request completes,
+/// since dropping a `FetchTask` aborts it.
+#[derive(Default)]
+pub struct RequestManager {
+    policy: RetryPolicy,
+    in_flight: HashMap<usize, InFlight>,
+    waiting: VecDeque<(usize, Target, Job)>,
+}
+
+impl RequestManager {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    /// Starts request `op` now, or queues it behind earlier writes to the
+    /// same target.
+    pub fn submit(&mut self, op: usize, target: Target, job: Job) -> Result<(), Error> {
+        if self.is_blocked(&target, self.waiting.len()) {
+            self.waiting.push_back((op, target, job));
+            Ok(())
+        } else {
+            self.start(op, target, job)
+        }
+    }
+
+    /// Schedules another attempt of request `op` after a failure with status
+    /// `code`, `callback` fires when it is due. Waits at least `retry_after`
+    /// when the server asked for it. Returns the delay, or `None` when the
+    /// request should not be retried.
+    pub fn retry(
+        &mut self,
+        op: usize,
+        code: u16,
+        retry_after: Option<Duration>,
+        callback: Callback<()>,
+    ) -> Option<Duration> {
+        let policy = self.policy;
+        let r = self.in_flight.get_mut(&op)?;
+        if !r.job.retryable || !policy.is_retryable_status(code) || r.attempt >= policy.max_attempts
+        {
+            return None;
+        }
+        let delay = policy.delay(r.attempt).max(retry_after.unwrap_or_default());
+        r.ft = None;
+        r.timeout = Some(TimeoutService::spawn(delay, callback));
+        Some(delay)
+    }
+
+    /// Sends request `op` again once its retry is due.
+    pub fn restart(&mut self, op: usize) -> Result<(), Error> {
+        if let Some(r) = self.in_flight.get_mut(&op) {
+            r.timeout = None;
+            r.attempt += 1;
+            r.ft = Some((r.job.start)()?);
+        }
+        Ok(())
+    }
+
+    /// Marks request `op` as completed and starts the queued requests it
+    /// was blocking. Returns the requests that failed to start.
+    pub fn finish(&mut self, op: usize) -> Vec<(usize, Error)> {
+        let mut failed = Vec::new();
+        if self.in_flight.remove(&op).is_none() {
+            return failed;
+        }
+        let mut idx = 0;
+        while idx < self.waiting.len() {
+            let target = self.waiting[idx].1;
+            if self.is_blocked(&target, idx) {
+                idx += 1;
+                continue;
+            }
+            let (op, target, job) = self.waiting.remove(idx).unwrap();
+            if let Err(e) = self.start(op, target, job) {
+                failed.push((op, e));
+            }
+        }
+        failed
+    }
+
+    /// Number of requests started or queued but not completed.
+    pub fn len(&self) -> usize {
+        self.in_flight.len() + self.waiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of requests waiting for their next attempt.
+    pub fn retrying(&self) -> usize {
+        self.in_flight.values().filter(|r| r.ft.is_none()).count()
+    }
+
+    fn start(&mut self, op: usize, target: Target, job: Job) -> Result<(), Error> {
+        let ft = (job.start)()?;
+        self.in_flight.insert(
+            op,
+            InFlight {
+                target,
+                job,
+                attempt: 1,
+                ft: Some(ft),
+                timeout: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Whether a request to `target` has to wait for an in-flight request,
+    /// or for one of the first `before` queued requests.
+    fn is_blocked(&self, target: &Target, before: usize) -> bool {
+        self.in_flight.values().any(|r| r.target.conflicts(target))
+            || self
+                .waiting
+                .iter()
+                .take(before)
+                .any(|(_, t, _)| t.conflicts(target))
+    }
+}
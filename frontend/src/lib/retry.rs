@@ -0,0 +1,52 @@
+use std::time::Duration;
+use yew::services::fetch::HeaderMap;
+
+/// When and how often failed requests are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Network errors are reported by `FetchService` as `408`.
+    pub fn is_retryable_status(&self, code: u16) -> bool {
+        code == 408 || code == 429 || (500..600).contains(&code)
+    }
+
+    /// Delay before attempt `attempt + 1`, doubling each time with up to half
+    /// of it as random jitter.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .checked_mul(1 << attempt.saturating_sub(1).min(16))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        let jitter = exp.as_millis() as f64 / 2.0 * js_sys::Math::random();
+        exp / 2 + Duration::from_millis(jitter as u64)
+    }
+}
+
+/// How long a `429` or `503` response asks to wait, from its `Retry-After`
+/// header in seconds.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let secs = headers.get("Retry-After")?.to_str().ok()?;
+    secs.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// Whether sending a request twice has the same effect as sending it once.
+pub fn is_idempotent(method: &str, keyed: bool) -> bool {
+    keyed || matches!(method, "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS")
+}
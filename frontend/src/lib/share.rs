@@ -0,0 +1,448 @@
+use super::build_request;
+use anyhow::Error;
+use std::time::UNIX_EPOCH;
+use todomvc_shared::{
+    InviteRequest, Member, Members, Role, ShareLink, ShareLinks, ShareRequest, TaskList,
+};
+use uuid::Uuid;
+use yew::{
+    events::KeyboardEvent,
+    format::{Binary, MsgPack, Nothing},
+    html,
+    services::{
+        fetch::{FetchTask, Request, Response},
+        FetchService,
+    },
+    Callback, ChangeData, Component, ComponentLink, Html, InputData, Properties, ShouldRender,
+};
+
+pub enum Msg {
+    Fetched(Members),
+    UpdateUsername(String),
+    SetInviteRole(Role),
+    Invite,
+    SetRole(usize, Role),
+    Revoke(Uuid),
+    FetchedLinks(ShareLinks),
+    SetExpiry(Option<u64>),
+    CreateLink,
+    LinkCreated(ShareLink),
+    RevokeLink(Uuid),
+    LinksChanged,
+    Done,
+    Left,
+    Failed(String),
+    Close,
+}
+
+#[derive(Clone, Properties)]
+pub struct Props {
+    pub list: TaskList,
+    /// The logged in user.
+    pub me: Uuid,
+    pub on_close: Callback<()>,
+    /// Called once the logged in user left the list.
+    pub on_leave: Callback<Uuid>,
+}
+
+/// Lifetimes offered for new share links, in seconds.
+const EXPIRY_CHOICES: [(&str, Option<u64>); 4] = [
+    ("never expires", None),
+    ("1 day", Some(24 * 60 * 60)),
+    ("7 days", Some(7 * 24 * 60 * 60)),
+    ("30 days", Some(30 * 24 * 60 * 60)),
+];
+
+/// Dialog listing the members of a list, owners can invite, change roles
+/// and revoke, everyone else can only leave. Owners also manage the
+/// read-only share links of the list here.
+pub struct ShareDialog {
+    link: ComponentLink<Self>,
+    props: Props,
+    members: Members,
+    username: String,
+    role: Role,
+    links: ShareLinks,
+    expires_in: Option<u64>,
+    error: Option<String>,
+    ft: Option<FetchTask>,
+}
+
+impl Component for ShareDialog {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut dialog = Self {
+            link,
+            props,
+            members: Vec::new(),
+            username: String::new(),
+            role: Role::Editor,
+            links: Vec::new(),
+            expires_in: None,
+            error: None,
+            ft: None,
+        };
+        dialog.fetch_members();
+        dialog
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Fetched(members) => {
+                self.ft = None;
+                self.members = members;
+                if self.is_owner() {
+                    self.fetch_links();
+                }
+                true
+            }
+            Msg::UpdateUsername(val) => {
+                self.username = val;
+                true
+            }
+            Msg::SetInviteRole(role) => {
+                self.role = role;
+                true
+            }
+            Msg::Invite => {
+                let username = self.username.trim().to_string();
+                if username.is_empty() {
+                    return false;
+                }
+                self.username.clear();
+                self.invite(&username, self.role);
+                true
+            }
+            Msg::SetRole(idx, role) => {
+                if let Some(m) = self.members.get(idx) {
+                    let username = m.username().clone();
+                    self.invite(&username, role);
+                }
+                true
+            }
+            Msg::Revoke(member) => {
+                let uri = format!("/list/member?id={}&member={}", self.props.list.id(), member);
+                let done = if member == self.props.me {
+                    || Msg::Left
+                } else {
+                    || Msg::Done
+                };
+                self.send(build_request("DELETE", uri, Nothing), done);
+                true
+            }
+            Msg::FetchedLinks(links) => {
+                self.ft = None;
+                self.links = links;
+                true
+            }
+            Msg::SetExpiry(expires_in) => {
+                self.expires_in = expires_in;
+                false
+            }
+            Msg::CreateLink => {
+                self.create_link();
+                true
+            }
+            Msg::LinkCreated(link) => {
+                self.ft = None;
+                self.error = None;
+                self.links.push(link);
+                true
+            }
+            Msg::RevokeLink(share) => {
+                let uri = format!("/list/share?id={}&share={}", self.props.list.id(), share);
+                self.send(build_request("DELETE", uri, Nothing), || Msg::LinksChanged);
+                true
+            }
+            Msg::LinksChanged => {
+                self.error = None;
+                self.fetch_links();
+                true
+            }
+            Msg::Done => {
+                self.error = None;
+                self.fetch_members();
+                true
+            }
+            Msg::Left => {
+                self.ft = None;
+                self.props.on_leave.emit(*self.props.list.id());
+                false
+            }
+            Msg::Failed(reason) => {
+                self.ft = None;
+                self.error = Some(reason);
+                true
+            }
+            Msg::Close => {
+                self.props.on_close.emit(());
+                false
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        let changed = self.props.list.id() != props.list.id();
+        self.props = props;
+        if changed {
+            self.members.clear();
+            self.links.clear();
+            self.error = None;
+            self.fetch_members();
+        }
+        true
+    }
+
+    fn view(&self) -> Html {
+        html! {
+            <div class="share-dialog">
+                <header>
+                    <h2>{ format!("Sharing \"{}\"", self.props.list.name()) }</h2>
+                    <button class="close" onclick=self.link.callback(|_| Msg::Close)>{ "×" }</button>
+                </header>
+                <ul class="members">
+                    { for self.members.iter().enumerate().map(|m| self.view_member(m)) }
+                </ul>
+                { self.view_invite() }
+                { self.view_links() }
+                { self.view_error() }
+            </div>
+        }
+    }
+}
+
+impl ShareDialog {
+    fn is_owner(&self) -> bool {
+        self.props.list.role() == Role::Owner
+    }
+
+    fn view_member(&self, (idx, member): (usize, &Member)) -> Html {
+        let id = *member.id();
+        let role = if self.is_owner() {
+            view_role_select(
+                member.role(),
+                self.link
+                    .batch_callback(move |role: Option<Role>| role.map(|r| Msg::SetRole(idx, r))),
+            )
+        } else {
+            html! { <span class="role">{ member.role().as_str() }</span> }
+        };
+        let revoke = if id == self.props.me {
+            html! { <button onclick=self.link.callback(move |_| Msg::Revoke(id))>{ "Leave" }</button> }
+        } else if self.is_owner() {
+            html! { <button onclick=self.link.callback(move |_| Msg::Revoke(id))>{ "Revoke" }</button> }
+        } else {
+            html! {}
+        };
+        html! {
+            <li>
+                <span class="username">{ member.username() }</span>
+                { role }
+                { revoke }
+            </li>
+        }
+    }
+
+    fn view_invite(&self) -> Html {
+        if !self.is_owner() {
+            return html! {};
+        }
+        html! {
+            <div class="invite">
+                <input
+                    placeholder="Username"
+                    value=self.username.clone()
+                    oninput=self.link.callback(|e: InputData| Msg::UpdateUsername(e.value))
+                    onkeypress=self.link.batch_callback(|e: KeyboardEvent| {
+                        if e.key() == "Enter" { Some(Msg::Invite) } else { None }
+                    })
+                />
+                { view_role_select(self.role, self.link.batch_callback(|role: Option<Role>| role.map(Msg::SetInviteRole))) }
+                <button disabled=self.ft.is_some() onclick=self.link.callback(|_| Msg::Invite)>
+                    { "Invite" }
+                </button>
+            </div>
+        }
+    }
+
+    fn view_links(&self) -> Html {
+        if !self.is_owner() {
+            return html! {};
+        }
+        html! {
+            <div class="share-links">
+                <h3>{ "Read-only links" }</h3>
+                <ul>
+                    { for self.links.iter().map(|l| self.view_link(l)) }
+                </ul>
+                <div class="new-link">
+                    <select onchange=self.link.batch_callback(|e: ChangeData| match e {
+                        ChangeData::Select(el) => EXPIRY_CHOICES
+                            .get(el.selected_index() as usize)
+                            .map(|(_, secs)| Msg::SetExpiry(*secs)),
+                        _ => None,
+                    })>
+                        { for EXPIRY_CHOICES.iter().map(|(label, secs)| html! {
+                            <option selected=*secs == self.expires_in>{ label }</option>
+                        }) }
+                    </select>
+                    <button disabled=self.ft.is_some() onclick=self.link.callback(|_| Msg::CreateLink)>
+                        { "Create link" }
+                    </button>
+                </div>
+            </div>
+        }
+    }
+
+    fn view_link(&self, link: &ShareLink) -> Html {
+        let id = *link.id();
+        let location = yew::utils::window().location();
+        let url = format!(
+            "{}{}?share={}",
+            location.origin().unwrap_or_default(),
+            location.pathname().unwrap_or_default(),
+            link.token()
+        );
+        let expiry = match link.expires_at() {
+            Some(at) => {
+                let millis = at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let date = js_sys::Date::new(&(millis as f64).into());
+                format!(
+                    "expires {}",
+                    date.to_locale_string("default", &Default::default())
+                )
+            }
+            None => "never expires".to_string(),
+        };
+        html! {
+            <li>
+                <input readonly=true value=url />
+                <span class="expiry">{ expiry }</span>
+                <button onclick=self.link.callback(move |_| Msg::RevokeLink(id))>{ "Revoke" }</button>
+            </li>
+        }
+    }
+
+    fn view_error(&self) -> Html {
+        match &self.error {
+            Some(error) => html! { <div class="notice">{ error }</div> },
+            None => html! {},
+        }
+    }
+
+    fn fetch_members(&mut self) {
+        let uri = format!("/list/members?id={}", self.props.list.id());
+        let callback = self
+            .link
+            .callback(|resp: Response<MsgPack<Result<Members, Error>>>| {
+                let (meta, MsgPack(data)) = resp.into_parts();
+                match data {
+                    Ok(members) if meta.status.is_success() => Msg::Fetched(members),
+                    _ => Msg::Failed(format!(
+                        "Could not load members, status code: {}",
+                        meta.status.as_u16()
+                    )),
+                }
+            });
+        self.start(FetchService::fetch_binary(
+            build_request("GET", uri, Nothing),
+            callback,
+        ));
+    }
+
+    fn fetch_links(&mut self) {
+        let uri = format!("/list/shares?id={}", self.props.list.id());
+        let callback = self
+            .link
+            .callback(|resp: Response<MsgPack<Result<ShareLinks, Error>>>| {
+                let (meta, MsgPack(data)) = resp.into_parts();
+                match data {
+                    Ok(links) if meta.status.is_success() => Msg::FetchedLinks(links),
+                    _ => Msg::Failed(format!(
+                        "Could not load share links, status code: {}",
+                        meta.status.as_u16()
+                    )),
+                }
+            });
+        self.start(FetchService::fetch_binary(
+            build_request("GET", uri, Nothing),
+            callback,
+        ));
+    }
+
+    fn create_link(&mut self) {
+        let uri = format!("/list/shares?id={}", self.props.list.id());
+        let share = ShareRequest {
+            expires_in: self.expires_in,
+        };
+        let callback = self
+            .link
+            .callback(|resp: Response<MsgPack<Result<ShareLink, Error>>>| {
+                let (meta, MsgPack(data)) = resp.into_parts();
+                match data {
+                    Ok(link) if meta.status.is_success() => Msg::LinkCreated(link),
+                    _ => Msg::Failed(format!(
+                        "Could not create a share link, status code: {}",
+                        meta.status.as_u16()
+                    )),
+                }
+            });
+        self.start(FetchService::fetch_binary(
+            build_request("POST", uri, MsgPack(&share)),
+            callback,
+        ));
+    }
+
+    fn invite(&mut self, username: &str, role: Role) {
+        let uri = format!("/list/members?id={}", self.props.list.id());
+        let invite = InviteRequest { username, role };
+        self.send(build_request("POST", uri, MsgPack(&invite)), || Msg::Done);
+    }
+
+    /// Sends a request whose response body is not needed, `done` is the
+    /// message on success.
+    fn send<IN: Into<Binary>>(&mut self, request: Request<IN>, done: fn() -> Msg) {
+        let callback = self.link.callback(move |resp: Response<Binary>| {
+            let status = resp.status();
+            if status.is_success() {
+                done()
+            } else {
+                Msg::Failed(match status.as_u16() {
+                    403 => "Only owners can do that.".to_string(),
+                    404 => "No such user.".to_string(),
+                    409 => "A list needs at least one owner.".to_string(),
+                    code => format!("Something went wrong, status code: {}", code),
+                })
+            }
+        });
+        self.start(FetchService::fetch_binary(request, callback));
+    }
+
+    fn start(&mut self, ft: Result<FetchTask, Error>) {
+        match ft {
+            Ok(ft) => self.ft = Some(ft),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+fn view_role_select(current: Role, onchange: Callback<Option<Role>>) -> Html {
+    html! {
+        <select
+            class="role"
+            onchange=onchange.reform(|e: ChangeData| match e {
+                ChangeData::Select(el) => Role::parse(&el.value()),
+                _ => None,
+            })
+        >
+            { for Role::ALL.iter().map(|r| html! {
+                <option value=r.as_str() selected=*r == current>{ r.as_str() }</option>
+            }) }
+        </select>
+    }
+}
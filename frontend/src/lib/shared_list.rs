@@ -0,0 +1,176 @@
+use super::{build_request, due, priority_marker, state::Filter, view_markdown};
+use anyhow::Error;
+use strum::IntoEnumIterator;
+use todomvc_shared::{Entry, SharedList};
+use yew::{
+    classes,
+    format::{MsgPack, Nothing},
+    html,
+    services::{
+        fetch::{FetchTask, Response},
+        FetchService,
+    },
+    Classes, Component, ComponentLink, Html, Properties, ShouldRender,
+};
+
+pub enum Msg {
+    Fetched(SharedList),
+    Failed(String),
+    SetFilter(Filter),
+}
+
+#[derive(Clone, Properties)]
+pub struct Props {
+    /// The token of the share link, from the `share` query parameter.
+    pub token: String,
+}
+
+/// A list opened through a share link, it can be read but not changed.
+pub struct SharedListView {
+    link: ComponentLink<Self>,
+    list: Option<SharedList>,
+    filter: Filter,
+    error: Option<String>,
+    _ft: Option<FetchTask>,
+}
+
+impl Component for SharedListView {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let uri = format!("/shared?token={}", props.token);
+        let callback = link.callback(|resp: Response<MsgPack<Result<SharedList, Error>>>| {
+            let (meta, MsgPack(data)) = resp.into_parts();
+            match data {
+                Ok(list) if meta.status.is_success() => Msg::Fetched(list),
+                _ if meta.status.as_u16() == 404 => {
+                    Msg::Failed("This link has expired or was revoked.".to_string())
+                }
+                _ => Msg::Failed(format!(
+                    "Could not load the list, status code: {}",
+                    meta.status.as_u16()
+                )),
+            }
+        });
+        let (ft, error) =
+            match FetchService::fetch_binary(build_request("GET", uri, Nothing), callback) {
+                Ok(ft) => (Some(ft), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+        Self {
+            link,
+            list: None,
+            filter: Filter::All,
+            error,
+            _ft: ft,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Fetched(list) => self.list = Some(list),
+            Msg::Failed(reason) => self.error = Some(reason),
+            Msg::SetFilter(filter) => self.filter = filter,
+        }
+        true
+    }
+
+    fn change(&mut self, _: Self::Properties) -> ShouldRender {
+        false
+    }
+
+    fn view(&self) -> Html {
+        let (name, entries) = match &self.list {
+            Some(list) => (list.name().as_str(), list.entries().as_slice()),
+            None => ("todos", &[][..]),
+        };
+        let hidden_class = if entries.is_empty() { "hidden" } else { "" };
+        let left = entries.iter().filter(|e| !*e.completed()).count();
+        html! {
+            <div class="todomvc-wrapper">
+                <section class="todoapp">
+                    <header class="header">
+                        <h1>{ name }</h1>
+                        <p class="read-only">{ "This list was shared with you read-only." }</p>
+                    </header>
+                    { self.view_error() }
+                    <section class=classes!("main", hidden_class)>
+                        <ul class="todo-list">
+                            { for entries.iter().filter(|e| self.filter.fits(e)).map(view_entry) }
+                        </ul>
+                    </section>
+                    <footer class=classes!("footer", hidden_class)>
+                        <span class="todo-count">
+                            <strong>{ left }</strong>
+                            { " item(s) left" }
+                        </span>
+                        <ul class="filters">
+                            { for Filter::iter().map(|flt| self.view_filter(flt)) }
+                        </ul>
+                    </footer>
+                </section>
+                <footer class="info">
+                    <p>{ "Part of " }<a href="http://todomvc.com/" target="_blank">{ "TodoMVC" }</a></p>
+                </footer>
+            </div>
+        }
+    }
+}
+
+impl SharedListView {
+    fn view_filter(&self, filter: Filter) -> Html {
+        let cls = if self.filter == filter {
+            "selected"
+        } else {
+            "not-selected"
+        };
+        html! {
+            <li>
+                <a class=cls
+                   href=filter.as_href()
+                   onclick=self.link.callback(move |_| Msg::SetFilter(filter))
+                >
+                    { filter }
+                </a>
+            </li>
+        }
+    }
+
+    fn view_error(&self) -> Html {
+        match &self.error {
+            Some(error) => html! { <div class="notice">{ error }</div> },
+            None => html! {},
+        }
+    }
+}
+
+fn view_entry(entry: &Entry) -> Html {
+    let mut class = Classes::from("todo");
+    if *entry.completed() {
+        class.push(" completed");
+    }
+    if due::is_overdue(entry) {
+        class.push(" overdue");
+    }
+    let due = match *entry.due_at() {
+        Some(at) => html! { <span class="due">{ due::describe(at) }</span> },
+        None => html! {},
+    };
+    let priority = *entry.priority();
+    html! {
+        <li class=class>
+            <div class="view">
+                <span class=classes!("priority", priority.as_str())>{ priority_marker(priority) }</span>
+                { if *entry.blocked() && !*entry.completed() { html! { <span class="blocked" title="Waiting for other tasks">{ "🔒" }</span> } } else { html! {} } }
+                <label>{ entry.content() }</label>
+                { due }
+                { for entry.recurrence().map(|r| html! { <span class="recurs" title=r.to_rule()>{ "↻" }</span> }) }
+                { if entry.notes().is_empty() { html! {} } else { view_markdown(entry.notes()) } }
+                <div class="tags">
+                    { for entry.tags().iter().map(|t| html! { <span class="tag">{ format!("#{}", t.name()) }</span> }) }
+                </div>
+            </div>
+        </li>
+    }
+}
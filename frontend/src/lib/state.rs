@@ -1,13 +1,29 @@
+use super::due;
 use serde::{Deserialize, Serialize};
-use strum::{EnumIter, ToString};
-use todomvc_shared::Entry;
+use std::{cmp::Ordering, time::SystemTime};
+use strum::{EnumIter, EnumString, ToString};
+use todomvc_shared::{Entry, Priority, Recurrence, Tag, MAX_SUBTASK_DEPTH};
+use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct State {
     pub entries: Vec<Entry>,
     pub filter: Filter,
+    /// How `entries` are kept ordered.
+    pub sort: Sort,
+    /// Entries below it are hidden.
+    pub min_priority: Priority,
+    /// Only entries with this tag are shown, if set.
+    pub tag_filter: Option<Uuid>,
+    /// Entries whose subtasks are hidden.
+    pub collapsed: Vec<Uuid>,
     pub value: String,
     pub edit_value: String,
+    /// The entry being edited in this session, kept out of `Entry` so that
+    /// editing never leaks into other clients.
+    pub editing: Option<Uuid>,
+    /// Entries other sessions hold an edit lease on.
+    pub edited_elsewhere: Vec<Uuid>,
 }
 
 impl State {
@@ -15,6 +31,19 @@ impl State {
         self.entries.len()
     }
 
+    /// Whether an entry is shown, as a closure that does not borrow `self`.
+    pub fn shows(&self) -> impl Fn(&Entry) -> bool {
+        let (filter, min_priority, tag) = (self.filter, self.min_priority, self.tag_filter);
+        move |e| {
+            filter.fits(e)
+                && *e.priority() >= min_priority
+                && match tag {
+                    Some(tag) => e.tags().iter().any(|t| *t.id() == tag),
+                    None => true,
+                }
+        }
+    }
+
     pub fn total_completed(&self) -> usize {
         self.entries
             .iter()
@@ -23,11 +52,8 @@ impl State {
     }
 
     pub fn is_all_completed(&self) -> bool {
-        let mut filtered_iter = self
-            .entries
-            .iter()
-            .filter(|e| self.filter.fits(e))
-            .peekable();
+        let shows = self.shows();
+        let mut filtered_iter = self.entries.iter().filter(|e| shows(e)).peekable();
 
         if filtered_iter.peek().is_none() {
             return false;
@@ -36,104 +62,448 @@ impl State {
         filtered_iter.all(|e| *e.completed())
     }
 
-    pub fn clear_completed(&mut self) {
+    pub fn clear_completed(&mut self) -> Vec<Inverse> {
+        let mut undo = Vec::new();
         let entries = self
             .entries
             .drain(..)
-            .filter(|e| Filter::Active.fits(e))
+            .enumerate()
+            .filter_map(|(idx, e)| {
+                if Filter::Active.fits(&e) {
+                    Some(e)
+                } else {
+                    undo.push(Inverse::Insert(idx, e));
+                    None
+                }
+            })
             .collect();
         self.entries = entries;
+        undo
     }
 
-    pub fn toggle(&mut self, idx: usize) -> Entry {
-        let filter = self.filter;
+    /// `None` if the entry still has open subtasks, those are done first.
+    pub fn toggle(&mut self, idx: usize) -> Option<(Entry, Vec<Inverse>)> {
+        let shows = self.shows();
+        let entry = self.entries.iter().filter(|e| shows(e)).nth(idx).unwrap();
+        let (done, total) = self.progress(*entry.id());
+        if !*entry.completed() && done < total {
+            return None;
+        }
         let entry = self
             .entries
             .iter_mut()
-            .filter(|e| filter.fits(e))
+            .filter(|e| shows(e))
             .nth(idx)
             .unwrap();
+        let before = entry.clone();
         entry.set_completed(!*entry.completed());
-        entry.clone()
+        let entry = entry.clone();
+        let mut undo = vec![Inverse::Replace(before, entry.clone())];
+        undo.extend(self.settle(*entry.parent_id()));
+        self.mark_blocked();
+        Some((entry, undo))
     }
 
-    pub fn toggle_all(&mut self, value: bool) {
-        let filter = self.filter;
+    /// The open entries `entry` waits for.
+    pub fn open_prerequisites(&self, entry: &Entry) -> Vec<&Entry> {
         self.entries
+            .iter()
+            .filter(|e| !*e.completed() && entry.depends_on().contains(e.id()))
+            .collect()
+    }
+
+    /// Works `blocked` out again from the local entries, as the server does,
+    /// once completion changed. Prerequisites that are gone block nothing.
+    pub fn mark_blocked(&mut self) {
+        let open: Vec<Uuid> = self
+            .entries
+            .iter()
+            .filter(|e| !*e.completed())
+            .map(|e| *e.id())
+            .collect();
+        for e in self.entries.iter_mut() {
+            let blocked = e.depends_on().iter().any(|d| open.contains(d));
+            e.set_blocked(blocked);
+        }
+    }
+
+    /// Whether entry `id` waits for entry `on`, directly or through others.
+    pub fn depends_transitively(&self, id: Uuid, on: Uuid) -> bool {
+        let mut seen = vec![id];
+        let mut i = 0;
+        while let Some(current) = seen.get(i).copied() {
+            let entry = self.entries.iter().find(|e| *e.id() == current);
+            for d in entry.map(|e| e.depends_on().as_slice()).unwrap_or_default() {
+                if *d == on {
+                    return true;
+                }
+                if !seen.contains(d) {
+                    seen.push(*d);
+                }
+            }
+            i += 1;
+        }
+        false
+    }
+
+    /// `None` if there is no such entry, it already waits for `on` or `on`
+    /// waits for it.
+    pub fn add_dependency(&mut self, id: Uuid, on: Uuid) -> Option<(Entry, Inverse)> {
+        if id == on || self.depends_transitively(on, id) {
+            return None;
+        }
+        let entry = self.entries.iter_mut().find(|e| *e.id() == id)?;
+        if entry.depends_on().contains(&on) {
+            return None;
+        }
+        let before = entry.clone();
+        let mut depends_on = entry.depends_on().clone();
+        depends_on.push(on);
+        entry.set_depends_on(depends_on);
+        self.mark_blocked();
+        let entry = self.entries.iter().find(|e| *e.id() == id)?.clone();
+        Some((entry.clone(), Inverse::Replace(before, entry)))
+    }
+
+    pub fn remove_dependency(&mut self, id: Uuid, on: Uuid) -> Option<(Entry, Inverse)> {
+        let entry = self.entries.iter_mut().find(|e| *e.id() == id)?;
+        let before = entry.clone();
+        let mut depends_on = entry.depends_on().clone();
+        depends_on.retain(|d| *d != on);
+        entry.set_depends_on(depends_on);
+        self.mark_blocked();
+        let entry = self.entries.iter().find(|e| *e.id() == id)?.clone();
+        Some((entry.clone(), Inverse::Replace(before, entry)))
+    }
+
+    pub fn toggle_all(&mut self, value: bool) -> Vec<Inverse> {
+        let shows = self.shows();
+        let mut undo: Vec<Inverse> = self
+            .entries
             .iter_mut()
-            .filter(|e| filter.fits(e))
-            .for_each(|e| {
+            .filter(|e| shows(e) && *e.completed() != value)
+            .map(|e| {
+                let before = e.clone();
                 e.set_completed(value);
-            });
+                Inverse::Replace(before, e.clone())
+            })
+            .collect();
+        let changed: Vec<Uuid> = undo
+            .iter()
+            .filter_map(|i| match i {
+                Inverse::Replace(e, _) => Some(*e.id()),
+                Inverse::Insert(..) => None,
+            })
+            .collect();
+        for id in changed {
+            let settled = self.settle(Some(id));
+            undo.extend(settled);
+        }
+        // an entry can change more than once, the oldest value goes back last
+        undo.reverse();
+        self.mark_blocked();
+        undo
     }
 
-    pub fn toggle_edit(&mut self, idx: usize) -> Entry {
-        let filter = self.filter;
-        let entry = self
+    /// Subtasks right below entry `id`: how many are done and how many there are.
+    pub fn progress(&self, id: Uuid) -> (usize, usize) {
+        self.entries
+            .iter()
+            .filter(|e| *e.parent_id() == Some(id))
+            .fold((0, 0), |(done, total), e| {
+                (done + *e.completed() as usize, total + 1)
+            })
+    }
+
+    /// How many levels entry `id` is below a top-level entry.
+    pub fn depth(&self, id: Uuid) -> usize {
+        let mut depth = 0;
+        let mut current = id;
+        while let Some(parent) = self
             .entries
-            .iter_mut()
-            .filter(|e| filter.fits(e))
-            .nth(idx)
-            .unwrap();
-        entry.set_editing(!*entry.editing());
+            .iter()
+            .find(|e| *e.id() == current)
+            .and_then(|e| *e.parent_id())
+        {
+            depth += 1;
+            current = parent;
+            if depth > MAX_SUBTASK_DEPTH {
+                break;
+            }
+        }
+        depth
+    }
+
+    /// Keeps completion consistent from entry `from` upwards the way the
+    /// server does: an entry with open subtasks is open, one that completes
+    /// itself is done once all its subtasks are.
+    pub fn settle(&mut self, from: Option<Uuid>) -> Vec<Inverse> {
+        let mut undo = Vec::new();
+        let mut current = from;
+        while let Some(id) = current {
+            let (done, total) = self.progress(id);
+            let entry = match self.entries.iter_mut().find(|e| *e.id() == id) {
+                Some(e) => e,
+                None => break,
+            };
+            let settled = if done < total {
+                false
+            } else {
+                *entry.completed() || (total > 0 && *entry.auto_complete())
+            };
+            // past `from`, the entries further up only change if this one did
+            if settled == *entry.completed() && Some(id) != from {
+                break;
+            }
+            if settled != *entry.completed() {
+                let before = entry.clone();
+                entry.set_completed(settled);
+                undo.push(Inverse::Replace(before, entry.clone()));
+            }
+            current = *entry.parent_id();
+        }
+        undo
+    }
+
+    pub fn toggle_collapsed(&mut self, id: Uuid) {
+        if self.collapsed.contains(&id) {
+            self.collapsed.retain(|c| *c != id);
+        } else {
+            self.collapsed.push(id);
+        }
+    }
+
+    pub fn toggle_auto_complete(&mut self, idx: usize) -> (Entry, Inverse) {
+        self.change(idx, |e| e.set_auto_complete(!*e.auto_complete()))
+    }
+
+    pub fn toggle_edit(&mut self, idx: usize) -> Entry {
+        let shows = self.shows();
+        let entry = self.entries.iter().filter(|e| shows(e)).nth(idx).unwrap();
+        if self.editing == Some(*entry.id()) {
+            self.editing = None;
+        } else {
+            self.editing = Some(*entry.id());
+        }
         entry.clone()
     }
 
-    pub fn clear_all_edit(&mut self) {
-        let filter = self.filter;
-        self.entries
-            .iter_mut()
-            .filter(|e| filter.fits(e))
-            .for_each(|e| {
-                e.set_completed(false);
-            });
+    pub fn is_editing(&self, entry: &Entry) -> bool {
+        self.editing == Some(*entry.id())
+    }
+
+    pub fn is_edited_elsewhere(&self, entry: &Entry) -> bool {
+        self.edited_elsewhere.contains(entry.id())
     }
 
-    pub fn complete_edit(&mut self, idx: usize, val: String) -> (Entry, bool) {
+    pub fn complete_edit(&mut self, idx: usize, val: String) -> (Entry, bool, Vec<Inverse>) {
+        self.editing = None;
         if val.is_empty() {
-            let e = self.remove(idx);
-            (e, true)
+            let (e, undo) = self.remove(idx);
+            (e, true, undo)
         } else {
-            let filter = self.filter;
+            let shows = self.shows();
             let entry = self
                 .entries
                 .iter_mut()
-                .filter(|e| filter.fits(e))
+                .filter(|e| shows(e))
                 .nth(idx)
                 .unwrap();
+            let before = entry.clone();
             entry.set_content(val);
-            entry.set_editing(!*entry.editing());
-            (entry.clone(), false)
+            (
+                entry.clone(),
+                false,
+                vec![Inverse::Replace(before, entry.clone())],
+            )
         }
     }
 
-    pub fn remove(&mut self, idx: usize) -> Entry {
+    pub fn set_due(&mut self, idx: usize, due_at: Option<SystemTime>) -> (Entry, Inverse) {
+        self.change(idx, |e| e.set_due_at(due_at))
+    }
+
+    pub fn set_recurrence(&mut self, idx: usize, r: Option<Recurrence>) -> (Entry, Inverse) {
+        self.change(idx, |e| e.set_recurrence(r))
+    }
+
+    pub fn cycle_priority(&mut self, idx: usize) -> (Entry, Inverse) {
+        self.change(idx, |e| e.set_priority(e.priority().cycle()))
+    }
+
+    /// `None` if there is no such entry or it already has the tag.
+    pub fn add_tag(&mut self, id: Uuid, tag: Tag) -> Option<(Entry, Inverse)> {
+        let entry = self.entries.iter_mut().find(|e| *e.id() == id)?;
+        if entry.tags().iter().any(|t| t.id() == tag.id()) {
+            return None;
+        }
+        let before = entry.clone();
+        let mut tags = entry.tags().clone();
+        tags.push(tag);
+        tags.sort_by(|a, b| a.name().cmp(b.name()));
+        entry.set_tags(tags);
+        Some((entry.clone(), Inverse::Replace(before, entry.clone())))
+    }
+
+    pub fn set_notes(&mut self, id: Uuid, notes: String) -> Option<(Entry, Inverse)> {
+        let entry = self.entries.iter_mut().find(|e| *e.id() == id)?;
+        let before = entry.clone();
+        entry.set_notes(notes);
+        Some((entry.clone(), Inverse::Replace(before, entry.clone())))
+    }
+
+    pub fn remove_tag(&mut self, id: Uuid, tag: Uuid) -> Option<(Entry, Inverse)> {
+        let entry = self.entries.iter_mut().find(|e| *e.id() == id)?;
+        let before = entry.clone();
+        let mut tags = entry.tags().clone();
+        tags.retain(|t| *t.id() != tag);
+        entry.set_tags(tags);
+        Some((entry.clone(), Inverse::Replace(before, entry.clone())))
+    }
+
+    /// Applies `f` to the `idx`th shown entry and sorts the entries again.
+    fn change(&mut self, idx: usize, f: impl FnOnce(&mut Entry)) -> (Entry, Inverse) {
+        let shows = self.shows();
+        let entry = self
+            .entries
+            .iter_mut()
+            .filter(|e| shows(e))
+            .nth(idx)
+            .unwrap();
+        let before = entry.clone();
+        f(entry);
+        let entry = entry.clone();
+        self.sort();
+        (entry.clone(), Inverse::Replace(before, entry))
+    }
+
+    /// Reorders `entries` by `sort`, call after entries are added or
+    /// changed. The sort is stable, so ties keep their order.
+    pub fn sort(&mut self) {
+        match self.sort {
+            Sort::Unsorted => {}
+            Sort::Due => self.entries.sort_by(|a, b| match (a.due_at(), b.due_at()) {
+                (Some(a), Some(b)) => a.cmp(b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }),
+            Sort::Priority => self.entries.sort_by(|a, b| b.priority().cmp(a.priority())),
+        }
+    }
+
+    /// Removes the `idx`th shown entry along with its subtasks, as the
+    /// server does.
+    pub fn remove(&mut self, idx: usize) -> (Entry, Vec<Inverse>) {
+        let shows = self.shows();
         let idx = {
             let entries = self
                 .entries
                 .iter()
                 .enumerate()
-                .filter(|&(_, e)| self.filter.fits(e))
+                .filter(|&(_, e)| shows(e))
                 .collect::<Vec<_>>();
             let &(idx, _) = entries.get(idx).unwrap();
             idx
         };
-        self.entries.remove(idx)
+        let e = self.entries.remove(idx);
+        let mut undo = vec![Inverse::Insert(idx, e.clone())];
+        let mut removed = vec![*e.id()];
+        while let Some(idx) = self
+            .entries
+            .iter()
+            .position(|s| matches!(s.parent_id(), Some(p) if removed.contains(p)))
+        {
+            let sub = self.entries.remove(idx);
+            removed.push(*sub.id());
+            undo.push(Inverse::Insert(idx, sub));
+        }
+        // put back in the opposite order, so that each index is right again
+        undo.reverse();
+        undo.extend(self.settle(*e.parent_id()));
+        self.mark_blocked();
+        (e, undo)
+    }
+
+    /// Undoes unconfirmed mutations, in the order they are given.
+    pub fn rollback(&mut self, undo: Vec<Inverse>) {
+        for inverse in undo {
+            match inverse {
+                Inverse::Insert(idx, e) => {
+                    let idx = idx.min(self.entries.len());
+                    self.entries.insert(idx, e);
+                }
+                Inverse::Replace(before, after) => {
+                    let current = self.entries.iter_mut().find(|e| e.id() == before.id());
+                    // a later change, confirmed or not, is kept
+                    if let Some(e) = current.filter(|e| is_unchanged(e, &after)) {
+                        *e = before;
+                    }
+                }
+            }
+        }
+        self.mark_blocked();
+        self.sort();
     }
 }
 
+/// The inverse of a local mutation, kept until the server confirms it.
+#[derive(Debug, Clone)]
+pub enum Inverse {
+    /// Put a removed entry back at its old position.
+    Insert(usize, Entry),
+    /// Restore the previous value of an entry, if it still has the value the
+    /// mutation gave it.
+    Replace(Entry, Entry),
+}
+
+/// Whether `e` is still `after`, leaving out `blocked`, which changes with
+/// the other entries.
+fn is_unchanged(e: &Entry, after: &Entry) -> bool {
+    let mut e = e.clone();
+    e.set_blocked(*after.blocked());
+    e == *after
+}
+
+/// Entries without a due date go last when sorting by it, the most urgent
+/// come first when sorting by priority.
+#[derive(Clone, Copy, Debug, EnumIter, EnumString, ToString, PartialEq, Serialize, Deserialize)]
+pub enum Sort {
+    Unsorted,
+    Due,
+    Priority,
+}
+
 #[derive(Clone, Copy, Debug, EnumIter, ToString, PartialEq, Serialize, Deserialize)]
 pub enum Filter {
     All,
     Active,
     Completed,
+    /// Unfinished entries due today, in local time.
+    Today,
+    /// Unfinished entries due after today.
+    Upcoming,
+    /// Unfinished entries past their due date.
+    Overdue,
+    /// Unfinished entries waiting for others.
+    Blocked,
 }
 impl Filter {
     pub fn fits(&self, entry: &Entry) -> bool {
+        let due = entry.due_at().map(due::to_millis);
         match *self {
             Filter::All => true,
             Filter::Active => !*entry.completed(),
             Filter::Completed => *entry.completed(),
+            Filter::Today => {
+                let today = due::start_of_day(0)..due::start_of_day(1);
+                !*entry.completed() && matches!(due, Some(at) if today.contains(&at))
+            }
+            Filter::Upcoming => {
+                !*entry.completed() && matches!(due, Some(at) if at >= due::start_of_day(1))
+            }
+            Filter::Overdue => due::is_overdue(entry),
+            Filter::Blocked => !*entry.completed() && *entry.blocked(),
         }
     }
 
@@ -142,6 +512,10 @@ impl Filter {
             Filter::All => "#/",
             Filter::Active => "#/active",
             Filter::Completed => "#/completed",
+            Filter::Today => "#/today",
+            Filter::Upcoming => "#/upcoming",
+            Filter::Overdue => "#/overdue",
+            Filter::Blocked => "#/blocked",
         }
     }
 }
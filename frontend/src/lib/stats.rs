@@ -0,0 +1,241 @@
+use super::{build_request, due};
+use anyhow::Error;
+use js_sys::Date;
+use std::time::{Duration, SystemTime};
+use todomvc_shared::{Period, Stats};
+use uuid::Uuid;
+use yew::{
+    format::{MsgPack, Nothing},
+    html,
+    services::{
+        fetch::{FetchTask, Response},
+        FetchService,
+    },
+    Callback, Component, ComponentLink, Html, Properties, ShouldRender,
+};
+
+/// Size of a chart in svg units, bars are scaled to fit.
+const CHART_WIDTH: f64 = 560.0;
+const CHART_HEIGHT: f64 = 140.0;
+/// Room below the bars for the dates.
+const LABEL_HEIGHT: f64 = 18.0;
+
+pub enum Msg {
+    Fetched(Stats),
+    /// Shows every list, or only the one the page was opened from.
+    SetAll(bool),
+    Failed(String),
+    Close,
+}
+
+#[derive(Clone, Properties)]
+pub struct Props {
+    /// The list shown before the page was opened.
+    pub list: Option<Uuid>,
+    pub on_close: Callback<()>,
+}
+
+/// Page charting how many tasks were created and completed lately, with
+/// counts of the open ones.
+pub struct StatsPage {
+    link: ComponentLink<Self>,
+    props: Props,
+    stats: Option<Stats>,
+    all: bool,
+    error: Option<String>,
+    ft: Option<FetchTask>,
+}
+
+impl Component for StatsPage {
+    type Message = Msg;
+    type Properties = Props;
+
+    fn create(props: Self::Properties, link: ComponentLink<Self>) -> Self {
+        let mut page = Self {
+            link,
+            all: props.list.is_none(),
+            props,
+            stats: None,
+            error: None,
+            ft: None,
+        };
+        page.fetch_stats();
+        page
+    }
+
+    fn update(&mut self, msg: Self::Message) -> ShouldRender {
+        match msg {
+            Msg::Fetched(stats) => {
+                self.ft = None;
+                self.error = None;
+                self.stats = Some(stats);
+                true
+            }
+            Msg::SetAll(all) => {
+                self.all = all;
+                self.fetch_stats();
+                true
+            }
+            Msg::Failed(reason) => {
+                self.ft = None;
+                self.error = Some(reason);
+                true
+            }
+            Msg::Close => {
+                self.props.on_close.emit(());
+                false
+            }
+        }
+    }
+
+    fn change(&mut self, props: Self::Properties) -> ShouldRender {
+        self.props = props;
+        true
+    }
+
+    fn view(&self) -> Html {
+        let all = self.all;
+        let scope = if self.props.list.is_some() {
+            html! {
+                <label class="scope">
+                    <input
+                        type="checkbox"
+                        checked=self.all
+                        onclick=self.link.callback(move |_| Msg::SetAll(!all))
+                    />
+                    { " All lists" }
+                </label>
+            }
+        } else {
+            html! {}
+        };
+        html! {
+            <section class="stats">
+                <header>
+                    <h2>{ "Statistics" }</h2>
+                    <button class="close" onclick=self.link.callback(|_| Msg::Close)>{ "×" }</button>
+                </header>
+                { scope }
+                { self.view_error() }
+                { match &self.stats {
+                    Some(stats) => view_stats(stats),
+                    None => html! {},
+                } }
+            </section>
+        }
+    }
+}
+
+impl StatsPage {
+    fn view_error(&self) -> Html {
+        match &self.error {
+            Some(error) => html! { <div class="notice">{ error }</div> },
+            None => html! {},
+        }
+    }
+
+    fn fetch_stats(&mut self) {
+        let uri = match self.props.list {
+            Some(list) if !self.all => format!("/stats?list={}", list),
+            _ => "/stats".to_string(),
+        };
+        let callback = self
+            .link
+            .callback(|resp: Response<MsgPack<Result<Stats, Error>>>| {
+                let (meta, MsgPack(data)) = resp.into_parts();
+                match data {
+                    Ok(stats) if meta.status.is_success() => Msg::Fetched(stats),
+                    _ => Msg::Failed(format!(
+                        "Could not load statistics, status code: {}",
+                        meta.status.as_u16()
+                    )),
+                }
+            });
+        match FetchService::fetch_binary(build_request("GET", uri, Nothing), callback) {
+            Ok(ft) => self.ft = Some(ft),
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+fn view_stats(stats: &Stats) -> Html {
+    let average = stats
+        .average_completion()
+        .map(describe_duration)
+        .unwrap_or_else(|| "–".to_string());
+    html! {
+        <>
+            <dl class="counts">
+                <div><dt>{ "Open" }</dt><dd>{ stats.open() }</dd></div>
+                <div><dt>{ "Overdue" }</dt><dd>{ stats.overdue() }</dd></div>
+                <div><dt>{ "Blocked" }</dt><dd>{ stats.blocked() }</dd></div>
+                <div><dt>{ "Completed" }</dt><dd>{ stats.completed() }</dd></div>
+                <div><dt>{ "Average time to complete" }</dt><dd>{ average }</dd></div>
+            </dl>
+            <p class="legend">
+                <span class="created">{ "■" }</span>{ " created " }
+                <span class="completed">{ "■" }</span>{ " completed" }
+            </p>
+            <h3>{ "Per day" }</h3>
+            { view_chart(stats.days()) }
+            <h3>{ "Per week" }</h3>
+            { view_chart(stats.weeks()) }
+        </>
+    }
+}
+
+/// Bars of the tasks created and completed in each period, side by side.
+fn view_chart(periods: &[Period]) -> Html {
+    let max = periods
+        .iter()
+        .map(|p| p.created().max(p.completed()))
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+    let slot = CHART_WIDTH / periods.len().max(1) as f64;
+    let bar = slot * 0.4;
+    let height = |count: i64| count as f64 / max * CHART_HEIGHT;
+    let view_bar = |class: &'static str, x: f64, count: i64| {
+        let h = height(count);
+        html! {
+            <rect class=class x=x.to_string() y=(CHART_HEIGHT - h).to_string() width=bar.to_string() height=h.to_string()>
+                <title>{ format!("{} {}", count, class) }</title>
+            </rect>
+        }
+    };
+    html! {
+        <svg
+            class="chart"
+            viewBox=format!("0 0 {} {}", CHART_WIDTH, CHART_HEIGHT + LABEL_HEIGHT)
+        >
+            <line class="axis" x1="0" y1=CHART_HEIGHT.to_string() x2=CHART_WIDTH.to_string() y2=CHART_HEIGHT.to_string() />
+            { for periods.iter().enumerate().map(|(i, p)| {
+                let x = i as f64 * slot + slot * 0.1;
+                html! {
+                    <g>
+                        { view_bar("created", x, p.created()) }
+                        { view_bar("completed", x + bar, p.completed()) }
+                        <text x=(x + bar).to_string() y=(CHART_HEIGHT + LABEL_HEIGHT - 4.0).to_string() text-anchor="middle">
+                            { describe_start(p.start()) }
+                        </text>
+                    </g>
+                }
+            }) }
+        </svg>
+    }
+}
+
+/// Day and month a period starts on, in UTC like the server counts them.
+fn describe_start(start: SystemTime) -> String {
+    let date = Date::new(&due::to_millis(start).into());
+    format!("{}/{}", date.get_utc_date(), date.get_utc_month() + 1)
+}
+
+fn describe_duration(d: Duration) -> String {
+    let minutes = d.as_secs() / 60;
+    match minutes {
+        m if m < 60 => format!("{} min", m),
+        m if m < 24 * 60 => format!("{:.1} h", m as f64 / 60.0),
+        m => format!("{:.1} days", m as f64 / (24.0 * 60.0)),
+    }
+}
@@ -1,5 +1,10 @@
+pub mod markdown;
+mod recurrence;
+
 use getset::*;
+pub use recurrence::{Recurrence, MAX_RECURRENCE_DAYS};
 use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Copy, CopyGetters, Serialize, Deserialize)]
@@ -11,15 +16,508 @@ pub struct Query {
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct TaskRequest<'a> {
     pub content: &'a str,
+    /// Client-generated id, creating the same id twice returns the existing task.
+    #[serde(default)]
+    pub id: Option<Uuid>,
+    #[serde(default)]
+    pub priority: Priority,
+    /// Creates the task as a subtask of this one, in the parent's list.
+    #[serde(default)]
+    pub parent: Option<Uuid>,
+}
+
+/// How many levels subtasks can be nested below a top-level task.
+pub const MAX_SUBTASK_DEPTH: usize = 3;
+
+/// How urgent a task is, each priority is above the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    None,
+    Low,
+    Medium,
+    High,
+}
+
+// deriving it with `#[default]` on a variant needs a newer compiler
+#[allow(clippy::derivable_impls)]
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::None
+    }
+}
+
+impl Priority {
+    pub const ALL: [Priority; 4] = [
+        Priority::None,
+        Priority::Low,
+        Priority::Medium,
+        Priority::High,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::None => "none",
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|p| p.as_str() == s)
+    }
+
+    /// The number stored for the priority, higher is more urgent.
+    pub fn level(&self) -> i16 {
+        *self as i16
+    }
+
+    /// Unknown levels are treated as no priority.
+    pub fn from_level(level: i16) -> Self {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|p| p.level() == level)
+            .unwrap_or_default()
+    }
+
+    /// The next priority, wrapping around from `High` to `None`.
+    pub fn cycle(&self) -> Self {
+        Self::from_level((self.level() + 1) % Self::ALL.len() as i16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ListRequest<'a> {
+    pub name: &'a str,
+    /// Client-generated id, creating the same id twice returns the existing list.
+    #[serde(default)]
+    pub id: Option<Uuid>,
+}
+
+/// What a member may do with a list, each role includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Role {
+    /// Reads the tasks.
+    Viewer,
+    /// Creates, updates and removes tasks.
+    Editor,
+    /// Renames, removes and shares the list.
+    Owner,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Viewer, Role::Editor, Role::Owner];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Owner => "owner",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|r| r.as_str() == s)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct TaskList {
+    #[getset(get = "pub")]
+    id: Uuid,
+    #[getset(get = "pub")]
+    name: String,
+    /// The role of the requesting user.
+    #[getset(get_copy = "pub")]
+    role: Role,
+}
+
+impl TaskList {
+    pub fn new(id: Uuid, name: String, role: Role) -> Self {
+        Self { id, name, role }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TagRequest<'a> {
+    pub name: &'a str,
+}
+
+/// A tag of the requesting user, or one another member put on a task.
+#[derive(Debug, Clone, PartialEq, Getters, Serialize, Deserialize)]
+#[getset(get = "pub")]
+pub struct Tag {
+    id: Uuid,
+    name: String,
+}
+
+impl Tag {
+    pub fn new(id: Uuid, name: String) -> Self {
+        Self { id, name }
+    }
+}
+
+/// A file attached to a task, downloaded from `/attachment?id=`.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct Attachment {
+    #[getset(get = "pub")]
+    id: Uuid,
+    #[getset(get = "pub")]
+    name: String,
+    #[getset(get = "pub")]
+    content_type: String,
+    /// In bytes.
+    #[getset(get_copy = "pub")]
+    size: u64,
+}
+
+impl Attachment {
+    pub fn new(id: Uuid, name: String, content_type: String, size: u64) -> Self {
+        Self {
+            id,
+            name,
+            content_type,
+            size,
+        }
+    }
+}
+
+/// A task matching a search.
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct SearchHit {
+    #[getset(get = "pub")]
+    id: Uuid,
+    #[getset(get = "pub")]
+    list_id: Uuid,
+    #[getset(get = "pub")]
+    content: String,
+    /// Higher for better matches, hits come best first.
+    #[getset(get_copy = "pub")]
+    rank: f32,
+    /// Where the task matches, in its content or notes, as pieces of text
+    /// flagged `true` for the matching words.
+    #[getset(get = "pub")]
+    snippet: Vec<(String, bool)>,
+}
+
+impl SearchHit {
+    pub fn new(
+        id: Uuid,
+        list_id: Uuid,
+        content: String,
+        rank: f32,
+        snippet: Vec<(String, bool)>,
+    ) -> Self {
+        Self {
+            id,
+            list_id,
+            content,
+            rank,
+            snippet,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct InviteRequest<'a> {
+    pub username: &'a str,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, PartialEq, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct Member {
+    #[getset(get = "pub")]
+    id: Uuid,
+    #[getset(get = "pub")]
+    username: String,
+    #[getset(get_copy = "pub")]
+    role: Role,
+}
+
+impl Member {
+    pub fn new(id: Uuid, username: String, role: Role) -> Self {
+        Self { id, username, role }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ShareRequest {
+    /// Lifetime in seconds, the link never expires if unset.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+/// A read-only link to a list, `token` is the signed secret to put in the url.
+#[derive(Debug, Clone, PartialEq, Getters, Serialize, Deserialize)]
+#[getset(get = "pub")]
+pub struct ShareLink {
+    id: Uuid,
+    token: String,
+    expires_at: Option<SystemTime>,
 }
 
-#[derive(Debug, Clone, Getters, Setters, Serialize, Deserialize)]
+impl ShareLink {
+    pub fn new(id: Uuid, token: String, expires_at: Option<SystemTime>) -> Self {
+        Self {
+            id,
+            token,
+            expires_at,
+        }
+    }
+}
+
+/// What a share link shows, without anything that identifies the members.
+#[derive(Debug, Clone, Getters, Serialize, Deserialize)]
+#[getset(get = "pub")]
+pub struct SharedList {
+    name: String,
+    entries: Entries,
+}
+
+impl SharedList {
+    pub fn new(name: String, entries: Entries) -> Self {
+        Self { name, entries }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Credentials<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+}
+
+#[derive(Debug, Clone, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct UserInfo {
+    #[getset(get = "pub")]
+    id: Uuid,
+    #[getset(get = "pub")]
+    username: String,
+    /// Admins can open the admin page.
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    admin: bool,
+}
+
+impl UserInfo {
+    pub fn new(id: Uuid, username: String, admin: bool) -> Self {
+        Self {
+            id,
+            username,
+            admin,
+        }
+    }
+}
+
+/// Ways to log in besides a password, for the login form.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoginOptions {
+    /// Name of the single sign-on provider, if one is configured.
+    pub sso: Option<String>,
+}
+
+/// A user as shown on the admin page.
+#[derive(Debug, Clone, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct UserStats {
+    #[getset(get = "pub")]
+    id: Uuid,
+    #[getset(get = "pub")]
+    username: String,
+    #[getset(get_copy = "pub")]
+    admin: bool,
+    #[getset(get_copy = "pub")]
+    disabled: bool,
+    #[getset(get_copy = "pub")]
+    created_at: SystemTime,
+    /// Tasks and lists the user owns.
+    #[getset(get_copy = "pub")]
+    tasks: i64,
+    #[getset(get_copy = "pub")]
+    lists: i64,
+    /// Bytes of task content and notes the user owns, plus the sizes of the
+    /// files attached to their tasks.
+    #[getset(get_copy = "pub")]
+    storage_bytes: i64,
+}
+
+impl UserStats {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: Uuid,
+        username: String,
+        admin: bool,
+        disabled: bool,
+        created_at: SystemTime,
+        tasks: i64,
+        lists: i64,
+        storage_bytes: i64,
+    ) -> Self {
+        Self {
+            id,
+            username,
+            admin,
+            disabled,
+            created_at,
+            tasks,
+            lists,
+            storage_bytes,
+        }
+    }
+}
+
+/// Tasks created and completed in the day or week from `start`, in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters, Serialize, Deserialize)]
+#[getset(get_copy = "pub")]
+pub struct Period {
+    start: SystemTime,
+    created: i64,
+    /// Tasks completed in the period that are still completed.
+    completed: i64,
+}
+
+impl Period {
+    pub fn new(start: SystemTime, created: i64, completed: i64) -> Self {
+        Self {
+            start,
+            created,
+            completed,
+        }
+    }
+}
+
+/// How the tasks a user can read are coming along, for the stats page.
+#[derive(Debug, Clone, Getters, CopyGetters, Serialize, Deserialize)]
+pub struct Stats {
+    /// The last `STATS_DAYS` days, oldest first.
+    #[getset(get = "pub")]
+    days: Vec<Period>,
+    /// The last `STATS_WEEKS` weeks from Monday, oldest first.
+    #[getset(get = "pub")]
+    weeks: Vec<Period>,
+    /// Mean time from creating a task to completing it, `None` until a task
+    /// is completed.
+    #[getset(get_copy = "pub")]
+    average_completion: Option<Duration>,
+    #[getset(get_copy = "pub")]
+    open: i64,
+    /// Open tasks past their due date.
+    #[getset(get_copy = "pub")]
+    overdue: i64,
+    /// Open tasks waiting for others.
+    #[getset(get_copy = "pub")]
+    blocked: i64,
+    #[getset(get_copy = "pub")]
+    completed: i64,
+}
+
+impl Stats {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        days: Vec<Period>,
+        weeks: Vec<Period>,
+        average_completion: Option<Duration>,
+        open: i64,
+        overdue: i64,
+        blocked: i64,
+        completed: i64,
+    ) -> Self {
+        Self {
+            days,
+            weeks,
+            average_completion,
+            open,
+            overdue,
+            blocked,
+            completed,
+        }
+    }
+}
+
+/// Days and weeks `Stats` go back.
+pub const STATS_DAYS: i32 = 14;
+pub const STATS_WEEKS: i32 = 12;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TokenRequest<'a> {
+    pub name: &'a str,
+    /// Tokens with read-only scope are rejected by routes that modify tasks.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Lifetime in seconds, the token never expires if unset.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Clone, Getters, Serialize, Deserialize)]
+#[getset(get = "pub")]
+pub struct TokenInfo {
+    id: Uuid,
+    name: String,
+    read_only: bool,
+    expires_at: Option<SystemTime>,
+}
+
+impl TokenInfo {
+    pub fn new(id: Uuid, name: String, read_only: bool, expires_at: Option<SystemTime>) -> Self {
+        Self {
+            id,
+            name,
+            read_only,
+            expires_at,
+        }
+    }
+}
+
+/// A freshly created token, the secret is only ever returned here.
+#[derive(Debug, Clone, Getters, Serialize, Deserialize)]
+#[getset(get = "pub")]
+pub struct NewToken {
+    info: TokenInfo,
+    token: String,
+}
+
+impl NewToken {
+    pub fn new(info: TokenInfo, token: String) -> Self {
+        Self { info, token }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Getters, Setters, Serialize, Deserialize)]
 #[getset(get = "pub")]
 pub struct Entry {
     id: Uuid,
     content: String,
     completed: bool,
-    editing: bool,
+    /// When the task is due, clients show it in their own time zone.
+    #[serde(default)]
+    due_at: Option<SystemTime>,
+    #[serde(default)]
+    priority: Priority,
+    /// Set through the tag routes, updating an entry leaves its tags alone.
+    #[serde(default)]
+    tags: Vec<Tag>,
+    /// Set when the task is created or through the parent route, updating
+    /// an entry leaves it alone.
+    #[serde(default)]
+    parent_id: Option<Uuid>,
+    /// Whether the task completes itself once all its subtasks are done.
+    #[serde(default)]
+    auto_complete: bool,
+    /// Completing the task creates its next occurrence, which takes the
+    /// rule over.
+    #[serde(default)]
+    recurrence: Option<Recurrence>,
+    /// Long-form markdown, rendered with `markdown::render`.
+    #[serde(default)]
+    notes: String,
+    /// Tasks of the same list that have to be completed first. Set through
+    /// the dependency routes, updating an entry leaves them alone.
+    #[serde(default)]
+    depends_on: Vec<Uuid>,
+    /// Whether any task in `depends_on` is still open, worked out by the
+    /// server.
+    #[serde(default)]
+    blocked: bool,
 }
 
 impl Default for Entry {
@@ -28,7 +526,15 @@ impl Default for Entry {
             id: Uuid::nil(),
             content: String::new(),
             completed: false,
-            editing: false,
+            due_at: None,
+            priority: Priority::None,
+            tags: Vec::new(),
+            parent_id: None,
+            auto_complete: false,
+            recurrence: None,
+            notes: String::new(),
+            depends_on: Vec::new(),
+            blocked: false,
         }
     }
 }
@@ -39,7 +545,15 @@ impl Entry {
             id,
             content,
             completed: false,
-            editing: false,
+            due_at: None,
+            priority: Priority::None,
+            tags: Vec::new(),
+            parent_id: None,
+            auto_complete: false,
+            recurrence: None,
+            notes: String::new(),
+            depends_on: Vec::new(),
+            blocked: false,
         }
     }
     pub fn set_id(&mut self, id: Uuid) {
@@ -51,11 +565,45 @@ impl Entry {
     pub fn set_completed(&mut self, completed: bool) {
         self.completed = completed;
     }
-    pub fn set_editing(&mut self, editing: bool) {
-        self.editing = editing;
+    pub fn set_due_at(&mut self, due_at: Option<SystemTime>) {
+        self.due_at = due_at;
+    }
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+    pub fn set_tags(&mut self, tags: Vec<Tag>) {
+        self.tags = tags;
+    }
+    pub fn set_parent_id(&mut self, parent_id: Option<Uuid>) {
+        self.parent_id = parent_id;
+    }
+    pub fn set_auto_complete(&mut self, auto_complete: bool) {
+        self.auto_complete = auto_complete;
+    }
+    pub fn set_recurrence(&mut self, recurrence: Option<Recurrence>) {
+        self.recurrence = recurrence;
+    }
+    pub fn set_notes(&mut self, notes: String) {
+        self.notes = notes;
+    }
+    pub fn set_depends_on(&mut self, depends_on: Vec<Uuid>) {
+        self.depends_on = depends_on;
+    }
+    pub fn set_blocked(&mut self, blocked: bool) {
+        self.blocked = blocked;
     }
 }
 
 pub type UpdateRequest = Entry;
 pub type UpdateAll = Vec<UpdateRequest>;
 pub type Entries = Vec<Entry>;
+/// Ids of the tasks other sessions are editing.
+pub type Editing = Vec<Uuid>;
+pub type Lists = Vec<TaskList>;
+pub type Tags = Vec<Tag>;
+pub type Attachments = Vec<Attachment>;
+pub type SearchHits = Vec<SearchHit>;
+pub type Members = Vec<Member>;
+pub type ShareLinks = Vec<ShareLink>;
+pub type Tokens = Vec<TokenInfo>;
+pub type Users = Vec<UserStats>;
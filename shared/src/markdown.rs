@@ -0,0 +1,280 @@
+//! The markdown notes are written in: paragraphs, headings, lists, fenced
+//! code blocks, inline code, emphasis and links. Everything but the tags
+//! generated here is escaped, so notes cannot inject markup, and links only
+//! go to web and mail addresses.
+
+/// Renders `src` to sanitized html.
+pub fn render(src: &str) -> String {
+    let mut out = String::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut lines = src.lines().peekable();
+    while let Some(line) = lines.next() {
+        let line = line.trim_start();
+        if line.starts_with("```") {
+            flush(&mut paragraph, &mut out);
+            out.push_str("<pre><code>");
+            for code in lines.by_ref() {
+                if code.trim_start().starts_with("```") {
+                    break;
+                }
+                escape(code, &mut out);
+                out.push('\n');
+            }
+            out.push_str("</code></pre>\n");
+        } else if let Some((ordered, item)) = list_item(line) {
+            flush(&mut paragraph, &mut out);
+            let tag = if ordered { "ol" } else { "ul" };
+            out.push_str(&format!("<{}>\n", tag));
+            let mut item = Some(item);
+            while let Some(text) = item {
+                out.push_str("<li>");
+                inline(text, &mut out);
+                out.push_str("</li>\n");
+                item = match lines.peek().and_then(|l| list_item(l.trim_start())) {
+                    Some((o, text)) if o == ordered => {
+                        lines.next();
+                        Some(text)
+                    }
+                    _ => None,
+                };
+            }
+            out.push_str(&format!("</{}>\n", tag));
+        } else if let Some((level, text)) = heading(line) {
+            flush(&mut paragraph, &mut out);
+            out.push_str(&format!("<h{}>", level));
+            inline(text, &mut out);
+            out.push_str(&format!("</h{}>\n", level));
+        } else if line.is_empty() {
+            flush(&mut paragraph, &mut out);
+        } else {
+            paragraph.push(line);
+        }
+    }
+    flush(&mut paragraph, &mut out);
+    out
+}
+
+fn flush(paragraph: &mut Vec<&str>, out: &mut String) {
+    if paragraph.is_empty() {
+        return;
+    }
+    out.push_str("<p>");
+    inline(&paragraph.join("\n"), out);
+    out.push_str("</p>\n");
+    paragraph.clear();
+}
+
+/// `- item`, `* item` and `+ item`, or `1. item` for ordered lists.
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    for bullet in &["- ", "* ", "+ "] {
+        if let Some(item) = line.strip_prefix(bullet) {
+            return Some((false, item));
+        }
+    }
+    let digits = line.find(|c: char| !c.is_ascii_digit())?;
+    match line[digits..].strip_prefix(". ") {
+        Some(item) if digits > 0 => Some((true, item)),
+        _ => None,
+    }
+}
+
+/// `# heading` down to `###### heading`.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.find(|c| c != '#')?;
+    match line[level..].strip_prefix(' ') {
+        Some(text) if (1..=6).contains(&level) => Some((level, text)),
+        _ => None,
+    }
+}
+
+/// Delimiters that are not closed anywhere in the rest of a text. A search
+/// for the end of a delimiter that fails once fails from every later position
+/// as well, so each search fails at most once and `inline` stays linear.
+#[derive(Default)]
+struct Unclosed {
+    code: bool,
+    strong: bool,
+    em: bool,
+    link: bool,
+}
+
+/// Inline code, `**strong**`, `*emphasis*` and `[links](https://...)`.
+fn inline(text: &str, out: &mut String) {
+    let mut unclosed = Unclosed::default();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let after = &rest[c.len_utf8()..];
+        match c {
+            '\\' => {
+                if let Some(next) = after.chars().next().filter(char::is_ascii_punctuation) {
+                    escape_char(next, out);
+                    rest = &after[1..];
+                    continue;
+                }
+            }
+            '`' if !unclosed.code => match after.find('`') {
+                Some(end) => {
+                    out.push_str("<code>");
+                    escape(&after[..end], out);
+                    out.push_str("</code>");
+                    rest = &after[end + 1..];
+                    continue;
+                }
+                None => unclosed.code = true,
+            },
+            '*' => {
+                let strong = after.starts_with('*');
+                let (tag, marker) = if strong {
+                    ("strong", "**")
+                } else {
+                    ("em", "*")
+                };
+                let inner = &rest[marker.len()..];
+                // no `*` left closes no `**` either
+                let found = if unclosed.em || (strong && unclosed.strong) {
+                    None
+                } else {
+                    inner.find(marker)
+                };
+                match found {
+                    Some(end) if end > 0 => {
+                        out.push_str(&format!("<{}>", tag));
+                        inline(&inner[..end], out);
+                        out.push_str(&format!("</{}>", tag));
+                        rest = &inner[end + marker.len()..];
+                        continue;
+                    }
+                    Some(_) => {}
+                    None if strong => unclosed.strong = true,
+                    None => unclosed.em = true,
+                }
+            }
+            '[' if !unclosed.link => match link(rest) {
+                Some((label, url, len)) => {
+                    if is_safe_url(url) {
+                        out.push_str("<a href=\"");
+                        escape(url, out);
+                        out.push_str("\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">");
+                        inline(label, out);
+                        out.push_str("</a>");
+                    } else {
+                        inline(label, out);
+                    }
+                    rest = &rest[len..];
+                    continue;
+                }
+                None => unclosed.link = true,
+            },
+            _ => {}
+        }
+        escape_char(c, out);
+        rest = after;
+    }
+}
+
+/// The label and url of a `[label](url "title")` at the start of `text`,
+/// with the length of the whole link. Titles are dropped.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let close = text.find("](")?;
+    let end = text[close + 2..].find(')')? + close + 2;
+    let url = text[close + 2..end].split_whitespace().next().unwrap_or("");
+    Some((&text[1..close], url, end + 1))
+}
+
+fn is_safe_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+}
+
+fn escape(text: &str, out: &mut String) {
+    text.chars().for_each(|c| escape_char(c, out));
+}
+
+fn escape_char(c: char, out: &mut String) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        c => out.push(c),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_only_go_to_web_and_mail_addresses() {
+        assert_eq!(
+            render("[draft](https://example.com/draft \"title\")"),
+            "<p><a href=\"https://example.com/draft\" rel=\"nofollow noopener noreferrer\" \
+             target=\"_blank\">draft</a></p>\n"
+        );
+        assert!(render("[mail](MAILTO:a@example.com)").contains("href=\"MAILTO:a@example.com\""));
+        for url in [
+            "javascript:alert`1`",
+            "JavaScript:alert`1`",
+            " javascript:alert`1`",
+            "data:text/html;base64,PHNjcmlwdD4=",
+            "vbscript:msgbox",
+            "//example.com",
+        ] {
+            let html = render(&format!("[click]({})", url));
+            assert_eq!(html, "<p>click</p>\n", "for {}", url);
+        }
+    }
+
+    #[test]
+    fn raw_html_is_escaped() {
+        assert_eq!(
+            render("<script>alert(1)</script>"),
+            "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n"
+        );
+        assert_eq!(
+            render("- <img src=x onerror=alert(1)>"),
+            "<ul>\n<li>&lt;img src=x onerror=alert(1)&gt;</li>\n</ul>\n"
+        );
+        assert_eq!(
+            render("```\n<script>\n```"),
+            "<pre><code>&lt;script&gt;\n</code></pre>\n"
+        );
+        assert_eq!(render("`<b>`"), "<p><code>&lt;b&gt;</code></p>\n");
+    }
+
+    #[test]
+    fn urls_cannot_break_out_of_the_attribute() {
+        assert_eq!(
+            render("[x](https://example.com/\"onmouseover=\"alert`1`)"),
+            "<p><a href=\"https://example.com/&quot;onmouseover=&quot;alert`1`\" \
+             rel=\"nofollow noopener noreferrer\" target=\"_blank\">x</a></p>\n"
+        );
+        let html = render("[x](https://example.com/'><script>)");
+        assert!(html.contains("href=\"https://example.com/&#39;&gt;&lt;script&gt;\""));
+        assert!(!html.contains("<script"));
+    }
+
+    #[test]
+    fn emphasis_nests() {
+        assert_eq!(
+            render("**bold *and italic* text**"),
+            "<p><strong>bold <em>and italic</em> text</strong></p>\n"
+        );
+        assert_eq!(
+            render("[**docs** `a*b`](https://example.com)"),
+            "<p><a href=\"https://example.com\" rel=\"nofollow noopener noreferrer\" \
+             target=\"_blank\"><strong>docs</strong> <code>a*b</code></a></p>\n"
+        );
+        assert_eq!(render("2 * 3 = 6"), "<p>2 * 3 = 6</p>\n");
+        assert_eq!(render("\\*not em\\*"), "<p>*not em*</p>\n");
+    }
+
+    #[test]
+    fn unclosed_delimiters_render_as_text() {
+        let text = format!("{}`*", "[x ".repeat(100_000));
+        assert_eq!(render(&text), format!("<p>{}</p>\n", text));
+    }
+}
@@ -0,0 +1,258 @@
+//! Rules for tasks that come back once they are completed. Dates are worked
+//! out in UTC, keeping the time of day of the due date.
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Longest wait `Recurrence::AfterCompletion` allows, in days.
+pub const MAX_RECURRENCE_DAYS: u16 = 366;
+
+/// When the next occurrence of a completed task is due.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Recurrence {
+    Daily,
+    /// On the weekdays set in the mask, bit 0 for Monday up to bit 6 for
+    /// Sunday.
+    Weekly(u8),
+    /// On the day of the month of the due date, or the last day of shorter
+    /// months.
+    Monthly,
+    /// The given number of days after the task was completed.
+    AfterCompletion(u16),
+}
+
+impl Recurrence {
+    pub const WEEKDAYS: [&'static str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Recurrence::Weekly(mask) if mask == 0 || mask >= 1 << 7 => {
+                Err("A weekly recurrence needs at least one weekday.".to_string())
+            }
+            Recurrence::AfterCompletion(days) if days == 0 || days > MAX_RECURRENCE_DAYS => {
+                Err(format!(
+                    "A task can come back 1 to {} days after it is completed.",
+                    MAX_RECURRENCE_DAYS
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// How the rule is stored, e.g. `weekly:mon,thu` or `after:3`.
+    pub fn to_rule(&self) -> String {
+        match *self {
+            Recurrence::Daily => "daily".to_string(),
+            Recurrence::Weekly(mask) => {
+                let days: Vec<_> = Self::WEEKDAYS
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & 1 << i != 0)
+                    .map(|(_, d)| *d)
+                    .collect();
+                format!("weekly:{}", days.join(","))
+            }
+            Recurrence::Monthly => "monthly".to_string(),
+            Recurrence::AfterCompletion(days) => format!("after:{}", days),
+        }
+    }
+
+    /// Reads `to_rule`, `None` for unknown or invalid rules.
+    pub fn parse(rule: &str) -> Option<Self> {
+        let r = match rule.split_once(':') {
+            None if rule == "daily" => Recurrence::Daily,
+            None if rule == "monthly" => Recurrence::Monthly,
+            Some(("weekly", days)) => {
+                let mut mask = 0;
+                for day in days.split(',') {
+                    let i = Self::WEEKDAYS.iter().position(|d| *d == day)?;
+                    mask |= 1 << i;
+                }
+                Recurrence::Weekly(mask)
+            }
+            Some(("after", days)) => Recurrence::AfterCompletion(days.parse().ok()?),
+            _ => return None,
+        };
+        r.validate().ok().map(|_| r)
+    }
+
+    /// When the occurrence after one due at `due` and completed at
+    /// `completed_at` is due: the first day fitting the rule after both,
+    /// counting from the completion for tasks without a due date.
+    pub fn next_due(&self, due: Option<SystemTime>, completed_at: SystemTime) -> SystemTime {
+        let done = to_secs(completed_at);
+        let base = due.map(to_secs).unwrap_or(done);
+        let after = base.max(done);
+        // the first day past `after` at the time of day of `base`
+        let next_day = base + ((after - base) / DAY + 1) * DAY;
+        let next = match *self {
+            Recurrence::Daily => next_day,
+            Recurrence::Weekly(mask) => (0..7)
+                .map(|i| next_day + i * DAY)
+                .find(|t| mask & 1 << weekday(*t) != 0)
+                .unwrap_or(next_day),
+            Recurrence::Monthly => {
+                let (year, month, day) = civil_from_days(base.div_euclid(DAY));
+                let time = base.rem_euclid(DAY);
+                (1..)
+                    .map(|k| {
+                        let m = year * 12 + month as i64 - 1 + k;
+                        let (y, m) = (m.div_euclid(12), m.rem_euclid(12) as u32 + 1);
+                        let d = day.min(days_in_month(y, m));
+                        days_from_civil(y, m, d) * DAY + time
+                    })
+                    .find(|t| *t > after)
+                    .unwrap()
+            }
+            Recurrence::AfterCompletion(days) => done + days as i64 * DAY,
+        };
+        UNIX_EPOCH + Duration::from_secs(next.max(0) as u64)
+    }
+}
+
+fn to_secs(at: SystemTime) -> i64 {
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+/// 0 for Monday, the epoch was a Thursday.
+fn weekday(secs: i64) -> u32 {
+    (secs.div_euclid(DAY) + 3).rem_euclid(7) as u32
+}
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since the epoch of a date, see
+/// <http://howardhinnant.github.io/date_algorithms.html>.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// The date of a day since the epoch.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `hour` o'clock UTC on a date.
+    fn at(year: i64, month: u32, day: u32, hour: i64) -> SystemTime {
+        let secs = days_from_civil(year, month, day) * DAY + hour * 60 * 60;
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    }
+
+    fn date(t: SystemTime) -> (i64, u32, u32) {
+        civil_from_days(to_secs(t).div_euclid(DAY))
+    }
+
+    #[test]
+    fn dates_round_trip_over_leap_days() {
+        for (year, month, day) in [(2000, 2, 29), (2021, 12, 31), (2024, 2, 29), (2024, 3, 1)] {
+            let days = days_from_civil(year, month, day);
+            assert_eq!(civil_from_days(days), (year, month, day));
+        }
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert!(is_leap(2000) && is_leap(2024));
+        assert!(!is_leap(1900) && !is_leap(2023));
+    }
+
+    #[test]
+    fn monthly_clamps_to_the_end_of_shorter_months() {
+        let due = at(2021, 1, 31, 9);
+        let next = Recurrence::Monthly.next_due(Some(due), due);
+        assert_eq!(next, at(2021, 2, 28, 9));
+        // and goes back to the 31st after
+        let next = Recurrence::Monthly.next_due(Some(due), next);
+        assert_eq!(next, at(2021, 3, 31, 9));
+        let next = Recurrence::Monthly.next_due(Some(due), at(2021, 4, 1, 0));
+        assert_eq!(next, at(2021, 4, 30, 9));
+    }
+
+    #[test]
+    fn monthly_lands_on_leap_days() {
+        let due = at(2024, 1, 31, 9);
+        assert_eq!(
+            Recurrence::Monthly.next_due(Some(due), due),
+            at(2024, 2, 29, 9)
+        );
+        let due = at(2000, 1, 30, 9);
+        assert_eq!(
+            date(Recurrence::Monthly.next_due(Some(due), due)),
+            (2000, 2, 29)
+        );
+    }
+
+    #[test]
+    fn monthly_from_a_leap_day_falls_on_feb_28_in_later_years() {
+        let due = at(2024, 2, 29, 9);
+        let late =
+            |year, month, day| Recurrence::Monthly.next_due(Some(due), at(year, month, day, 12));
+        assert_eq!(date(late(2025, 2, 1)), (2025, 2, 28));
+        assert_eq!(date(late(2028, 2, 1)), (2028, 2, 29));
+        assert_eq!(date(late(2025, 3, 1)), (2025, 3, 29));
+    }
+
+    #[test]
+    fn weekly_picks_the_next_marked_weekday() {
+        // a Monday
+        let monday = at(2021, 9, 20, 8);
+        assert_eq!(weekday(to_secs(monday)), 0);
+        let mon_thu = Recurrence::Weekly(0b1001);
+        assert_eq!(mon_thu.next_due(Some(monday), monday), at(2021, 9, 23, 8));
+        let thursday = at(2021, 9, 23, 8);
+        assert_eq!(
+            mon_thu.next_due(Some(thursday), thursday),
+            at(2021, 9, 27, 8)
+        );
+        // completed late, the due date's time of day is kept
+        let sundays = Recurrence::Weekly(1 << 6);
+        assert_eq!(
+            sundays.next_due(Some(monday), at(2021, 10, 3, 20)),
+            at(2021, 10, 10, 8)
+        );
+        // over the end of a leap February
+        let wed = at(2024, 2, 28, 8);
+        assert_eq!(
+            Recurrence::Weekly(1 << 3).next_due(Some(wed), wed),
+            at(2024, 2, 29, 8)
+        );
+    }
+
+    #[test]
+    fn tasks_without_a_due_date_count_from_their_completion() {
+        let done = at(2024, 2, 28, 15);
+        assert_eq!(Recurrence::Daily.next_due(None, done), at(2024, 2, 29, 15));
+        assert_eq!(
+            Recurrence::AfterCompletion(2).next_due(None, done),
+            at(2024, 3, 1, 15)
+        );
+    }
+}